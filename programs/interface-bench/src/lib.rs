@@ -0,0 +1,156 @@
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::log::sol_log_compute_units;
+use std::collections::HashMap;
+use token_interface::{IAccountMeta, PreflightPayload};
+use token_program::{
+    find_entry, insert_entry, ledger_size, split_ledger_mut, LedgerAccount, LedgerAccounts,
+    LedgerEntry,
+};
+
+declare_id!("9ydUULJpjYuu7nDADBYYpvX9ayb2vGz1vBDchRszNdg7");
+
+// This program only exists to compare the compute units spent by
+// different implementations of `token-interface` internals.
+// Each instruction logs compute units right before and after the code being measured.
+#[program]
+pub mod interface_bench {
+    use super::*;
+
+    // `requested` holds indices into the remaining accounts, in the order
+    // a preflight payload would request them
+    pub fn match_legacy(ctx: Context<Bench>, requested: Vec<u8>) -> Result<()> {
+        let payload = payload_for(ctx.remaining_accounts, &requested);
+
+        sol_log_compute_units();
+        let found = legacy_match_accounts(&payload, ctx.remaining_accounts)?;
+        sol_log_compute_units();
+
+        msg!("matched {}", found.len());
+        Ok(())
+    }
+
+    pub fn match_accounts(ctx: Context<Bench>, requested: Vec<u8>) -> Result<()> {
+        let payload = payload_for(ctx.remaining_accounts, &requested);
+
+        sol_log_compute_units();
+        let found = payload.match_accounts(ctx.remaining_accounts)?;
+        sol_log_compute_units();
+
+        msg!("matched {}", found.len());
+        Ok(())
+    }
+
+    // Both ledger benches debit one of `holders` and credit a new holder,
+    // the way `token_program::transfer` does with each ledger layout
+    pub fn ledger_legacy(_ctx: Context<Bench>, holders: u32) -> Result<()> {
+        let accounts: LedgerAccounts = (0..holders)
+            .map(|i| {
+                (
+                    holder(2 * i),
+                    LedgerAccount {
+                        amount: 1,
+                        is_frozen: false,
+                    },
+                )
+            })
+            .collect();
+        let data = accounts.try_to_vec()?;
+        let (owner, to) = (holder(holders / 2 * 2), holder(holders | 1));
+
+        sol_log_compute_units();
+        let mut accounts = LedgerAccounts::try_from_slice(&data)?;
+        accounts.get_mut(&owner).unwrap().amount -= 1;
+        accounts.insert(
+            to,
+            LedgerAccount {
+                amount: 1,
+                is_frozen: false,
+            },
+        );
+        let data = accounts.try_to_vec()?;
+        sol_log_compute_units();
+
+        msg!("ledger holds {} bytes", data.len());
+        Ok(())
+    }
+
+    pub fn ledger_sorted(_ctx: Context<Bench>, holders: u32) -> Result<()> {
+        let mut data = vec![0; ledger_size(holders as usize + 1)];
+        for i in 0..holders {
+            let entry = LedgerEntry {
+                owner: holder(2 * i),
+                amount: 1,
+                flags: 0,
+            };
+            insert_entry(&mut data, i as usize, entry)?;
+        }
+        let (owner, to) = (holder(holders / 2 * 2), holder(holders | 1));
+
+        sol_log_compute_units();
+        let (_, entries) = split_ledger_mut(&mut data)?;
+        let source = find_entry(entries, &owner).unwrap();
+        entries[source].amount -= 1;
+        let index = find_entry(entries, &to).unwrap_err();
+        insert_entry(
+            &mut data,
+            index,
+            LedgerEntry {
+                owner: to,
+                amount: 1,
+                flags: 0,
+            },
+        )?;
+        sol_log_compute_units();
+
+        msg!("ledger holds {} bytes", data.len());
+        Ok(())
+    }
+}
+
+// Distinct keys that sort in the same order as `i`
+fn holder(i: u32) -> Pubkey {
+    let mut key = [0; 32];
+    key[..4].copy_from_slice(&i.to_be_bytes());
+    Pubkey::new_from_array(key)
+}
+
+fn payload_for(accounts: &[AccountInfo], requested: &[u8]) -> PreflightPayload {
+    PreflightPayload::new(
+        requested
+            .iter()
+            .map(|i| IAccountMeta {
+                pubkey: accounts[*i as usize].key(),
+                signer: false,
+                writable: false,
+            })
+            .collect(),
+    )
+}
+
+// The `HashMap` based matcher that `PreflightPayload::match_accounts` used to be
+fn legacy_match_accounts<'info>(
+    payload: &PreflightPayload,
+    accounts: &[AccountInfo<'info>],
+) -> Result<Vec<AccountInfo<'info>>> {
+    let mut map = HashMap::<Pubkey, AccountInfo>::new();
+
+    for acc in accounts {
+        map.insert(acc.key(), acc.clone());
+    }
+
+    let mut found_accounts = Vec::<AccountInfo>::new();
+    for acc in payload.accounts.iter() {
+        let found_acc = map.get(&acc.pubkey);
+        if found_acc.is_none() {
+            msg!(&format!("account not found: {:?}", acc.pubkey));
+            return Err(ProgramError::NotEnoughAccountKeys.into());
+        }
+        found_accounts.push(found_acc.unwrap().clone());
+    }
+    msg!("found accounts: {:?}", found_accounts.len());
+
+    Ok(found_accounts)
+}
+
+#[derive(Accounts)]
+pub struct Bench {}
@@ -1,4 +1,7 @@
 use anchor_lang::prelude::*;
+use anchor_lang::solana_program::system_instruction::MAX_PERMITTED_DATA_LENGTH;
+use anchor_lang::system_program;
+use anchor_lang::Discriminator;
 
 declare_id!("32d7pdBNmDmXAXcHkivteqLjaNVovWQ1JMn85LdyyAux");
 use std::collections::HashMap;
@@ -11,117 +14,1237 @@ pub enum TokenError {
     MathOverflow,
     #[msg("Ledger capacity is full")]
     LedgerCapacityFull,
+    #[msg("Too many routers")]
+    TooManyRouters,
+    #[msg("Ledger size is out of range")]
+    InvalidLedgerSize,
+    #[msg("Owner has no balance in the ledger")]
+    UnknownHolder,
+    #[msg("Account still holds a balance")]
+    NonZeroBalance,
+    #[msg("A requested account was not passed")]
+    MissingAccount,
+    #[msg("Signer is not authorized")]
+    Unauthorized,
+    #[msg("Mint metadata is too long")]
+    InvalidMetadata,
+    #[msg("Ledger does not belong to the mint")]
+    LedgerMismatch,
+    #[msg("Account is frozen")]
+    AccountFrozen,
+    #[msg("Failed to serialize account data")]
+    SerializationFailed,
+    #[msg("Mint rejects zero-amount transfers")]
+    ZeroAmount,
+    #[msg("Transfer fee is above 100%")]
+    InvalidFee,
+    #[msg("Owner or recipient is not attested by the compliance program")]
+    NotAttested,
+    #[msg("Attestation has expired")]
+    AttestationExpired,
 }
 
 pub const LEDGER_PREFIX: &str = "ledger";
+pub const ROUTING_PREFIX: &str = "routing";
+pub const BALANCE_PREFIX: &str = "balance";
+pub const MINT_PREFIX: &str = "mint";
+pub const DELEGATE_PREFIX: &str = "delegate";
+pub const FEES_PREFIX: &str = "fees";
+// Seed of attestations, under the compliance program
+pub const ATTESTATION_PREFIX: &str = "attestation";
+pub const MAX_ROUTERS: usize = 8;
+// Holders the ledger has room for when it is created
 pub const DEFAULT_CAPACITY: usize = 5;
+// discriminator + `SortedLedger`
+pub const LEDGER_HEADER_SIZE: usize = 8 + std::mem::size_of::<SortedLedger>();
+pub const LEDGER_ENTRY_SIZE: usize = std::mem::size_of::<LedgerEntry>();
+// `LedgerEntry::flags` bit set on frozen balances
+pub const ENTRY_FROZEN: u8 = 1;
+// `SortedLedger::flags` bit set on mints that reject zero-amount transfers
+pub const LEDGER_REJECT_ZERO_AMOUNT: u8 = 1;
+// Ledgers can grow up to the largest account the runtime allows, unless configured lower
+pub const DEFAULT_MAX_LEDGER_SIZE: u32 = MAX_PERMITTED_DATA_LENGTH as u32;
+// discriminator + total_supply + mint_authority + freeze_authority
+pub const BALANCE_MINT_SIZE: usize = 8 + 8 + 32 + 32;
+// discriminator + amount + is_frozen
+pub const BALANCE_SIZE: usize = 8 + 8 + 1;
+// discriminator + delegate + allowance
+pub const DELEGATION_SIZE: usize = 8 + 32 + 8;
+// discriminator + withheld
+pub const FEE_COLLECTOR_SIZE: usize = 8 + 8;
+// Transfer fees are in basis points of the amount, up to all of it
+pub const MAX_FEE_BPS: u16 = 10_000;
+pub const MAX_NAME_LEN: usize = 32;
+pub const MAX_SYMBOL_LEN: usize = 10;
+// discriminator + id + name + symbol + decimals
+// Mint states end with a copy of the ledger's compliance program, past the
+// longest `MintState`, so preflight can find it without the ledger
+pub const MINT_COMPLIANCE_OFFSET: usize = 8 + 32 + (4 + MAX_NAME_LEN) + (4 + MAX_SYMBOL_LEN) + 1;
+pub const MINT_STATE_SIZE: usize = MINT_COMPLIANCE_OFFSET + 32;
 
 #[program]
 pub mod token_program {
-    use anchor_lang::solana_program::program::{get_return_data, invoke, set_return_data};
-    use anchor_lang::solana_program::{hash, instruction::Instruction};
-    use token_interface::{IAccountMeta, PreflightPayload};
+    use anchor_lang::solana_program::program::set_return_data;
+    use anchor_lang::solana_program::sysvar;
+    use token_interface::{
+        find_preflight_payer, BalancePreview, CallerAttestation, FundingRequirement, IAccountMeta,
+        InterfaceTransfer, PayerRole, PreflightPayload, TransferRoute,
+    };
 
     use super::*;
 
-    /// Just used to initialize this program's singleton Ledger account
-    pub fn initialize_mint(ctx: Context<InitializeMint>, supply: u64) -> Result<()> {
-        let ledger = &mut ctx.accounts.ledger;
-        let authority = &ctx.accounts.authority.key();
-        let mut accounts: LedgerAccounts = HashMap::with_capacity(DEFAULT_CAPACITY);
-        ledger.mint_authority = *authority;
-        ledger.freeze_authority = *authority;
-        ledger.total_supply = supply;
-
-        // Hack to avoid creating `mint_to` instruction
-        // just mint all supply to the authority
-        accounts.insert(
-            *authority,
-            LedgerAccount {
+    // Creates the mint identified by `id`: its `MintState`, which callers pass
+    // as the interface `mint`, and its ledger
+    pub fn initialize_mint(
+        ctx: Context<InitializeMint>,
+        id: Pubkey,
+        metadata: MintMetadata,
+        supply: u64,
+    ) -> Result<()> {
+        // The program id stands for the ledger of single-mint deployments
+        if id == crate::id() {
+            return Err(TokenError::LedgerMismatch.into());
+        }
+        if metadata.name.len() > MAX_NAME_LEN || metadata.symbol.len() > MAX_SYMBOL_LEN {
+            return Err(TokenError::InvalidMetadata.into());
+        }
+        ctx.accounts.mint.id = id;
+        ctx.accounts.mint.metadata = metadata;
+
+        let authority = ctx.accounts.authority.key();
+        {
+            let mut ledger = ctx.accounts.ledger.load_init()?;
+            ledger.mint_authority = authority;
+            ledger.freeze_authority = authority;
+            ledger.total_supply = supply;
+            ledger.max_size = DEFAULT_MAX_LEDGER_SIZE;
+        }
+
+        // The initial supply goes to the authority, `mint_to` issues more later
+        let ledger_info = ctx.accounts.ledger.to_account_info();
+        let mut data = ledger_info.try_borrow_mut_data()?;
+        insert_entry(
+            &mut data,
+            0,
+            LedgerEntry {
+                owner: authority,
                 amount: supply,
-                is_frozen: false,
+                flags: 0,
             },
-        );
-        ledger.opaque_accounts = accounts.try_to_vec().unwrap();
+        )
+    }
+
+    // Converts a ledger from the Borsh `LegacyLedger` layout to `SortedLedger`.
+    // The authority gets back the rent it no longer needs, or pays for the
+    // larger header of ledgers with few holders.
+    pub fn migrate_ledger(ctx: Context<MigrateLedger>) -> Result<()> {
+        let ledger_info = ctx.accounts.ledger.to_account_info();
+        let legacy = LegacyLedger::try_from_account(&ledger_info)?;
+        if legacy.mint_authority != ctx.accounts.authority.key() {
+            return Err(TokenError::Unauthorized.into());
+        }
+
+        let mut entries: Vec<LedgerEntry> = get_ledger_accounts(&legacy.opaque_accounts)?
+            .into_iter()
+            .map(|(owner, account)| LedgerEntry {
+                owner,
+                amount: account.amount,
+                flags: if account.is_frozen { ENTRY_FROZEN } else { 0 },
+            })
+            .collect();
+        entries.sort_unstable_by_key(|e| e.owner);
+        let header = SortedLedger {
+            total_supply: legacy.total_supply,
+            mint_authority: legacy.mint_authority,
+            freeze_authority: legacy.freeze_authority,
+            // Legacy ledgers never grew, so they keep their size as the cap
+            max_size: (ledger_info.data_len().max(ledger_size(entries.len())) as u32)
+                .min(DEFAULT_MAX_LEDGER_SIZE),
+            len: entries.len() as u32,
+            flags: 0,
+            fee_bps: 0,
+            max_fee: 0,
+            fee_collector: Pubkey::default(),
+            compliance_program: Pubkey::default(),
+        };
+
+        let new_len = ledger_size(entries.len());
+        let authority = ctx.accounts.authority.to_account_info();
+        if new_len > ledger_info.data_len() {
+            let system_program = ctx.accounts.system_program.to_account_info();
+            grow_ledger(&ledger_info, &authority, &[system_program], new_len)?;
+        } else {
+            shrink_ledger(&ledger_info, &authority, new_len)?;
+        }
+        let mut data = ledger_info.try_borrow_mut_data()?;
+        data[..8].copy_from_slice(&SortedLedger::discriminator());
+        *bytemuck::from_bytes_mut(&mut data[8..LEDGER_HEADER_SIZE]) = header;
+        split_ledger_mut(&mut data)?.1.copy_from_slice(&entries);
+        Ok(())
+    }
+
+    // Creates a mint whose balances live in per-owner `Balance` accounts
+    // instead of the ledger, minting all supply to the authority
+    pub fn initialize_balance_mint(ctx: Context<InitializeBalanceMint>, supply: u64) -> Result<()> {
+        let mint = &mut ctx.accounts.mint;
+        let authority = ctx.accounts.authority.key();
+        mint.mint_authority = authority;
+        mint.freeze_authority = authority;
+        mint.total_supply = supply;
+
+        ctx.accounts.balance.amount = supply;
+        ctx.accounts.balance.is_frozen = false;
+        Ok(())
+    }
+
+    // Restricts `transfer` to CPIs from `routers`. An empty list lifts the restriction.
+    pub fn initialize_routing(ctx: Context<InitializeRouting>, routers: Vec<Pubkey>) -> Result<()> {
+        if routers.len() > MAX_ROUTERS {
+            return Err(TokenError::TooManyRouters.into());
+        }
+        ctx.accounts.routing.routers = routers;
+        Ok(())
+    }
+
+    pub fn set_routers(ctx: Context<SetRouters>, routers: Vec<Pubkey>) -> Result<()> {
+        if routers.len() > MAX_ROUTERS {
+            return Err(TokenError::TooManyRouters.into());
+        }
+        ctx.accounts.routing.routers = routers;
+        Ok(())
+    }
+
+    // Caps how far transfers may grow the ledger. Holders past the cap
+    // can only be added once others close their accounts.
+    pub fn set_max_ledger_size(ctx: Context<SetMaxLedgerSize>, max_size: u32) -> Result<()> {
+        let ledger_info = ctx.accounts.ledger.to_account_info();
+        if (max_size as usize) < ledger_info.data_len() || max_size > DEFAULT_MAX_LEDGER_SIZE {
+            return Err(TokenError::InvalidLedgerSize.into());
+        }
+        ctx.accounts.ledger.load_mut()?.max_size = max_size;
+        Ok(())
+    }
+
+    // Makes zero-amount transfers fail with `ZeroAmount` instead of succeeding
+    // without effect
+    pub fn set_reject_zero_amount(ctx: Context<ConfigureLedger>, reject: bool) -> Result<()> {
+        let mut ledger = ctx.accounts.ledger.load_mut()?;
+        if reject {
+            ledger.flags |= LEDGER_REJECT_ZERO_AMOUNT;
+        } else {
+            ledger.flags &= !LEDGER_REJECT_ZERO_AMOUNT;
+        }
+        Ok(())
+    }
+
+    // Withholds `fee_bps` of every transfer, up to `max_fee`, in a `FeeCollector`
+    // at `["fees", ledger]` until the mint authority withdraws it
+    pub fn initialize_transfer_fee(
+        ctx: Context<InitializeTransferFee>,
+        fee_bps: u16,
+        max_fee: u64,
+    ) -> Result<()> {
+        let mut ledger = ctx.accounts.ledger.load_mut()?;
+        ledger.fee_collector = ctx.accounts.fee_collector.key();
+        set_fee(&mut ledger, fee_bps, max_fee)
+    }
+
+    // A fee of 0 bps stops charging fees, leaving withheld fees in the collector
+    pub fn set_transfer_fee(
+        ctx: Context<SetTransferFee>,
+        fee_bps: u16,
+        max_fee: u64,
+    ) -> Result<()> {
+        set_fee(&mut *ctx.accounts.ledger.load_mut()?, fee_bps, max_fee)
+    }
+
+    // Moves all withheld fees to `to`'s balance, adding an entry at the
+    // mint authority's expense for new holders
+    pub fn withdraw_fees(ctx: Context<WithdrawFees>) -> Result<()> {
+        let withheld = ctx.accounts.fee_collector.withheld;
+        ctx.accounts.fee_collector.withheld = 0;
+        credit_entry(
+            &ctx.accounts.ledger.to_account_info(),
+            &ctx.accounts.mint_authority.to_account_info(),
+            &[ctx.accounts.system_program.to_account_info()],
+            ctx.accounts.to.key,
+            withheld,
+        )
+    }
+
+    // Requires both sides of every transfer to hold an unexpired attestation
+    // from `compliance_program`. `None` lifts the requirement.
+    pub fn set_compliance_program(
+        ctx: Context<SetComplianceProgram>,
+        compliance_program: Option<Pubkey>,
+    ) -> Result<()> {
+        let compliance_program = compliance_program.unwrap_or_default();
+        ctx.accounts.ledger.load_mut()?.compliance_program = compliance_program;
+        ctx.accounts.mint.to_account_info().try_borrow_mut_data()?
+            [MINT_COMPLIANCE_OFFSET..MINT_STATE_SIZE]
+            .copy_from_slice(compliance_program.as_ref());
+        Ok(())
+    }
+
+    // Removes the owner's empty balance from the ledger, shrinking it
+    // and refunding the freed rent to `destination`
+    pub fn close_account(ctx: Context<CloseAccount>) -> Result<()> {
+        let owner = ctx.accounts.owner.key();
+        let ledger_info = ctx.accounts.ledger.to_account_info();
+        let len = {
+            let mut data = ledger_info.try_borrow_mut_data()?;
+            let (header, entries) = split_ledger_mut(&mut data)?;
+            let index = find_entry(entries, &owner).map_err(|_| TokenError::UnknownHolder)?;
+            if entries[index].amount > 0 {
+                return Err(TokenError::NonZeroBalance.into());
+            }
+            // Closing would thaw the balance once the owner receives tokens again
+            if entries[index].flags & ENTRY_FROZEN != 0 {
+                return Err(TokenError::AccountFrozen.into());
+            }
+            entries.copy_within(index + 1.., index);
+            header.len -= 1;
+            header.len as usize
+        };
+        shrink_ledger(&ledger_info, &ctx.accounts.destination, ledger_size(len))
+    }
+
+    // Issues `amount` new tokens to `to`, adding an entry for new holders
+    pub fn mint_to(ctx: Context<MintTo>, amount: u64) -> Result<()> {
+        {
+            let mut header = ctx.accounts.ledger.load_mut()?;
+            header.total_supply = header
+                .total_supply
+                .checked_add(amount)
+                .ok_or(TokenError::MathOverflow)?;
+        }
+        credit_entry(
+            &ctx.accounts.ledger.to_account_info(),
+            &ctx.accounts.mint_authority.to_account_info(),
+            &[ctx.accounts.system_program.to_account_info()],
+            ctx.accounts.to.key,
+            amount,
+        )
+    }
+
+    // Destroys `amount` of the owner's tokens. Delegates burn from their allowance.
+    pub fn burn(ctx: Context<Burn>, amount: u64) -> Result<()> {
+        if ctx.accounts.authority.key != ctx.accounts.owner.key {
+            spend_allowance(ctx.accounts.authority.key, &ctx.accounts.delegation, amount)?;
+        }
+
+        debit_entry(
+            &ctx.accounts.ledger.to_account_info(),
+            ctx.accounts.owner.key,
+            amount,
+        )?;
+        let mut header = ctx.accounts.ledger.load_mut()?;
+        header.total_supply = header
+            .total_supply
+            .checked_sub(amount)
+            .ok_or(TokenError::MathOverflow)?;
+        Ok(())
+    }
+
+    pub fn set_mint_authority(
+        ctx: Context<SetMintAuthority>,
+        mint_authority: Pubkey,
+    ) -> Result<()> {
+        ctx.accounts.ledger.load_mut()?.mint_authority = mint_authority;
+        Ok(())
+    }
+
+    // Lets `delegate` burn up to `allowance` of the owner's tokens
+    pub fn approve(ctx: Context<Approve>, allowance: u64) -> Result<()> {
+        ctx.accounts.delegation.delegate = ctx.accounts.delegate.key();
+        ctx.accounts.delegation.allowance = allowance;
+        Ok(())
+    }
+
+    // Closes the owner's delegation, refunding its rent
+    pub fn revoke(_ctx: Context<Revoke>) -> Result<()> {
+        Ok(())
+    }
+
+    // `mint_to` for balance mints, creating the recipient's `Balance` on first use
+    pub fn mint_to_balance(ctx: Context<MintToBalance>, amount: u64) -> Result<()> {
+        let mint = &mut ctx.accounts.mint;
+        mint.total_supply = mint
+            .total_supply
+            .checked_add(amount)
+            .ok_or(TokenError::MathOverflow)?;
+        credit_balance(
+            &ctx.accounts.balance,
+            &ctx.accounts.mint_authority,
+            &[ctx.accounts.system_program.to_account_info()],
+            &mint.key(),
+            ctx.accounts.to.key,
+            *ctx.bumps.get("balance").unwrap(),
+            amount,
+        )
+    }
+
+    // `burn` for balance mints
+    pub fn burn_balance(ctx: Context<BurnBalance>, amount: u64) -> Result<()> {
+        if ctx.accounts.authority.key != ctx.accounts.owner.key {
+            spend_allowance(ctx.accounts.authority.key, &ctx.accounts.delegation, amount)?;
+        }
+
+        let mut balance = spendable_balance(&ctx.accounts.balance, amount)?;
+        balance.amount -= amount;
+        balance.exit(&crate::id())?;
+        let mint = &mut ctx.accounts.mint;
+        mint.total_supply = mint
+            .total_supply
+            .checked_sub(amount)
+            .ok_or(TokenError::MathOverflow)?;
+        Ok(())
+    }
+
+    // `approve` for balance mints, with the delegation at `["delegate", mint, owner]`
+    pub fn approve_balance(ctx: Context<ApproveBalance>, allowance: u64) -> Result<()> {
+        ctx.accounts.delegation.delegate = ctx.accounts.delegate.key();
+        ctx.accounts.delegation.allowance = allowance;
+        Ok(())
+    }
+
+    pub fn revoke_balance(_ctx: Context<RevokeBalance>) -> Result<()> {
+        Ok(())
+    }
+
+    // Stops the owner's balance from being sent or received
+    pub fn freeze_account(ctx: Context<FreezeAccount>) -> Result<()> {
+        set_frozen(&ctx, true)
+    }
+
+    pub fn thaw_account(ctx: Context<FreezeAccount>) -> Result<()> {
+        set_frozen(&ctx, false)
+    }
+
+    pub fn set_freeze_authority(
+        ctx: Context<SetFreezeAuthority>,
+        freeze_authority: Pubkey,
+    ) -> Result<()> {
+        ctx.accounts.ledger.load_mut()?.freeze_authority = freeze_authority;
+        Ok(())
+    }
+
+    // `freeze_account` for balance mints, which keep the flag in the owner's `Balance`
+    pub fn freeze_balance(ctx: Context<FreezeBalance>) -> Result<()> {
+        set_balance_frozen(&ctx, true)
+    }
+
+    pub fn thaw_balance(ctx: Context<FreezeBalance>) -> Result<()> {
+        set_balance_frozen(&ctx, false)
+    }
+
+    pub fn set_balance_freeze_authority(
+        ctx: Context<SetBalanceFreezeAuthority>,
+        freeze_authority: Pubkey,
+    ) -> Result<()> {
+        ctx.accounts.mint.freeze_authority = freeze_authority;
         Ok(())
     }
 
     pub fn preflight_transfer(ctx: Context<ITransfer>, amount: u64) -> Result<()> {
-        let ledger = Pubkey::find_program_address(&[LEDGER_PREFIX.as_bytes()], &crate::id()).0;
+        // Ledger mints lock their ledger, balance mints only pass the mint along
+        let (ledger, is_balance_mint) = match mint_balances(&ctx.accounts.mint)? {
+            MintBalances::Ledger(ledger) => (ledger, false),
+            MintBalances::Accounts => (ctx.accounts.mint.key(), true),
+        };
 
-        set_return_data(
-            &PreflightPayload {
-                accounts: vec![IAccountMeta {
-                    pubkey: ledger,
+        // Callers may pass the ledger or balance accounts as hints, in which case
+        // we only ask for funding when the recipient's balance needs new space,
+        // and can preview the resulting balances
+        let hint = |key: &Pubkey| ctx.remaining_accounts.iter().find(|acc| acc.key == key);
+        let mut balance_accounts = vec![];
+        let mut source_balance = None;
+        let mut fee_collector = None;
+        let mut fee = 0;
+        let mut attestations = vec![];
+        let funding;
+        if is_balance_mint {
+            let source = balance_address(ctx.accounts.mint.key, ctx.accounts.owner.key).0;
+            let destination = balance_address(ctx.accounts.mint.key, ctx.accounts.to.key).0;
+            let destination_info = hint(&destination).filter(|acc| !acc.data_is_empty());
+            // Transfers into a frozen balance fail, so no preview for them either
+            let destination_frozen = match destination_info {
+                Some(info) => Account::<Balance>::try_from(info)?.is_frozen,
+                None => false,
+            };
+            if let Some(source_info) = hint(&source).filter(|acc| !acc.data_is_empty()) {
+                let source = Account::<Balance>::try_from(source_info)?;
+                if !source.is_frozen && !destination_frozen {
+                    source_balance = Some(source.amount);
+                }
+            }
+            let destination_exists = destination_info.is_some();
+            funding = if destination_exists || amount == 0 || source == destination {
+                None
+            } else {
+                Some(Rent::get()?.minimum_balance(BALANCE_SIZE))
+            };
+            balance_accounts = vec![
+                IAccountMeta {
+                    pubkey: source,
                     signer: false,
                     writable: true,
-                }],
+                },
+                IAccountMeta {
+                    pubkey: destination,
+                    signer: false,
+                    writable: true,
+                },
+            ];
+        } else {
+            // Gated mints keep their compliance program in the mint state,
+            // so attestations don't depend on the ledger hint
+            let compliance_program = mint_compliance_program(&ctx.accounts.mint)?;
+            if compliance_program != Pubkey::default() {
+                attestations
+                    .push(attestation_address(&compliance_program, ctx.accounts.owner.key).0);
+                if ctx.accounts.owner.key != ctx.accounts.to.key {
+                    attestations
+                        .push(attestation_address(&compliance_program, ctx.accounts.to.key).0);
+                }
             }
-            .try_to_vec()
-            .unwrap(),
+
+            // Without the ledger hint we can't tell whether the mint charges
+            // fees, so we always ask for the fee collector
+            let mut needs_growth = true;
+            fee_collector = Some(fee_collector_address(&ledger).0);
+            if let Some(ledger_info) = hint(&ledger) {
+                AccountLoader::<SortedLedger>::try_from(ledger_info)?;
+                let data = ledger_info.try_borrow_data()?;
+                let (header, entries) = split_ledger(&data)?;
+                needs_growth = find_entry(entries, ctx.accounts.to.key).is_err()
+                    && ledger_info.data_len() < ledger_size(header.len as usize + 1);
+                let rejected = amount == 0 && header.flags & LEDGER_REJECT_ZERO_AMOUNT != 0;
+                fee_collector = (header.fee_bps > 0).then_some(header.fee_collector);
+                if ctx.accounts.owner.key != ctx.accounts.to.key {
+                    fee = header.transfer_fee(amount);
+                }
+                let is_frozen = |owner: &Pubkey| {
+                    find_entry(entries, owner)
+                        .map(|index| entries[index].flags & ENTRY_FROZEN != 0)
+                        .unwrap_or(false)
+                };
+                if !rejected
+                    && !is_frozen(ctx.accounts.owner.key)
+                    && !is_frozen(ctx.accounts.to.key)
+                {
+                    // Owners without an entry can't transfer, not even zero
+                    source_balance = find_entry(entries, ctx.accounts.owner.key)
+                        .ok()
+                        .map(|index| entries[index].amount);
+                }
+            }
+            let rent = Rent::get()?;
+            // Zero amounts and transfers to oneself never add holders
+            let adds_holder = amount > 0 && ctx.accounts.owner.key != ctx.accounts.to.key;
+            funding = if needs_growth && adds_holder {
+                Some(rent.minimum_balance(LEDGER_ENTRY_SIZE) - rent.minimum_balance(0))
+            } else {
+                None
+            };
+        }
+
+        // No preview when the transfer is going to fail anyway
+        let preview = source_balance
+            .filter(|balance| *balance >= amount)
+            .map(|_| {
+                BalancePreview::transfer(
+                    ctx.accounts.mint.key(),
+                    ctx.accounts.owner.key(),
+                    ctx.accounts.to.key(),
+                    amount,
+                    fee,
+                )
+            });
+
+        // Likewise, without the routing hint we can't tell whether routing
+        // is enforced, so we always ask for the instructions sysvar
+        let routing = routing_address(&ledger).0;
+        let routing_hint = ctx
+            .remaining_accounts
+            .iter()
+            .find(|acc| acc.key() == routing);
+        let routers = match routing_hint {
+            Some(routing_info) => get_routers(routing_info)?,
+            None => None,
+        };
+
+        let mut payload = PreflightPayload::new(vec![
+            IAccountMeta {
+                pubkey: ledger,
+                signer: false,
+                writable: !is_balance_mint,
+            },
+            IAccountMeta {
+                pubkey: routing,
+                signer: false,
+                writable: false,
+            },
+        ]);
+        if routing_hint.is_none() || routers.is_some() {
+            payload.accounts.push(IAccountMeta {
+                pubkey: sysvar::instructions::ID,
+                signer: false,
+                writable: false,
+            });
+        }
+        if let Some(routers) = routers {
+            payload = payload.with_routers(routers);
+        }
+        payload.accounts.extend(balance_accounts);
+        if let Some(fee_collector) = fee_collector {
+            payload.accounts.push(IAccountMeta {
+                pubkey: fee_collector,
+                signer: false,
+                writable: true,
+            });
+        }
+        payload
+            .accounts
+            .extend(attestations.into_iter().map(|pubkey| IAccountMeta {
+                pubkey,
+                signer: false,
+                writable: false,
+            }));
+        if let Some(lamports) = funding {
+            // New space is billed to the caller's payer when they passed one
+            let (payer, role) = match find_preflight_payer(ctx.remaining_accounts) {
+                Some(payer) => (payer.key(), PayerRole::Payer),
+                None => (ctx.accounts.authority.key(), PayerRole::Authority),
+            };
+            payload.accounts.push(IAccountMeta {
+                pubkey: payer,
+                signer: false,
+                writable: true,
+            });
+            payload.accounts.push(IAccountMeta {
+                pubkey: system_program::ID,
+                signer: false,
+                writable: false,
+            });
+            payload = payload.with_funding(FundingRequirement {
+                lamports,
+                payer: role,
+            });
+            if role == PayerRole::Payer {
+                payload = payload.with_signers(vec![payer]);
+            }
+        }
+        if let Some(preview) = preview {
+            payload = payload.with_preview(preview);
+        }
+
+        set_return_data(
+            &payload
+                .for_caller(ctx.remaining_accounts)
+                .try_to_vec()
+                .map_err(|_| TokenError::SerializationFailed)?,
         );
         Ok(())
     }
 
     // Transfer tokens from one account to another
-    // but only update their stored balance in the ledger account
-    pub fn transfer(ctx: Context<Transfer>, amount: u64) -> Result<()> {
-        assert_eq!(ctx.accounts.authority.key(), ctx.accounts.owner.key());
-        let ledger = &mut ctx.accounts.ledger;
-
-        let mut accounts = get_ledger_accounts(&ledger.opaque_accounts)?;
-        update_balance(&mut accounts, ctx.accounts.owner.key, amount, false)?;
+    // but only update their stored balance in the ledger account,
+    // or in the owners' balance accounts for balance mints.
+    // When the recipient needs new space, it is paid for by the payer
+    // requested by preflight.
+    pub fn transfer<'info>(
+        ctx: Context<'_, '_, '_, 'info, Transfer<'info>>,
+        amount: u64,
+    ) -> Result<()> {
+        if ctx.accounts.authority.key != ctx.accounts.owner.key {
+            return Err(TokenError::Unauthorized.into());
+        }
+        if let Some(routers) = get_routers(&ctx.accounts.routing)? {
+            CallerAttestation::find(ctx.remaining_accounts)?.require_router(&routers)?;
+        }
 
-        if accounts.get(&ctx.accounts.to.key()).is_none() {
-            if accounts.len() >= DEFAULT_CAPACITY {
-                return Err(TokenError::LedgerCapacityFull.into());
-            } else {
-                accounts.insert(
-                    ctx.accounts.to.key(),
-                    LedgerAccount {
-                        amount: 0,
-                        is_frozen: false,
-                    },
-                );
+        // Preflight requested either the caller's payer or the authority
+        // as the only writable signer among the additional accounts
+        let authority = ctx.accounts.authority.to_account_info();
+        let payer = ctx
+            .remaining_accounts
+            .iter()
+            .find(|acc| acc.is_signer && acc.is_writable)
+            .unwrap_or(&authority);
+        match mint_balances(&ctx.accounts.mint)? {
+            MintBalances::Ledger(ledger) => {
+                if ctx.accounts.ledger.key() != ledger {
+                    return Err(TokenError::LedgerMismatch.into());
+                }
+                transfer_in_ledger(&ctx.accounts.ledger, payer, &ctx, amount)?;
+            }
+            MintBalances::Accounts => {
+                if ctx.accounts.ledger.key != ctx.accounts.mint.key {
+                    return Err(TokenError::LedgerMismatch.into());
+                }
+                transfer_balances(payer, &ctx, amount)?;
             }
         }
 
-        update_balance(&mut accounts, ctx.accounts.to.key, amount, true)?;
-        ledger.opaque_accounts = accounts.try_to_vec().unwrap();
-
+        emit!(InterfaceTransfer {
+            program: crate::id(),
+            mint: ctx.accounts.mint.key(),
+            owner: ctx.accounts.owner.key(),
+            to: ctx.accounts.to.key(),
+            authority: ctx.accounts.authority.key(),
+            amount,
+            route: TransferRoute::Direct,
+        });
         Ok(())
     }
 }
 
-type LedgerAccounts = HashMap<Pubkey, LedgerAccount>;
-fn get_ledger_accounts(data: &[u8]) -> Result<LedgerAccounts> {
-    Ok(LedgerAccounts::try_from_slice(&data)?)
+// Sets or clears `ENTRY_FROZEN` on the owner's entry
+fn set_frozen(ctx: &Context<FreezeAccount>, frozen: bool) -> Result<()> {
+    let ledger_info = ctx.accounts.ledger.to_account_info();
+    let mut data = ledger_info.try_borrow_mut_data()?;
+    let (_, entries) = split_ledger_mut(&mut data)?;
+    let index =
+        find_entry(entries, ctx.accounts.owner.key).map_err(|_| TokenError::UnknownHolder)?;
+    if frozen {
+        entries[index].flags |= ENTRY_FROZEN;
+    } else {
+        entries[index].flags &= !ENTRY_FROZEN;
+    }
+    Ok(())
+}
+
+// Sets or clears `is_frozen` on the owner's `Balance`
+fn set_balance_frozen(ctx: &Context<FreezeBalance>, frozen: bool) -> Result<()> {
+    let balance_info = &ctx.accounts.balance;
+    if balance_info.data_is_empty() {
+        return Err(TokenError::UnknownHolder.into());
+    }
+    let mut balance = Account::<Balance>::try_from(balance_info)?;
+    balance.is_frozen = frozen;
+    balance.exit(&crate::id())
 }
 
-fn update_balance(
-    accounts: &mut LedgerAccounts,
+// Where a mint keeps its balances
+enum MintBalances {
+    // In the ledger at this address
+    Ledger(Pubkey),
+    // In per-owner `Balance` accounts
+    Accounts,
+}
+
+// The program id is the mint of single-mint deployments. Any other mint
+// is either a `BalanceMint` or a `MintState` naming its ledger.
+fn mint_balances(mint: &AccountInfo) -> Result<MintBalances> {
+    if *mint.key == crate::id() {
+        return Ok(MintBalances::Ledger(ledger_address(mint.key).0));
+    }
+    let is_balance_mint = *mint.owner == crate::id()
+        && mint
+            .try_borrow_data()?
+            .starts_with(&BalanceMint::discriminator());
+    if is_balance_mint {
+        return Ok(MintBalances::Accounts);
+    }
+    let state = Account::<MintState>::try_from(mint)?;
+    Ok(MintBalances::Ledger(ledger_address(&state.id).0))
+}
+
+// The compliance program `set_compliance_program` copied into a ledger
+// mint's state. Single-mint deployments have no state and can't be gated.
+fn mint_compliance_program(mint: &AccountInfo) -> Result<Pubkey> {
+    if *mint.key == crate::id() {
+        return Ok(Pubkey::default());
+    }
+    let data = mint.try_borrow_data()?;
+    Ok(data
+        .get(MINT_COMPLIANCE_OFFSET..MINT_STATE_SIZE)
+        .and_then(|bytes| Pubkey::try_from(bytes).ok())
+        .unwrap_or_default())
+}
+
+// The ledger of the mint identified by `id`. Single-mint deployments,
+// identified by the program id, keep theirs at the unseeded address.
+pub fn ledger_address(id: &Pubkey) -> (Pubkey, u8) {
+    if *id == crate::id() {
+        Pubkey::find_program_address(&[LEDGER_PREFIX.as_bytes()], &crate::id())
+    } else {
+        Pubkey::find_program_address(&[LEDGER_PREFIX.as_bytes(), id.as_ref()], &crate::id())
+    }
+}
+
+// The `MintState` of the mint identified by `id`
+pub fn mint_address(id: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[MINT_PREFIX.as_bytes(), id.as_ref()], &crate::id())
+}
+
+// Where the ledger's transfer fees are withheld
+pub fn fee_collector_address(ledger: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[FEES_PREFIX.as_bytes(), ledger.as_ref()], &crate::id())
+}
+
+fn set_fee(ledger: &mut SortedLedger, fee_bps: u16, max_fee: u64) -> Result<()> {
+    if fee_bps > MAX_FEE_BPS {
+        return Err(TokenError::InvalidFee.into());
+    }
+    ledger.fee_bps = fee_bps;
+    ledger.max_fee = max_fee;
+    Ok(())
+}
+
+// Where `compliance_program` attests `owner`
+pub fn attestation_address(compliance_program: &Pubkey, owner: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[ATTESTATION_PREFIX.as_bytes(), owner.as_ref()],
+        compliance_program,
+    )
+}
+
+// Fails unless the compliance program's attestation of `owner` is among
+// `accounts` and expires after `now`
+fn require_attestation(
+    compliance_program: &Pubkey,
+    accounts: &[AccountInfo],
     owner: &Pubkey,
+    now: i64,
+) -> Result<()> {
+    let address = attestation_address(compliance_program, owner).0;
+    let attestation_info = find_account(accounts, &address)?;
+    if attestation_info.owner != compliance_program {
+        return Err(TokenError::NotAttested.into());
+    }
+    let attestation = Attestation::deserialize(&mut &attestation_info.try_borrow_data()?[..])
+        .map_err(|_| TokenError::NotAttested)?;
+    if attestation.owner != *owner {
+        return Err(TokenError::NotAttested.into());
+    }
+    if attestation.expires_at <= now {
+        return Err(TokenError::AttestationExpired.into());
+    }
+    Ok(())
+}
+
+// The routing of a ledger, or of a balance mint when passed the mint
+pub fn routing_address(ledger: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[ROUTING_PREFIX.as_bytes(), ledger.as_ref()], &crate::id())
+}
+
+// Updates both balances in place, inserting an entry for new recipients,
+// and withholds the mint's fee from what the recipient receives.
+// Transfers to oneself only check that the owner could send `amount`.
+fn transfer_in_ledger<'info>(
+    ledger_info: &AccountInfo<'info>,
+    payer: &AccountInfo<'info>,
+    ctx: &Context<'_, '_, '_, 'info, Transfer<'info>>,
     amount: u64,
-    is_add: bool,
 ) -> Result<()> {
-    let mut account = accounts.get(owner).unwrap().clone();
-    if is_add {
-        account.amount = account
-            .amount
-            .checked_add(amount)
+    let header = *AccountLoader::<SortedLedger>::try_from(ledger_info)?.load()?;
+    if amount == 0 && header.flags & LEDGER_REJECT_ZERO_AMOUNT != 0 {
+        return Err(TokenError::ZeroAmount.into());
+    }
+    let (owner, to) = (ctx.accounts.owner.key, ctx.accounts.to.key);
+    let compliance_program = header.compliance_program;
+    if compliance_program != Pubkey::default() {
+        let now = Clock::get()?.unix_timestamp;
+        require_attestation(&compliance_program, ctx.remaining_accounts, owner, now)?;
+        require_attestation(&compliance_program, ctx.remaining_accounts, to, now)?;
+    }
+    if owner == to {
+        let data = ledger_info.try_borrow_data()?;
+        spendable_entry(split_ledger(&data)?.1, owner, amount)?;
+        return Ok(());
+    }
+    let fee = header.transfer_fee(amount);
+    debit_entry(ledger_info, owner, amount)?;
+    credit_entry(ledger_info, payer, ctx.remaining_accounts, to, amount - fee)?;
+    if fee > 0 {
+        let collector_info = find_account(ctx.remaining_accounts, &{ header.fee_collector })?;
+        let mut collector = Account::<FeeCollector>::try_from(collector_info)?;
+        collector.withheld = collector
+            .withheld
+            .checked_add(fee)
             .ok_or(TokenError::MathOverflow)?;
+        collector.exit(&crate::id())?;
+    }
+    Ok(())
+}
+
+// Index of the owner's entry, if they can send `amount` from it
+fn spendable_entry(entries: &[LedgerEntry], owner: &Pubkey, amount: u64) -> Result<usize> {
+    let index = find_entry(entries, owner).map_err(|_| TokenError::UnknownHolder)?;
+    if entries[index].flags & ENTRY_FROZEN != 0 {
+        return Err(TokenError::AccountFrozen.into());
+    }
+    if { entries[index].amount } < amount {
+        return Err(TokenError::InsufficientFunds.into());
+    }
+    Ok(index)
+}
+
+// Takes `amount` from the owner's entry
+fn debit_entry(ledger_info: &AccountInfo, owner: &Pubkey, amount: u64) -> Result<()> {
+    let mut data = ledger_info.try_borrow_mut_data()?;
+    let (_, entries) = split_ledger_mut(&mut data)?;
+    let index = spendable_entry(entries, owner, amount)?;
+    entries[index].amount -= amount;
+    Ok(())
+}
+
+// Adds `amount` to the owner's entry, inserting one for new holders and
+// growing the ledger at the expense of `payer` when it is out of space.
+// Zero amounts don't make anyone a holder.
+fn credit_entry<'info>(
+    ledger_info: &AccountInfo<'info>,
+    payer: &AccountInfo<'info>,
+    remaining_accounts: &[AccountInfo<'info>],
+    owner: &Pubkey,
+    amount: u64,
+) -> Result<()> {
+    let (max_size, len, index) = {
+        let mut data = ledger_info.try_borrow_mut_data()?;
+        let (header, entries) = split_ledger_mut(&mut data)?;
+        match find_entry(entries, owner) {
+            Ok(index) => {
+                if entries[index].flags & ENTRY_FROZEN != 0 {
+                    return Err(TokenError::AccountFrozen.into());
+                }
+                entries[index].amount = entries[index]
+                    .amount
+                    .checked_add(amount)
+                    .ok_or(TokenError::MathOverflow)?;
+                return Ok(());
+            }
+            Err(_) if amount == 0 => return Ok(()),
+            Err(index) => (header.max_size, header.len, index),
+        }
+    };
+
+    let required_len = ledger_size(len as usize + 1);
+    if required_len > max_size as usize {
+        return Err(TokenError::LedgerCapacityFull.into());
+    }
+    if required_len > ledger_info.data_len() {
+        grow_ledger(ledger_info, payer, remaining_accounts, required_len)?;
+    }
+    let mut data = ledger_info.try_borrow_mut_data()?;
+    insert_entry(
+        &mut data,
+        index,
+        LedgerEntry {
+            owner: *owner,
+            amount,
+            flags: 0,
+        },
+    )
+}
+
+// Moves `amount` between the owners' balance accounts, creating the
+// recipient's on first use unless `amount` is zero. Transfers to oneself
+// only check that the owner could send `amount`.
+fn transfer_balances<'info>(
+    payer: &AccountInfo<'info>,
+    ctx: &Context<'_, '_, '_, 'info, Transfer<'info>>,
+    amount: u64,
+) -> Result<()> {
+    let mint = ctx.accounts.mint.key;
+    let source = balance_address(mint, ctx.accounts.owner.key).0;
+    let (destination, bump) = balance_address(mint, ctx.accounts.to.key);
+
+    let mut source = spendable_balance(find_account(ctx.remaining_accounts, &source)?, amount)?;
+    if ctx.accounts.owner.key == ctx.accounts.to.key {
+        return Ok(());
+    }
+    source.amount -= amount;
+    source.exit(&crate::id())?;
+
+    credit_balance(
+        find_account(ctx.remaining_accounts, &destination)?,
+        payer,
+        ctx.remaining_accounts,
+        mint,
+        ctx.accounts.to.key,
+        bump,
+        amount,
+    )
+}
+
+// The owner's balance, if they can send `amount` from it
+fn spendable_balance<'info>(
+    balance_info: &AccountInfo<'info>,
+    amount: u64,
+) -> Result<Account<'info, Balance>> {
+    if balance_info.data_is_empty() {
+        return Err(TokenError::UnknownHolder.into());
+    }
+    let balance = Account::<Balance>::try_from(balance_info)?;
+    if balance.is_frozen {
+        return Err(TokenError::AccountFrozen.into());
+    }
+    if balance.amount < amount {
+        return Err(TokenError::InsufficientFunds.into());
+    }
+    Ok(balance)
+}
+
+// Adds `amount` to the owner's balance, creating it at the expense of
+// `payer` on first use unless `amount` is zero
+fn credit_balance<'info>(
+    balance_info: &AccountInfo<'info>,
+    payer: &AccountInfo<'info>,
+    remaining_accounts: &[AccountInfo<'info>],
+    mint: &Pubkey,
+    owner: &Pubkey,
+    bump: u8,
+    amount: u64,
+) -> Result<()> {
+    if balance_info.data_is_empty() && amount == 0 {
+        return Ok(());
+    }
+    let mut balance = if balance_info.data_is_empty() {
+        // Anyone can send lamports to the address beforehand, which would make
+        // `create_account` fail, so top up, allocate and assign separately
+        let system_program = find_account(remaining_accounts, &system_program::ID)?;
+        let seeds: &[&[u8]] = &[
+            BALANCE_PREFIX.as_bytes(),
+            mint.as_ref(),
+            owner.as_ref(),
+            &[bump],
+        ];
+        let lamports = Rent::get()?
+            .minimum_balance(BALANCE_SIZE)
+            .saturating_sub(balance_info.lamports());
+        if lamports > 0 {
+            system_program::transfer(
+                CpiContext::new(
+                    system_program.clone(),
+                    system_program::Transfer {
+                        from: payer.clone(),
+                        to: balance_info.clone(),
+                    },
+                ),
+                lamports,
+            )?;
+        }
+        system_program::allocate(
+            CpiContext::new_with_signer(
+                system_program.clone(),
+                system_program::Allocate {
+                    account_to_allocate: balance_info.clone(),
+                },
+                &[seeds],
+            ),
+            BALANCE_SIZE as u64,
+        )?;
+        system_program::assign(
+            CpiContext::new_with_signer(
+                system_program.clone(),
+                system_program::Assign {
+                    account_to_assign: balance_info.clone(),
+                },
+                &[seeds],
+            ),
+            &crate::id(),
+        )?;
+        Account::<Balance>::try_from_unchecked(balance_info)?
     } else {
-        account.amount = account
-            .amount
-            .checked_sub(amount)
-            .ok_or(TokenError::InsufficientFunds)?;
+        Account::<Balance>::try_from(balance_info)?
+    };
+    if balance.is_frozen {
+        return Err(TokenError::AccountFrozen.into());
     }
-    accounts.insert(*owner, account);
+    balance.amount = balance
+        .amount
+        .checked_add(amount)
+        .ok_or(TokenError::MathOverflow)?;
+    balance.exit(&crate::id())
+}
+
+// Takes `amount` from the allowance `authority` was given in `delegation_info`
+fn spend_allowance(authority: &Pubkey, delegation_info: &AccountInfo, amount: u64) -> Result<()> {
+    if delegation_info.owner != &crate::id() || delegation_info.data_is_empty() {
+        return Err(TokenError::Unauthorized.into());
+    }
+    let mut delegation = Account::<Delegation>::try_from(delegation_info)?;
+    if &delegation.delegate != authority {
+        return Err(TokenError::Unauthorized.into());
+    }
+    delegation.allowance = delegation
+        .allowance
+        .checked_sub(amount)
+        .ok_or(TokenError::InsufficientFunds)?;
+    delegation.exit(&crate::id())
+}
+
+// The account holding `owner`'s balance of a balance mint
+pub fn balance_address(mint: &Pubkey, owner: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[BALANCE_PREFIX.as_bytes(), mint.as_ref(), owner.as_ref()],
+        &crate::id(),
+    )
+}
+
+fn find_account<'a, 'info>(
+    accounts: &'a [AccountInfo<'info>],
+    key: &Pubkey,
+) -> Result<&'a AccountInfo<'info>> {
+    accounts
+        .iter()
+        .find(|acc| acc.key == key)
+        .ok_or_else(|| TokenError::MissingAccount.into())
+}
+
+// Space needed to store `holders` entries in the ledger
+pub const fn ledger_size(holders: usize) -> usize {
+    LEDGER_HEADER_SIZE + holders * LEDGER_ENTRY_SIZE
+}
+
+// Splits a ledger's data into its header and entries. Callers check
+// the discriminator, e.g. through `AccountLoader`.
+pub fn split_ledger(data: &[u8]) -> Result<(&SortedLedger, &[LedgerEntry])> {
+    let (header, rest) = data[8..].split_at(LEDGER_HEADER_SIZE - 8);
+    let header: &SortedLedger = bytemuck::from_bytes(header);
+    let entries = rest
+        .get(..header.len as usize * LEDGER_ENTRY_SIZE)
+        .ok_or(TokenError::InvalidLedgerSize)?;
+    Ok((header, bytemuck::cast_slice(entries)))
+}
+
+pub fn split_ledger_mut(data: &mut [u8]) -> Result<(&mut SortedLedger, &mut [LedgerEntry])> {
+    let (header, rest) = data[8..].split_at_mut(LEDGER_HEADER_SIZE - 8);
+    let header: &mut SortedLedger = bytemuck::from_bytes_mut(header);
+    let entries = rest
+        .get_mut(..header.len as usize * LEDGER_ENTRY_SIZE)
+        .ok_or(TokenError::InvalidLedgerSize)?;
+    Ok((header, bytemuck::cast_slice_mut(entries)))
+}
+
+// Index of `owner`'s entry, or where to insert it to keep the entries sorted
+pub fn find_entry(entries: &[LedgerEntry], owner: &Pubkey) -> std::result::Result<usize, usize> {
+    entries.binary_search_by(|entry| entry.owner.cmp(owner))
+}
+
+// Inserts `entry` at `index`, which the ledger's data must have room for
+pub fn insert_entry(data: &mut [u8], index: usize, entry: LedgerEntry) -> Result<()> {
+    split_ledger_mut(data)?.0.len += 1;
+    let (_, entries) = split_ledger_mut(data)?;
+    entries.copy_within(index..entries.len() - 1, index + 1);
+    entries[index] = entry;
     Ok(())
 }
 
-#[account]
-pub struct Ledger {
+// The routers `transfer` is restricted to, if routing was initialized with any
+fn get_routers(routing: &AccountInfo) -> Result<Option<Vec<Pubkey>>> {
+    if routing.owner != &crate::id() || routing.data_is_empty() {
+        return Ok(None);
+    }
+    let routing = Account::<Routing>::try_from(routing)?;
+    if routing.routers.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(routing.routers.clone()))
+}
+
+// Reallocates the ledger to `new_len`, topping up rent from `payer`.
+// Fails if the caller did not pass the system program, which only happens
+// when they skipped the funding accounts requested during preflight.
+fn grow_ledger<'info>(
+    ledger: &AccountInfo<'info>,
+    payer: &AccountInfo<'info>,
+    remaining_accounts: &[AccountInfo<'info>],
+    new_len: usize,
+) -> Result<()> {
+    let system_program = find_account(remaining_accounts, &system_program::ID)?;
+
+    let lamports = Rent::get()?
+        .minimum_balance(new_len)
+        .saturating_sub(ledger.lamports());
+    if lamports > 0 {
+        system_program::transfer(
+            CpiContext::new(
+                system_program.clone(),
+                system_program::Transfer {
+                    from: payer.clone(),
+                    to: ledger.clone(),
+                },
+            ),
+            lamports,
+        )?;
+    }
+    ledger.realloc(new_len, false)?;
+    Ok(())
+}
+
+// Reallocates the ledger down to `new_len`, moving the rent it no longer needs to `destination`
+fn shrink_ledger<'info>(
+    ledger: &AccountInfo<'info>,
+    destination: &AccountInfo<'info>,
+    new_len: usize,
+) -> Result<()> {
+    ledger.realloc(new_len, false)?;
+    let refund = ledger
+        .lamports()
+        .saturating_sub(Rent::get()?.minimum_balance(new_len));
+    **ledger.try_borrow_mut_lamports()? -= refund;
+    **destination.try_borrow_mut_lamports()? += refund;
+    Ok(())
+}
+
+pub type LedgerAccounts = HashMap<Pubkey, LedgerAccount>;
+fn get_ledger_accounts(data: &[u8]) -> Result<LedgerAccounts> {
+    Ok(LedgerAccounts::try_from_slice(data)?)
+}
+
+pub use zero_copy_layout::{LedgerEntry, SortedLedger};
+
+// bytemuck's derive adds a `check` fn to packed structs that nothing calls
+#[allow(dead_code)]
+mod zero_copy_layout {
+    use super::*;
+
+    // Fixed part of the ledger. It is followed by `len` entries sorted by
+    // owner, and room for more up to the size of the account.
+    // Packed so account data can be cast regardless of its alignment.
+    #[account(zero_copy)]
+    #[repr(C, packed)]
+    pub struct SortedLedger {
+        pub total_supply: u64,
+        pub mint_authority: Pubkey,
+        pub freeze_authority: Pubkey,
+        // Size in bytes that transfers may grow the ledger to
+        pub max_size: u32,
+        pub len: u32,
+        // `LEDGER_*` bits configuring transfers
+        pub flags: u8,
+        // Share of each transfer withheld in `fee_collector`, up to `max_fee`
+        pub fee_bps: u16,
+        pub max_fee: u64,
+        pub fee_collector: Pubkey,
+        // Program whose attestations both sides of a transfer need, unless default
+        pub compliance_program: Pubkey,
+    }
+
+    #[zero_copy]
+    #[repr(C, packed)]
+    pub struct LedgerEntry {
+        pub owner: Pubkey,
+        pub amount: u64,
+        pub flags: u8,
+    }
+}
+
+impl SortedLedger {
+    // What `transfer` withholds from `amount`
+    pub fn transfer_fee(&self, amount: u64) -> u64 {
+        let fee = amount as u128 * self.fee_bps as u128 / MAX_FEE_BPS as u128;
+        (fee as u64).min(self.max_fee)
+    }
+}
+
+// The ledger layout before `SortedLedger`, only read by `migrate_ledger`.
+// Frozen as deployed, so it keeps the discriminator of its old name `Ledger`.
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct LegacyLedger {
     pub total_supply: u64,
     pub mint_authority: Pubkey,
     pub freeze_authority: Pubkey,
@@ -129,6 +1252,82 @@ pub struct Ledger {
     pub opaque_accounts: Vec<u8>,
 }
 
+impl LegacyLedger {
+    pub fn discriminator() -> [u8; 8] {
+        let hash = anchor_lang::solana_program::hash::hash(b"account:Ledger");
+        let mut discriminator = [0; 8];
+        discriminator.copy_from_slice(&hash.to_bytes()[..8]);
+        discriminator
+    }
+
+    fn try_from_account(info: &AccountInfo) -> Result<Self> {
+        if info.owner != &crate::id() {
+            return Err(ErrorCode::AccountOwnedByWrongProgram.into());
+        }
+        let data = info.try_borrow_data()?;
+        if data.get(..8) != Some(&Self::discriminator()[..]) {
+            return Err(ErrorCode::AccountDiscriminatorMismatch.into());
+        }
+        Self::deserialize(&mut &data[8..]).map_err(|_| ErrorCode::AccountDidNotDeserialize.into())
+    }
+}
+
+#[derive(Clone, AnchorSerialize, AnchorDeserialize)]
+pub struct MintMetadata {
+    pub name: String,
+    pub symbol: String,
+    pub decimals: u8,
+}
+
+// A mint whose balances live in the ledger at `ledger_address(id)`
+#[account]
+pub struct MintState {
+    pub id: Pubkey,
+    pub metadata: MintMetadata,
+}
+
+// A mint whose balances live in `Balance` accounts at `balance_address`
+#[account]
+pub struct BalanceMint {
+    pub total_supply: u64,
+    pub mint_authority: Pubkey,
+    pub freeze_authority: Pubkey,
+}
+
+#[account]
+pub struct Balance {
+    pub amount: u64,
+    pub is_frozen: bool,
+}
+
+// Lets `delegate` burn up to `allowance` of an owner's tokens, at
+// `["delegate", ledger, owner]`, or `["delegate", mint, owner]` for balance mints
+#[account]
+pub struct Delegation {
+    pub delegate: Pubkey,
+    pub allowance: u64,
+}
+
+// What a compliance program keeps at `attestation_address` for each owner
+// it cleared, until `expires_at`
+#[derive(AnchorSerialize, AnchorDeserialize)]
+pub struct Attestation {
+    pub owner: Pubkey,
+    pub expires_at: i64,
+}
+
+// Transfer fees withheld from a ledger's transfers, at `["fees", ledger]`.
+// They count towards `total_supply` until withdrawn.
+#[account]
+pub struct FeeCollector {
+    pub withheld: u64,
+}
+
+#[account]
+pub struct Routing {
+    pub routers: Vec<Pubkey>,
+}
+
 #[derive(Debug, Clone, AnchorSerialize, AnchorDeserialize)]
 pub struct LedgerAccount {
     pub amount: u64,
@@ -136,19 +1335,313 @@ pub struct LedgerAccount {
 }
 
 #[derive(Accounts)]
+#[instruction(id: Pubkey)]
 pub struct InitializeMint<'info> {
     #[account(mut)]
     pub authority: Signer<'info>,
     #[account(init,
-        seeds=[LEDGER_PREFIX.as_bytes()],
+        seeds=[MINT_PREFIX.as_bytes(), id.as_ref()],
+        bump,
+        payer=authority,
+        space=MINT_STATE_SIZE
+    )]
+    pub mint: Account<'info, MintState>,
+    #[account(init,
+        seeds=[LEDGER_PREFIX.as_bytes(), id.as_ref()],
+        bump,
+        payer=authority,
+        space=ledger_size(DEFAULT_CAPACITY)
+    )]
+    pub ledger: AccountLoader<'info, SortedLedger>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct MigrateLedger<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    /// CHECK: still in the `LegacyLedger` layout, which `migrate_ledger` checks
+    #[account(mut, seeds=[LEDGER_PREFIX.as_bytes()], bump)]
+    pub ledger: UncheckedAccount<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeBalanceMint<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    #[account(init, payer=authority, space=BALANCE_MINT_SIZE)]
+    pub mint: Account<'info, BalanceMint>,
+    #[account(init,
+        seeds=[BALANCE_PREFIX.as_bytes(), mint.key().as_ref(), authority.key().as_ref()],
+        bump,
+        payer=authority,
+        space=BALANCE_SIZE
+    )]
+    pub balance: Account<'info, Balance>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeRouting<'info> {
+    #[account(mut)]
+    pub authority: Signer<'info>,
+    #[account(constraint = ledger.load()?.mint_authority == authority.key())]
+    pub ledger: AccountLoader<'info, SortedLedger>,
+    #[account(init,
+        seeds=[ROUTING_PREFIX.as_bytes(), ledger.key().as_ref()],
         bump,
         payer=authority,
-        space=8 + 8 + 32 + 32 + 4 + DEFAULT_CAPACITY * (32 + std::mem::size_of::<LedgerAccount>())
+        space=8 + 4 + MAX_ROUTERS * 32
+    )]
+    pub routing: Account<'info, Routing>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetRouters<'info> {
+    pub authority: Signer<'info>,
+    #[account(constraint = ledger.load()?.mint_authority == authority.key())]
+    pub ledger: AccountLoader<'info, SortedLedger>,
+    #[account(mut, seeds=[ROUTING_PREFIX.as_bytes(), ledger.key().as_ref()], bump)]
+    pub routing: Account<'info, Routing>,
+}
+
+#[derive(Accounts)]
+pub struct SetMaxLedgerSize<'info> {
+    pub authority: Signer<'info>,
+    #[account(mut, constraint = ledger.load()?.mint_authority == authority.key())]
+    pub ledger: AccountLoader<'info, SortedLedger>,
+}
+
+#[derive(Accounts)]
+pub struct ConfigureLedger<'info> {
+    pub mint_authority: Signer<'info>,
+    #[account(mut, constraint = ledger.load()?.mint_authority == mint_authority.key() @ TokenError::Unauthorized)]
+    pub ledger: AccountLoader<'info, SortedLedger>,
+}
+
+#[derive(Accounts)]
+pub struct SetComplianceProgram<'info> {
+    #[account(mut)]
+    pub mint_authority: Signer<'info>,
+    #[account(mut, constraint = ledger.load()?.mint_authority == mint_authority.key() @ TokenError::Unauthorized)]
+    pub ledger: AccountLoader<'info, SortedLedger>,
+    // Mint states created without room for the compliance program grow to hold it
+    #[account(mut,
+        constraint = ledger_address(&mint.id).0 == ledger.key() @ TokenError::LedgerMismatch,
+        realloc = MINT_STATE_SIZE,
+        realloc::payer = mint_authority,
+        realloc::zero = false
+    )]
+    pub mint: Account<'info, MintState>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct InitializeTransferFee<'info> {
+    #[account(mut)]
+    pub mint_authority: Signer<'info>,
+    #[account(mut, constraint = ledger.load()?.mint_authority == mint_authority.key() @ TokenError::Unauthorized)]
+    pub ledger: AccountLoader<'info, SortedLedger>,
+    #[account(init,
+        seeds=[FEES_PREFIX.as_bytes(), ledger.key().as_ref()],
+        bump,
+        payer=mint_authority,
+        space=FEE_COLLECTOR_SIZE
+    )]
+    pub fee_collector: Account<'info, FeeCollector>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetTransferFee<'info> {
+    pub mint_authority: Signer<'info>,
+    #[account(mut, constraint = ledger.load()?.mint_authority == mint_authority.key() @ TokenError::Unauthorized)]
+    pub ledger: AccountLoader<'info, SortedLedger>,
+    #[account(seeds=[FEES_PREFIX.as_bytes(), ledger.key().as_ref()], bump)]
+    pub fee_collector: Account<'info, FeeCollector>,
+}
+
+#[derive(Accounts)]
+pub struct WithdrawFees<'info> {
+    #[account(mut)]
+    pub mint_authority: Signer<'info>,
+    #[account(mut, constraint = ledger.load()?.mint_authority == mint_authority.key() @ TokenError::Unauthorized)]
+    pub ledger: AccountLoader<'info, SortedLedger>,
+    #[account(mut, seeds=[FEES_PREFIX.as_bytes(), ledger.key().as_ref()], bump)]
+    pub fee_collector: Account<'info, FeeCollector>,
+    /// CHECK: only names the entry to credit
+    pub to: UncheckedAccount<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct CloseAccount<'info> {
+    pub owner: Signer<'info>,
+    #[account(mut)]
+    pub ledger: AccountLoader<'info, SortedLedger>,
+    /// CHECK: only receives the rent freed from the ledger
+    #[account(mut)]
+    pub destination: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct MintTo<'info> {
+    #[account(mut)]
+    pub mint_authority: Signer<'info>,
+    #[account(mut, constraint = ledger.load()?.mint_authority == mint_authority.key() @ TokenError::Unauthorized)]
+    pub ledger: AccountLoader<'info, SortedLedger>,
+    /// CHECK: only names the entry to credit
+    pub to: UncheckedAccount<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct Burn<'info> {
+    // The owner, or their delegate
+    pub authority: Signer<'info>,
+    /// CHECK: only names the entry to debit
+    pub owner: UncheckedAccount<'info>,
+    #[account(mut)]
+    pub ledger: AccountLoader<'info, SortedLedger>,
+    /// CHECK: may not be initialized, only read when `authority` isn't the owner
+    #[account(mut, seeds=[DELEGATE_PREFIX.as_bytes(), ledger.key().as_ref(), owner.key().as_ref()], bump)]
+    pub delegation: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetMintAuthority<'info> {
+    pub mint_authority: Signer<'info>,
+    #[account(mut, constraint = ledger.load()?.mint_authority == mint_authority.key() @ TokenError::Unauthorized)]
+    pub ledger: AccountLoader<'info, SortedLedger>,
+}
+
+#[derive(Accounts)]
+pub struct Approve<'info> {
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    pub ledger: AccountLoader<'info, SortedLedger>,
+    /// CHECK: only recorded as the delegate
+    pub delegate: UncheckedAccount<'info>,
+    #[account(init,
+        seeds=[DELEGATE_PREFIX.as_bytes(), ledger.key().as_ref(), owner.key().as_ref()],
+        bump,
+        payer=owner,
+        space=DELEGATION_SIZE
     )]
-    pub ledger: Account<'info, Ledger>,
+    pub delegation: Account<'info, Delegation>,
     pub system_program: Program<'info, System>,
 }
 
+#[derive(Accounts)]
+pub struct Revoke<'info> {
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    pub ledger: AccountLoader<'info, SortedLedger>,
+    #[account(mut,
+        seeds=[DELEGATE_PREFIX.as_bytes(), ledger.key().as_ref(), owner.key().as_ref()],
+        bump,
+        close=owner
+    )]
+    pub delegation: Account<'info, Delegation>,
+}
+
+#[derive(Accounts)]
+pub struct MintToBalance<'info> {
+    #[account(mut)]
+    pub mint_authority: Signer<'info>,
+    #[account(mut, constraint = mint.mint_authority == mint_authority.key() @ TokenError::Unauthorized)]
+    pub mint: Account<'info, BalanceMint>,
+    /// CHECK: only names the balance to credit
+    pub to: UncheckedAccount<'info>,
+    /// CHECK: may not be initialized, see `credit_balance`
+    #[account(mut, seeds=[BALANCE_PREFIX.as_bytes(), mint.key().as_ref(), to.key().as_ref()], bump)]
+    pub balance: UncheckedAccount<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct BurnBalance<'info> {
+    // The owner, or their delegate
+    pub authority: Signer<'info>,
+    /// CHECK: only names the balance to debit
+    pub owner: UncheckedAccount<'info>,
+    #[account(mut)]
+    pub mint: Account<'info, BalanceMint>,
+    /// CHECK: may not be initialized, see `spendable_balance`
+    #[account(mut, seeds=[BALANCE_PREFIX.as_bytes(), mint.key().as_ref(), owner.key().as_ref()], bump)]
+    pub balance: UncheckedAccount<'info>,
+    /// CHECK: may not be initialized, only read when `authority` isn't the owner
+    #[account(mut, seeds=[DELEGATE_PREFIX.as_bytes(), mint.key().as_ref(), owner.key().as_ref()], bump)]
+    pub delegation: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct ApproveBalance<'info> {
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    pub mint: Account<'info, BalanceMint>,
+    /// CHECK: only recorded as the delegate
+    pub delegate: UncheckedAccount<'info>,
+    #[account(init,
+        seeds=[DELEGATE_PREFIX.as_bytes(), mint.key().as_ref(), owner.key().as_ref()],
+        bump,
+        payer=owner,
+        space=DELEGATION_SIZE
+    )]
+    pub delegation: Account<'info, Delegation>,
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct RevokeBalance<'info> {
+    #[account(mut)]
+    pub owner: Signer<'info>,
+    pub mint: Account<'info, BalanceMint>,
+    #[account(mut,
+        seeds=[DELEGATE_PREFIX.as_bytes(), mint.key().as_ref(), owner.key().as_ref()],
+        bump,
+        close=owner
+    )]
+    pub delegation: Account<'info, Delegation>,
+}
+
+#[derive(Accounts)]
+pub struct FreezeAccount<'info> {
+    pub freeze_authority: Signer<'info>,
+    #[account(mut, constraint = ledger.load()?.freeze_authority == freeze_authority.key() @ TokenError::Unauthorized)]
+    pub ledger: AccountLoader<'info, SortedLedger>,
+    /// CHECK: only names the entry to freeze or thaw
+    pub owner: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetFreezeAuthority<'info> {
+    pub freeze_authority: Signer<'info>,
+    #[account(mut, constraint = ledger.load()?.freeze_authority == freeze_authority.key() @ TokenError::Unauthorized)]
+    pub ledger: AccountLoader<'info, SortedLedger>,
+}
+
+#[derive(Accounts)]
+pub struct FreezeBalance<'info> {
+    pub freeze_authority: Signer<'info>,
+    #[account(constraint = mint.freeze_authority == freeze_authority.key() @ TokenError::Unauthorized)]
+    pub mint: Account<'info, BalanceMint>,
+    /// CHECK: only names the balance to freeze or thaw
+    pub owner: UncheckedAccount<'info>,
+    /// CHECK: may not be initialized, see `set_balance_frozen`
+    #[account(mut, seeds=[BALANCE_PREFIX.as_bytes(), mint.key().as_ref(), owner.key().as_ref()], bump)]
+    pub balance: UncheckedAccount<'info>,
+}
+
+#[derive(Accounts)]
+pub struct SetBalanceFreezeAuthority<'info> {
+    pub freeze_authority: Signer<'info>,
+    #[account(mut, constraint = mint.freeze_authority == freeze_authority.key() @ TokenError::Unauthorized)]
+    pub mint: Account<'info, BalanceMint>,
+}
+
 #[derive(Accounts)]
 pub struct ITransfer<'info> {
     /// CHECK:
@@ -169,6 +1662,10 @@ pub struct Transfer<'info> {
     pub authority: Signer<'info>,
     /// CHECK:
     pub mint: AccountInfo<'info>,
-    #[account(mut, seeds=[LEDGER_PREFIX.as_bytes()], bump)]
-    pub ledger: Account<'info, Ledger>,
+    /// CHECK: the mint's ledger, or the mint itself for balance mints. Checked
+    /// against `mint` by `transfer`, and loaded by `transfer_in_ledger`.
+    pub ledger: UncheckedAccount<'info>,
+    /// CHECK: may not be initialized, see `get_routers`
+    #[account(seeds=[ROUTING_PREFIX.as_bytes(), ledger.key().as_ref()], bump)]
+    pub routing: UncheckedAccount<'info>,
 }
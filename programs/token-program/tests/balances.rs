@@ -0,0 +1,273 @@
+// Anchor's derives use cfgs that newer compilers warn about
+#![allow(unknown_lints, unexpected_cfgs)]
+use std::collections::HashSet;
+
+use anchor_lang::{system_program, AnchorSerialize};
+use solana_program_test::{BanksClient, ProgramTest};
+use solana_sdk::{
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    sysvar,
+};
+use token_interface::PayerRole;
+use token_interface_conformance::{anchor_processor, decode_payload, Conformance};
+use token_program::{
+    balance_address, fee_collector_address, routing_address, TokenError, BALANCE_SIZE,
+};
+
+mod common;
+use common::*;
+
+// Accounts a transfer write-locks besides the fee payer and the owner
+async fn write_locks(
+    banks_client: &mut BanksClient,
+    payer: &Keypair,
+    mint: &Pubkey,
+    owner: &Keypair,
+    to: &Pubkey,
+) -> HashSet<Pubkey> {
+    preflight(banks_client, payer, mint, owner, to, 1)
+        .await
+        .accounts
+        .iter()
+        .filter(|acc| acc.writable && acc.pubkey != payer.pubkey() && acc.pubkey != owner.pubkey())
+        .map(|acc| acc.pubkey)
+        .collect()
+}
+
+#[tokio::test]
+async fn preflight_requests_balance_accounts() {
+    let mut context = setup(1_000).await;
+    let (banks_client, payer) = (&mut context.banks_client, &context.payer);
+    let mint = initialize_balance_mint(banks_client, payer, 100).await;
+    let to = Pubkey::new_unique();
+
+    let payload = preflight(banks_client, payer, &mint, payer, &to, 1).await;
+    let accounts: Vec<(Pubkey, bool)> = payload
+        .accounts
+        .iter()
+        .map(|acc| (acc.pubkey, acc.writable))
+        .collect();
+    assert_eq!(
+        accounts,
+        vec![
+            (mint, false),
+            (routing_address(&mint).0, false),
+            (sysvar::instructions::ID, false),
+            (balance_address(&mint, &payer.pubkey()).0, true),
+            (balance_address(&mint, &to).0, true),
+            (payer.pubkey(), true),
+            (system_program::ID, false),
+        ]
+    );
+    let rent = banks_client.get_rent().await.unwrap();
+    let funding = payload.funding().unwrap();
+    assert_eq!(funding.lamports, rent.minimum_balance(BALANCE_SIZE));
+    assert_eq!(funding.payer, PayerRole::Authority);
+}
+
+#[tokio::test]
+async fn transfer_creates_the_destination_balance() {
+    let mut context = setup(1_000).await;
+    let (banks_client, payer) = (&mut context.banks_client, &context.payer);
+    let mint = initialize_balance_mint(banks_client, payer, 100).await;
+    let holder = Keypair::new();
+
+    transfer(banks_client, payer, &mint, payer, &holder.pubkey(), 10)
+        .await
+        .unwrap();
+    assert_eq!(
+        balance_of(banks_client, &mint, &payer.pubkey()).await,
+        Some(90)
+    );
+    assert_eq!(
+        balance_of(banks_client, &mint, &holder.pubkey()).await,
+        Some(10)
+    );
+
+    // Existing balances are reused
+    transfer(banks_client, payer, &mint, &holder, &payer.pubkey(), 4)
+        .await
+        .unwrap();
+    transfer(banks_client, payer, &mint, payer, &payer.pubkey(), 50)
+        .await
+        .unwrap();
+    assert_eq!(
+        balance_of(banks_client, &mint, &payer.pubkey()).await,
+        Some(94)
+    );
+    assert_eq!(
+        balance_of(banks_client, &mint, &holder.pubkey()).await,
+        Some(6)
+    );
+
+    // Balance mints leave the ledger alone
+    assert_eq!(balance(banks_client, &payer.pubkey()).await, Some(1_000));
+    assert_eq!(balance(banks_client, &holder.pubkey()).await, None);
+}
+
+// Lamports sent to the balance address up front can't block its creation
+#[tokio::test]
+async fn transfer_creates_prefunded_balances() {
+    let mut context = setup(1_000).await;
+    let (banks_client, payer) = (&mut context.banks_client, &context.payer);
+    let mint = initialize_balance_mint(banks_client, payer, 100).await;
+    let rent = banks_client.get_rent().await.unwrap();
+    let (short, covered) = (Pubkey::new_unique(), Pubkey::new_unique());
+    // The least an empty account can hold, short of the balance's rent
+    prefund_balance(banks_client, payer, &mint, &short, rent.minimum_balance(0)).await;
+    let lamports = rent.minimum_balance(BALANCE_SIZE) + 1;
+    prefund_balance(banks_client, payer, &mint, &covered, lamports).await;
+
+    transfer(banks_client, payer, &mint, payer, &short, 10)
+        .await
+        .unwrap();
+    transfer(banks_client, payer, &mint, payer, &covered, 20)
+        .await
+        .unwrap();
+    assert_eq!(balance_of(banks_client, &mint, &short).await, Some(10));
+    assert_eq!(balance_of(banks_client, &mint, &covered).await, Some(20));
+
+    // Only the shortfall is topped up, and nothing is taken back
+    let short_account = banks_client
+        .get_account(balance_address(&mint, &short).0)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(short_account.owner, token_program::id());
+    assert_eq!(short_account.lamports, rent.minimum_balance(BALANCE_SIZE));
+    let covered_account = banks_client
+        .get_account(balance_address(&mint, &covered).0)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(covered_account.lamports, lamports);
+}
+
+#[tokio::test]
+async fn transfer_checks_balances() {
+    let mut context = setup(1_000).await;
+    let (banks_client, payer) = (&mut context.banks_client, &context.payer);
+    let mint = initialize_balance_mint(banks_client, payer, 100).await;
+    let stranger = Keypair::new();
+
+    let result = transfer(banks_client, payer, &mint, payer, &stranger.pubkey(), 101).await;
+    assert_token_error(result, TokenError::InsufficientFunds);
+    let result = transfer(banks_client, payer, &mint, &stranger, &payer.pubkey(), 1).await;
+    assert_token_error(result, TokenError::UnknownHolder);
+    assert_eq!(
+        balance_of(banks_client, &mint, &stranger.pubkey()).await,
+        None
+    );
+
+    // Zero amounts succeed without creating the destination's balance
+    let payload = preflight(banks_client, payer, &mint, payer, &stranger.pubkey(), 0).await;
+    assert!(payload.funding().is_none());
+    transfer(banks_client, payer, &mint, payer, &stranger.pubkey(), 0)
+        .await
+        .unwrap();
+    assert_eq!(
+        balance_of(banks_client, &mint, &stranger.pubkey()).await,
+        None
+    );
+}
+
+// Callers that predate extensions decode return data as a bare `Vec<IAccountMeta>`
+#[tokio::test]
+async fn preflight_without_the_extensions_hint_returns_bare_accounts() {
+    let mut context = setup(1_000).await;
+    let (banks_client, payer) = (&mut context.banks_client, &context.payer);
+    let mint = initialize_balance_mint(banks_client, payer, 100).await;
+    let to = Pubkey::new_unique();
+
+    let payload = preflight(banks_client, payer, &mint, payer, &to, 1).await;
+    assert!(payload.funding().is_some());
+    let data = preflight_data(banks_client, payer, &mint, payer, &to, 1, &[]).await;
+    let bare = decode_payload(&data).unwrap();
+    assert!(bare.extensions.is_empty());
+    assert_eq!(
+        bare.accounts.try_to_vec().unwrap(),
+        payload.accounts.try_to_vec().unwrap()
+    );
+}
+
+#[tokio::test]
+async fn hinted_balances_skip_funding() {
+    let mut context = setup(1_000).await;
+    let (banks_client, payer) = (&mut context.banks_client, &context.payer);
+    let mint = initialize_balance_mint(banks_client, payer, 100).await;
+    let holder = Pubkey::new_unique();
+    transfer(banks_client, payer, &mint, payer, &holder, 1)
+        .await
+        .unwrap();
+
+    let hints = [
+        balance_address(&mint, &payer.pubkey()).0,
+        balance_address(&mint, &holder).0,
+    ];
+    let hinted = preflight_with_hints(banks_client, payer, &mint, payer, &holder, 1, &hints).await;
+    assert!(hinted.funding().is_none());
+    assert_eq!(hinted.accounts.len(), 5);
+    let preview = hinted.preview().unwrap();
+    assert_eq!(preview.deltas.len(), 2);
+}
+
+// Transfers between unrelated holders never share a write lock in balance
+// mode, while every ledger transfer write-locks the ledger
+#[tokio::test]
+async fn balance_transfers_do_not_contend() {
+    let mut context = setup(1_000).await;
+    let (banks_client, payer) = (&mut context.banks_client, &context.payer);
+    let mint = initialize_balance_mint(banks_client, payer, 100).await;
+    let (alice, bob) = (Keypair::new(), Keypair::new());
+    for holder in [&alice, &bob] {
+        transfer(
+            banks_client,
+            payer,
+            &mint_key(),
+            payer,
+            &holder.pubkey(),
+            10,
+        )
+        .await
+        .unwrap();
+        transfer(banks_client, payer, &mint, payer, &holder.pubkey(), 10)
+            .await
+            .unwrap();
+    }
+    let (carol, dave) = (Pubkey::new_unique(), Pubkey::new_unique());
+
+    let id = mint_key();
+    let ledger_a = write_locks(banks_client, payer, &id, &alice, &carol).await;
+    let ledger_b = write_locks(banks_client, payer, &id, &bob, &dave).await;
+    // Unless hinted otherwise, preflight also asks for the fee collector
+    assert_eq!(
+        ledger_a
+            .intersection(&ledger_b)
+            .copied()
+            .collect::<HashSet<_>>(),
+        HashSet::from([ledger_key(), fee_collector_address(&ledger_key()).0])
+    );
+
+    let balances_a = write_locks(banks_client, payer, &mint, &alice, &carol).await;
+    let balances_b = write_locks(banks_client, payer, &mint, &bob, &dave).await;
+    assert_eq!(balances_a.len(), 2);
+    assert!(balances_a.is_disjoint(&balances_b));
+}
+
+#[tokio::test]
+async fn balance_mint_conforms() {
+    let mut program_test = ProgramTest::default();
+    program_test.add_program(
+        "token_program",
+        token_program::id(),
+        anchor_processor!(token_program::entry),
+    );
+    let mut context = program_test.start_with_context().await;
+    let mint = initialize_balance_mint(&mut context.banks_client, &context.payer, 1_000).await;
+
+    Conformance::new(token_program::id(), mint)
+        .run(&mut context.banks_client, &context.payer)
+        .await
+        .assert_passed();
+}
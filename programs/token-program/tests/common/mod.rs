@@ -0,0 +1,334 @@
+#![allow(dead_code)]
+use anchor_lang::{
+    system_program, AccountDeserialize, Discriminator, InstructionData, ToAccountMetas,
+};
+use solana_program_test::{BanksClient, BanksClientError, ProgramTest, ProgramTestContext};
+use solana_sdk::{
+    instruction::{AccountMeta, Instruction, InstructionError},
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    system_instruction,
+    transaction::{Transaction, TransactionError},
+};
+use token_interface::{PreflightPayload, EXTENSIONS_HINT};
+use token_interface_conformance::{anchor_processor, decode_payload};
+use token_program::{
+    balance_address, fee_collector_address, find_entry, ledger_address, ledger_size, mint_address,
+    split_ledger, Balance, FeeCollector, MintMetadata, SortedLedger,
+};
+
+// Identifies the mint `setup` creates
+pub fn mint_id() -> Pubkey {
+    Pubkey::new_from_array([7; 32])
+}
+
+// The `MintState` transfers of the `setup` mint are sent with
+pub fn mint_key() -> Pubkey {
+    mint_address(&mint_id()).0
+}
+
+pub fn ledger_key() -> Pubkey {
+    ledger_address(&mint_id()).0
+}
+
+// Starts a bank running `token_program`, with `supply` minted to the payer
+pub async fn setup(supply: u64) -> ProgramTestContext {
+    setup_with(ProgramTest::default(), supply).await
+}
+
+// Like `setup`, also running the programs already added to `program_test`
+pub async fn setup_with(mut program_test: ProgramTest, supply: u64) -> ProgramTestContext {
+    program_test.add_program(
+        "token_program",
+        token_program::id(),
+        anchor_processor!(token_program::entry),
+    );
+    let mut context = program_test.start_with_context().await;
+    initialize_mint(
+        &mut context.banks_client,
+        &context.payer,
+        &mint_id(),
+        supply,
+    )
+    .await
+    .unwrap();
+    context
+}
+
+// Creates the ledger mint identified by `id`, with `supply` minted to the payer
+pub async fn initialize_mint(
+    banks_client: &mut BanksClient,
+    payer: &Keypair,
+    id: &Pubkey,
+    supply: u64,
+) -> Result<(), BanksClientError> {
+    let ix = Instruction {
+        program_id: token_program::id(),
+        accounts: token_program::accounts::InitializeMint {
+            authority: payer.pubkey(),
+            mint: mint_address(id).0,
+            ledger: ledger_address(id).0,
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: token_program::instruction::InitializeMint {
+            id: *id,
+            metadata: MintMetadata {
+                name: "Test".to_string(),
+                symbol: "TST".to_string(),
+                decimals: 0,
+            },
+            supply,
+        }
+        .data(),
+    };
+    process(banks_client, payer, &[], ix).await
+}
+
+// Creates a balance mint with `supply` minted to the payer
+pub async fn initialize_balance_mint(
+    banks_client: &mut BanksClient,
+    payer: &Keypair,
+    supply: u64,
+) -> Pubkey {
+    let mint = Keypair::new();
+    let ix = Instruction {
+        program_id: token_program::id(),
+        accounts: token_program::accounts::InitializeBalanceMint {
+            authority: payer.pubkey(),
+            mint: mint.pubkey(),
+            balance: balance_address(&mint.pubkey(), &payer.pubkey()).0,
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: token_program::instruction::InitializeBalanceMint { supply }.data(),
+    };
+    process(banks_client, payer, &[&mint], ix).await.unwrap();
+    mint.pubkey()
+}
+
+// Sends `ix` paid for by `payer`, signed by `signers` as well
+pub async fn process(
+    banks_client: &mut BanksClient,
+    payer: &Keypair,
+    signers: &[&Keypair],
+    ix: Instruction,
+) -> Result<(), BanksClientError> {
+    let mut keypairs = vec![payer];
+    keypairs.extend(signers.iter().filter(|kp| kp.pubkey() != payer.pubkey()));
+    let blockhash = banks_client.get_latest_blockhash().await?;
+    let tx = Transaction::new_signed_with_payer(&[ix], Some(&payer.pubkey()), &keypairs, blockhash);
+    banks_client.process_transaction(tx).await
+}
+
+fn interface_accounts(mint: &Pubkey, owner: &Keypair, to: &Pubkey) -> Vec<AccountMeta> {
+    token_program::accounts::ITransfer {
+        owner: owner.pubkey(),
+        to: *to,
+        authority: owner.pubkey(),
+        mint: *mint,
+    }
+    .to_account_metas(None)
+}
+
+// Resolves the additional accounts for a transfer of `mint` the way interface callers do
+pub async fn preflight(
+    banks_client: &mut BanksClient,
+    payer: &Keypair,
+    mint: &Pubkey,
+    owner: &Keypair,
+    to: &Pubkey,
+    amount: u64,
+) -> PreflightPayload {
+    preflight_with_hints(banks_client, payer, mint, owner, to, amount, &[]).await
+}
+
+// Like `preflight`, passing `hints` as read-only remaining accounts
+pub async fn preflight_with_hints(
+    banks_client: &mut BanksClient,
+    payer: &Keypair,
+    mint: &Pubkey,
+    owner: &Keypair,
+    to: &Pubkey,
+    amount: u64,
+    hints: &[Pubkey],
+) -> PreflightPayload {
+    let hints: Vec<Pubkey> = hints.iter().copied().chain([EXTENSIONS_HINT]).collect();
+    let data = preflight_data(banks_client, payer, mint, owner, to, amount, &hints).await;
+    decode_payload(&data).unwrap()
+}
+
+// Raw return data of `preflight_transfer`, which only has extensions
+// when `hints` include `EXTENSIONS_HINT`
+pub async fn preflight_data(
+    banks_client: &mut BanksClient,
+    payer: &Keypair,
+    mint: &Pubkey,
+    owner: &Keypair,
+    to: &Pubkey,
+    amount: u64,
+    hints: &[Pubkey],
+) -> Vec<u8> {
+    let mut accounts = interface_accounts(mint, owner, to);
+    accounts.extend(
+        hints
+            .iter()
+            .map(|key| AccountMeta::new_readonly(*key, false)),
+    );
+    let ix = Instruction {
+        program_id: token_program::id(),
+        accounts,
+        data: token_program::instruction::PreflightTransfer { amount }.data(),
+    };
+    let mut keypairs = vec![payer];
+    if owner.pubkey() != payer.pubkey() {
+        keypairs.push(owner);
+    }
+    let blockhash = banks_client.get_latest_blockhash().await.unwrap();
+    let tx = Transaction::new_signed_with_payer(&[ix], Some(&payer.pubkey()), &keypairs, blockhash);
+    let simulation = banks_client.simulate_transaction(tx).await.unwrap();
+    simulation.result.unwrap().unwrap();
+    simulation
+        .simulation_details
+        .unwrap()
+        .return_data
+        .unwrap()
+        .data
+}
+
+// Preflights and sends a transfer of `amount` of `mint` from `owner` to `to`
+pub async fn transfer(
+    banks_client: &mut BanksClient,
+    payer: &Keypair,
+    mint: &Pubkey,
+    owner: &Keypair,
+    to: &Pubkey,
+    amount: u64,
+) -> Result<(), BanksClientError> {
+    let payload = preflight(banks_client, payer, mint, owner, to, amount).await;
+    transfer_with_payload(banks_client, payer, mint, owner, to, amount, &payload).await
+}
+
+// Sends a transfer with the additional accounts of `payload`
+pub async fn transfer_with_payload(
+    banks_client: &mut BanksClient,
+    payer: &Keypair,
+    mint: &Pubkey,
+    owner: &Keypair,
+    to: &Pubkey,
+    amount: u64,
+    payload: &PreflightPayload,
+) -> Result<(), BanksClientError> {
+    let mut accounts = interface_accounts(mint, owner, to);
+    accounts.extend(payload.accounts.iter().map(|acc| AccountMeta {
+        pubkey: acc.pubkey,
+        is_signer: acc.signer,
+        is_writable: acc.writable,
+    }));
+    let ix = Instruction {
+        program_id: token_program::id(),
+        accounts,
+        data: token_program::instruction::Transfer { amount }.data(),
+    };
+    process(banks_client, payer, &[owner], ix).await
+}
+
+pub async fn ledger(banks_client: &mut BanksClient) -> (SortedLedger, usize) {
+    ledger_at(banks_client, &ledger_key()).await
+}
+
+pub async fn ledger_at(banks_client: &mut BanksClient, key: &Pubkey) -> (SortedLedger, usize) {
+    let account = banks_client.get_account(*key).await.unwrap().unwrap();
+    assert_eq!(account.data[..8], SortedLedger::discriminator());
+    let header = *split_ledger(&account.data).unwrap().0;
+    (header, account.data.len())
+}
+
+// Asserts that the ledger at `key` holds one entry per owner, sorted by owner,
+// and that its balances add up to `total_supply`
+pub async fn assert_ledger_invariants(banks_client: &mut BanksClient, key: &Pubkey) {
+    let account = banks_client.get_account(*key).await.unwrap().unwrap();
+    assert_eq!(account.data[..8], SortedLedger::discriminator());
+    let (header, entries) = split_ledger(&account.data).unwrap();
+    assert!(account.data.len() >= ledger_size(entries.len()));
+    assert!(
+        entries.windows(2).all(|pair| pair[0].owner < pair[1].owner),
+        "ledger entries are not sorted"
+    );
+    let sum: u128 = entries.iter().map(|entry| entry.amount as u128).sum();
+    let withheld = withheld_fees(banks_client, key).await.unwrap_or(0);
+    assert_eq!(
+        sum + withheld as u128,
+        header.total_supply as u128,
+        "balances don't add up"
+    );
+}
+
+// Fees withheld from transfers in the ledger at `key`, if it charges any
+pub async fn withheld_fees(banks_client: &mut BanksClient, key: &Pubkey) -> Option<u64> {
+    let account = banks_client
+        .get_account(fee_collector_address(key).0)
+        .await
+        .unwrap()?;
+    let collector = FeeCollector::try_deserialize(&mut account.data.as_slice()).unwrap();
+    Some(collector.withheld)
+}
+
+// `owner`'s balance in the `setup` ledger, if they have an entry
+pub async fn balance(banks_client: &mut BanksClient, owner: &Pubkey) -> Option<u64> {
+    balance_in(banks_client, &ledger_key(), owner).await
+}
+
+// `owner`'s balance in the ledger at `key`, if they have an entry
+pub async fn balance_in(
+    banks_client: &mut BanksClient,
+    key: &Pubkey,
+    owner: &Pubkey,
+) -> Option<u64> {
+    let account = banks_client.get_account(*key).await.unwrap().unwrap();
+    let (_, entries) = split_ledger(&account.data).unwrap();
+    find_entry(entries, owner)
+        .ok()
+        .map(|index| entries[index].amount)
+}
+
+// Asserts that a transaction failed with `error`, a `TokenError` or Anchor `ErrorCode`
+pub fn assert_token_error(result: Result<(), BanksClientError>, error: impl Into<u32>) {
+    match result.unwrap_err() {
+        BanksClientError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(code),
+        )) => assert_eq!(code, error.into()),
+        err => panic!("unexpected error: {:?}", err),
+    }
+}
+
+// Sends `lamports` to `owner`'s balance address before its account exists
+pub async fn prefund_balance(
+    banks_client: &mut BanksClient,
+    payer: &Keypair,
+    mint: &Pubkey,
+    owner: &Pubkey,
+    lamports: u64,
+) {
+    let ix =
+        system_instruction::transfer(&payer.pubkey(), &balance_address(mint, owner).0, lamports);
+    process(banks_client, payer, &[], ix).await.unwrap();
+}
+
+// `owner`'s balance of a balance mint, if their balance account exists
+pub async fn balance_of(
+    banks_client: &mut BanksClient,
+    mint: &Pubkey,
+    owner: &Pubkey,
+) -> Option<u64> {
+    let account = banks_client
+        .get_account(balance_address(mint, owner).0)
+        .await
+        .unwrap()?;
+    Some(
+        Balance::try_deserialize(&mut account.data.as_slice())
+            .unwrap()
+            .amount,
+    )
+}
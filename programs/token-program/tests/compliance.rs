@@ -0,0 +1,257 @@
+// Anchor's derives use cfgs that newer compilers warn about
+#![allow(unknown_lints, unexpected_cfgs)]
+use anchor_lang::{AnchorDeserialize, AnchorSerialize, InstructionData, ToAccountMetas};
+use solana_program_test::{processor, BanksClient, BanksClientError, ProgramTest};
+use solana_sdk::{
+    account_info::{next_account_info, AccountInfo},
+    clock::Clock,
+    entrypoint::ProgramResult,
+    instruction::{AccountMeta, Instruction},
+    program::invoke_signed,
+    program_error::ProgramError,
+    pubkey::Pubkey,
+    rent::Rent,
+    signature::{Keypair, Signer},
+    system_instruction, system_program,
+    sysvar::Sysvar,
+};
+use token_program::{attestation_address, Attestation, TokenError, ATTESTATION_PREFIX};
+
+mod common;
+use common::*;
+
+// owner + expires_at
+const ATTESTATION_SIZE: usize = 32 + 8;
+
+fn attestation_program_id() -> Pubkey {
+    Pubkey::new_from_array([9; 32])
+}
+
+// A minimal compliance program. Its only instruction takes an `expires_at`
+// timestamp and attests the owner until then, creating the attestation on
+// first use. Accounts: payer, owner, attestation, system program.
+fn process_attestation(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    data: &[u8],
+) -> ProgramResult {
+    let accounts = &mut accounts.iter();
+    let payer = next_account_info(accounts)?;
+    let owner = next_account_info(accounts)?;
+    let attestation = next_account_info(accounts)?;
+    let system_program = next_account_info(accounts)?;
+    let expires_at = i64::try_from_slice(data).map_err(|_| ProgramError::InvalidInstructionData)?;
+
+    let (address, bump) = attestation_address(program_id, owner.key);
+    if *attestation.key != address {
+        return Err(ProgramError::InvalidSeeds);
+    }
+    if attestation.data_is_empty() {
+        invoke_signed(
+            &system_instruction::create_account(
+                payer.key,
+                &address,
+                Rent::get()?.minimum_balance(ATTESTATION_SIZE),
+                ATTESTATION_SIZE as u64,
+                program_id,
+            ),
+            &[payer.clone(), attestation.clone(), system_program.clone()],
+            &[&[ATTESTATION_PREFIX.as_bytes(), owner.key.as_ref(), &[bump]]],
+        )?;
+    }
+    Attestation {
+        owner: *owner.key,
+        expires_at,
+    }
+    .serialize(&mut &mut attestation.try_borrow_mut_data()?[..])
+    .map_err(|_| ProgramError::AccountDataTooSmall)
+}
+
+async fn attest(
+    banks_client: &mut BanksClient,
+    payer: &Keypair,
+    owner: &Pubkey,
+    expires_at: i64,
+) -> Result<(), BanksClientError> {
+    let ix = Instruction {
+        program_id: attestation_program_id(),
+        accounts: vec![
+            AccountMeta::new(payer.pubkey(), true),
+            AccountMeta::new_readonly(*owner, false),
+            AccountMeta::new(
+                attestation_address(&attestation_program_id(), owner).0,
+                false,
+            ),
+            AccountMeta::new_readonly(system_program::ID, false),
+        ],
+        data: expires_at.try_to_vec().unwrap(),
+    };
+    process(banks_client, payer, &[], ix).await
+}
+
+fn set_compliance_program(
+    mint_authority: &Pubkey,
+    compliance_program: Option<Pubkey>,
+) -> Instruction {
+    Instruction {
+        program_id: token_program::id(),
+        accounts: token_program::accounts::SetComplianceProgram {
+            mint_authority: *mint_authority,
+            ledger: ledger_key(),
+            mint: mint_key(),
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: token_program::instruction::SetComplianceProgram { compliance_program }.data(),
+    }
+}
+
+#[tokio::test]
+async fn gated_transfers_need_unexpired_attestations() {
+    let mut program_test = ProgramTest::default();
+    program_test.add_program(
+        "attestation",
+        attestation_program_id(),
+        processor!(process_attestation),
+    );
+    let mut context = setup_with(program_test, 1_000).await;
+    let (banks_client, payer) = (&mut context.banks_client, &context.payer);
+    let now = banks_client
+        .get_sysvar::<Clock>()
+        .await
+        .unwrap()
+        .unix_timestamp;
+    let holder = Pubkey::new_unique();
+
+    let outsider = Keypair::new();
+    let result = process(
+        banks_client,
+        payer,
+        &[&outsider],
+        set_compliance_program(&outsider.pubkey(), Some(attestation_program_id())),
+    )
+    .await;
+    assert_token_error(result, TokenError::Unauthorized);
+    process(
+        banks_client,
+        payer,
+        &[],
+        set_compliance_program(&payer.pubkey(), Some(attestation_program_id())),
+    )
+    .await
+    .unwrap();
+
+    // Preflight asks for both attestations, with or without the ledger hint
+    for hints in [vec![], vec![ledger_key()]] {
+        let payload =
+            preflight_with_hints(banks_client, payer, &mint_key(), payer, &holder, 10, &hints)
+                .await;
+        for owner in [payer.pubkey(), holder] {
+            let attestation = attestation_address(&attestation_program_id(), &owner).0;
+            assert!(payload
+                .accounts
+                .iter()
+                .any(|acc| acc.pubkey == attestation && !acc.writable));
+        }
+    }
+
+    let result = transfer(banks_client, payer, &mint_key(), payer, &holder, 10).await;
+    assert_token_error(result, TokenError::NotAttested);
+    attest(banks_client, payer, &payer.pubkey(), now + 3_600)
+        .await
+        .unwrap();
+    let result = transfer(banks_client, payer, &mint_key(), payer, &holder, 11).await;
+    assert_token_error(result, TokenError::NotAttested);
+    attest(banks_client, payer, &holder, now - 1).await.unwrap();
+    let result = transfer(banks_client, payer, &mint_key(), payer, &holder, 12).await;
+    assert_token_error(result, TokenError::AttestationExpired);
+
+    attest(banks_client, payer, &holder, now + 3_600)
+        .await
+        .unwrap();
+    transfer(banks_client, payer, &mint_key(), payer, &holder, 13)
+        .await
+        .unwrap();
+    assert_eq!(balance(banks_client, &holder).await, Some(13));
+
+    // Lifting the requirement drops the attestations again
+    process(
+        banks_client,
+        payer,
+        &[],
+        set_compliance_program(&payer.pubkey(), None),
+    )
+    .await
+    .unwrap();
+    transfer(banks_client, payer, &mint_key(), payer, &holder, 14)
+        .await
+        .unwrap();
+    assert_eq!(balance(banks_client, &holder).await, Some(27));
+}
+
+#[tokio::test]
+async fn attestations_from_other_programs_are_rejected() {
+    let mut program_test = ProgramTest::default();
+    program_test.add_program(
+        "attestation",
+        attestation_program_id(),
+        processor!(process_attestation),
+    );
+    let mut context = setup_with(program_test, 1_000).await;
+    let (banks_client, payer) = (&mut context.banks_client, &context.payer);
+    let now = banks_client
+        .get_sysvar::<Clock>()
+        .await
+        .unwrap()
+        .unix_timestamp;
+    let holder = Pubkey::new_unique();
+    for owner in [payer.pubkey(), holder] {
+        attest(banks_client, payer, &owner, now + 3_600)
+            .await
+            .unwrap();
+    }
+
+    // Attestations from another program don't count
+    process(
+        banks_client,
+        payer,
+        &[],
+        set_compliance_program(&payer.pubkey(), Some(Pubkey::new_unique())),
+    )
+    .await
+    .unwrap();
+    let result = transfer(banks_client, payer, &mint_key(), payer, &holder, 10).await;
+    assert_token_error(result, TokenError::NotAttested);
+    assert_eq!(balance(banks_client, &holder).await, None);
+}
+
+#[tokio::test]
+async fn lifting_the_requirement_drops_attestations() {
+    let mut context = setup(1_000).await;
+    let (banks_client, payer) = (&mut context.banks_client, &context.payer);
+    let holder = Pubkey::new_unique();
+    let compliance_program = Pubkey::new_unique();
+
+    process(
+        banks_client,
+        payer,
+        &[],
+        set_compliance_program(&payer.pubkey(), Some(compliance_program)),
+    )
+    .await
+    .unwrap();
+    process(
+        banks_client,
+        payer,
+        &[],
+        set_compliance_program(&payer.pubkey(), None),
+    )
+    .await
+    .unwrap();
+
+    let payload = preflight(banks_client, payer, &mint_key(), payer, &holder, 1).await;
+    for owner in [payer.pubkey(), holder] {
+        let attestation = attestation_address(&compliance_program, &owner).0;
+        assert!(payload.accounts.iter().all(|acc| acc.pubkey != attestation));
+    }
+}
@@ -0,0 +1,54 @@
+// Anchor's derives use cfgs that newer compilers warn about
+#![allow(unknown_lints, unexpected_cfgs)]
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::entrypoint::ProgramResult;
+use solana_program_test::ProgramTest;
+use token_interface::test_vectors::TRANSFER_DISCRIMINATOR;
+use token_interface_conformance::{anchor_processor, Conformance, CHECKS};
+
+mod common;
+use common::*;
+
+// Takes transfers whose first discriminator byte is wrong, like a program
+// that only matches part of the discriminator would
+fn lenient_entry<'info>(
+    program_id: &Pubkey,
+    accounts: &'info [AccountInfo<'info>],
+    data: &[u8],
+) -> ProgramResult {
+    let mut data = data.to_vec();
+    if data.len() >= 8 && data[1..8] == TRANSFER_DISCRIMINATOR[1..] {
+        data[0] = TRANSFER_DISCRIMINATOR[0];
+    }
+    token_program::entry(program_id, accounts, &data)
+}
+
+#[tokio::test]
+async fn token_program_conforms() {
+    let mut context = setup(1_000).await;
+
+    Conformance::new(token_program::id(), mint_key())
+        .run(&mut context.banks_client, &context.payer)
+        .await
+        .assert_passed();
+}
+
+#[tokio::test]
+async fn loose_discriminators_fail_conformance() {
+    let mut program_test = ProgramTest::default();
+    program_test.add_program(
+        "token_program",
+        token_program::id(),
+        anchor_processor!(lenient_entry),
+    );
+    let mut context = program_test.start_with_context().await;
+    initialize_mint(&mut context.banks_client, &context.payer, &mint_id(), 1_000)
+        .await
+        .unwrap();
+
+    let report = Conformance::new(token_program::id(), mint_key())
+        .run(&mut context.banks_client, &context.payer)
+        .await;
+    let failures: Vec<&str> = report.failures().map(|check| check.name).collect();
+    assert_eq!(failures, vec![CHECKS[0]], "{}", report);
+}
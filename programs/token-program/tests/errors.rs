@@ -0,0 +1,150 @@
+// Anchor's derives use cfgs that newer compilers warn about
+#![allow(unknown_lints, unexpected_cfgs)]
+use anchor_lang::{InstructionData, ToAccountMetas};
+use solana_program_test::{BanksClient, BanksClientError};
+use solana_sdk::{
+    instruction::{AccountMeta, Instruction},
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+};
+use token_program::{balance_address, TokenError};
+
+mod common;
+use common::*;
+
+// Sends the transfer `owner` would preflight, but signed by `authority` instead
+async fn transfer_as(
+    banks_client: &mut BanksClient,
+    payer: &Keypair,
+    mint: &Pubkey,
+    owner: &Keypair,
+    authority: &Keypair,
+    to: &Pubkey,
+    amount: u64,
+) -> Result<(), BanksClientError> {
+    let payload = preflight(banks_client, payer, mint, owner, to, amount).await;
+    let mut accounts = token_program::accounts::ITransfer {
+        owner: owner.pubkey(),
+        to: *to,
+        authority: authority.pubkey(),
+        mint: *mint,
+    }
+    .to_account_metas(None);
+    accounts.extend(payload.accounts.iter().map(|acc| AccountMeta {
+        pubkey: acc.pubkey,
+        is_signer: acc.signer,
+        is_writable: acc.writable,
+    }));
+    let ix = Instruction {
+        program_id: token_program::id(),
+        accounts,
+        data: token_program::instruction::Transfer { amount }.data(),
+    };
+    process(banks_client, payer, &[authority], ix).await
+}
+
+#[tokio::test]
+async fn transfers_from_unknown_owners_fail() {
+    let mut context = setup(1_000).await;
+    let (banks_client, payer) = (&mut context.banks_client, &context.payer);
+    let balance_mint = initialize_balance_mint(banks_client, payer, 100).await;
+    let stranger = Keypair::new();
+
+    let hints = [
+        ledger_key(),
+        balance_address(&balance_mint, &stranger.pubkey()).0,
+    ];
+    for mint in [mint_key(), balance_mint] {
+        // Not even a zero amount is previewed
+        let payload = preflight_with_hints(
+            banks_client,
+            payer,
+            &mint,
+            &stranger,
+            &payer.pubkey(),
+            0,
+            &hints,
+        )
+        .await;
+        assert!(payload.preview().is_none());
+        let result = transfer(banks_client, payer, &mint, &stranger, &payer.pubkey(), 0).await;
+        assert_token_error(result, TokenError::UnknownHolder);
+        let result = transfer(banks_client, payer, &mint, &stranger, &stranger.pubkey(), 1).await;
+        assert_token_error(result, TokenError::UnknownHolder);
+    }
+    assert_eq!(balance(banks_client, &stranger.pubkey()).await, None);
+    assert_ledger_invariants(banks_client, &ledger_key()).await;
+}
+
+#[tokio::test]
+async fn transfers_signed_by_someone_else_fail() {
+    let mut context = setup(1_000).await;
+    let (banks_client, payer) = (&mut context.banks_client, &context.payer);
+    let balance_mint = initialize_balance_mint(banks_client, payer, 100).await;
+    let intruder = Keypair::new();
+
+    for mint in [mint_key(), balance_mint] {
+        let result = transfer_as(
+            banks_client,
+            payer,
+            &mint,
+            payer,
+            &intruder,
+            &intruder.pubkey(),
+            1,
+        )
+        .await;
+        assert_token_error(result, TokenError::Unauthorized);
+    }
+    assert_eq!(balance(banks_client, &payer.pubkey()).await, Some(1_000));
+    assert_eq!(
+        balance_of(banks_client, &balance_mint, &payer.pubkey()).await,
+        Some(100)
+    );
+}
+
+#[tokio::test]
+async fn self_transfers_keep_balances() {
+    let mut context = setup(1_000).await;
+    let (banks_client, payer) = (&mut context.banks_client, &context.payer);
+    let balance_mint = initialize_balance_mint(banks_client, payer, 100).await;
+
+    transfer(
+        banks_client,
+        payer,
+        &mint_key(),
+        payer,
+        &payer.pubkey(),
+        400,
+    )
+    .await
+    .unwrap();
+    transfer(
+        banks_client,
+        payer,
+        &balance_mint,
+        payer,
+        &payer.pubkey(),
+        40,
+    )
+    .await
+    .unwrap();
+    assert_eq!(balance(banks_client, &payer.pubkey()).await, Some(1_000));
+    assert_eq!(
+        balance_of(banks_client, &balance_mint, &payer.pubkey()).await,
+        Some(100)
+    );
+
+    // Moving more than the balance to oneself still fails
+    let result = transfer(
+        banks_client,
+        payer,
+        &mint_key(),
+        payer,
+        &payer.pubkey(),
+        1_001,
+    )
+    .await;
+    assert_token_error(result, TokenError::InsufficientFunds);
+    assert_ledger_invariants(banks_client, &ledger_key()).await;
+}
@@ -0,0 +1,220 @@
+// Anchor's derives use cfgs that newer compilers warn about
+#![allow(unknown_lints, unexpected_cfgs)]
+use anchor_lang::{error::ErrorCode, system_program, InstructionData, ToAccountMetas};
+use solana_sdk::{
+    instruction::Instruction,
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+};
+use token_interface::PreflightPayload;
+use token_program::{fee_collector_address, TokenError};
+
+mod common;
+use common::*;
+
+fn fee_collector_key() -> Pubkey {
+    fee_collector_address(&ledger_key()).0
+}
+
+fn initialize_transfer_fee(mint_authority: &Pubkey, fee_bps: u16, max_fee: u64) -> Instruction {
+    Instruction {
+        program_id: token_program::id(),
+        accounts: token_program::accounts::InitializeTransferFee {
+            mint_authority: *mint_authority,
+            ledger: ledger_key(),
+            fee_collector: fee_collector_key(),
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: token_program::instruction::InitializeTransferFee { fee_bps, max_fee }.data(),
+    }
+}
+
+fn set_transfer_fee(mint_authority: &Pubkey, fee_bps: u16, max_fee: u64) -> Instruction {
+    Instruction {
+        program_id: token_program::id(),
+        accounts: token_program::accounts::SetTransferFee {
+            mint_authority: *mint_authority,
+            ledger: ledger_key(),
+            fee_collector: fee_collector_key(),
+        }
+        .to_account_metas(None),
+        data: token_program::instruction::SetTransferFee { fee_bps, max_fee }.data(),
+    }
+}
+
+fn withdraw_fees(mint_authority: &Pubkey, to: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: token_program::id(),
+        accounts: token_program::accounts::WithdrawFees {
+            mint_authority: *mint_authority,
+            ledger: ledger_key(),
+            fee_collector: fee_collector_key(),
+            to: *to,
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: token_program::instruction::WithdrawFees {}.data(),
+    }
+}
+
+fn requests_fee_collector(payload: &PreflightPayload) -> bool {
+    payload
+        .accounts
+        .iter()
+        .any(|acc| acc.pubkey == fee_collector_key() && acc.writable)
+}
+
+#[tokio::test]
+async fn transfer_fees_are_withheld_until_withdrawn() {
+    let mut context = setup(1_000).await;
+    let (banks_client, payer) = (&mut context.banks_client, &context.payer);
+    let holder = Pubkey::new_unique();
+
+    let result = process(
+        banks_client,
+        payer,
+        &[],
+        initialize_transfer_fee(&payer.pubkey(), 10_001, 5),
+    )
+    .await;
+    assert_token_error(result, TokenError::InvalidFee);
+    process(
+        banks_client,
+        payer,
+        &[],
+        initialize_transfer_fee(&payer.pubkey(), 100, 5),
+    )
+    .await
+    .unwrap();
+    let outsider = Keypair::new();
+    let result = process(
+        banks_client,
+        payer,
+        &[&outsider],
+        set_transfer_fee(&outsider.pubkey(), 0, 0),
+    )
+    .await;
+    assert_token_error(result, TokenError::Unauthorized);
+
+    // 1% of each transfer, at most 5
+    transfer(banks_client, payer, &mint_key(), payer, &holder, 100)
+        .await
+        .unwrap();
+    assert_eq!(balance(banks_client, &holder).await, Some(99));
+    transfer(banks_client, payer, &mint_key(), payer, &holder, 800)
+        .await
+        .unwrap();
+    assert_eq!(balance(banks_client, &holder).await, Some(894));
+    assert_eq!(balance(banks_client, &payer.pubkey()).await, Some(100));
+    assert_eq!(withheld_fees(banks_client, &ledger_key()).await, Some(6));
+
+    // Transfers to oneself don't pay fees
+    transfer(banks_client, payer, &mint_key(), payer, &payer.pubkey(), 50)
+        .await
+        .unwrap();
+    assert_eq!(balance(banks_client, &payer.pubkey()).await, Some(100));
+    assert_eq!(withheld_fees(banks_client, &ledger_key()).await, Some(6));
+    assert_ledger_invariants(banks_client, &ledger_key()).await;
+
+    let treasury = Pubkey::new_unique();
+    let result = process(
+        banks_client,
+        payer,
+        &[&outsider],
+        withdraw_fees(&outsider.pubkey(), &outsider.pubkey()),
+    )
+    .await;
+    assert_token_error(result, TokenError::Unauthorized);
+    process(
+        banks_client,
+        payer,
+        &[],
+        withdraw_fees(&payer.pubkey(), &treasury),
+    )
+    .await
+    .unwrap();
+    assert_eq!(balance(banks_client, &treasury).await, Some(6));
+    assert_eq!(withheld_fees(banks_client, &ledger_key()).await, Some(0));
+    assert_ledger_invariants(banks_client, &ledger_key()).await;
+}
+
+#[tokio::test]
+async fn preflight_requests_the_fee_collector() {
+    let mut context = setup(1_000).await;
+    let (banks_client, payer) = (&mut context.banks_client, &context.payer);
+    let holder = Pubkey::new_unique();
+    let hints = [ledger_key()];
+
+    // Without the ledger, preflight can't tell that the mint charges no fees
+    let payload = preflight(banks_client, payer, &mint_key(), payer, &holder, 100).await;
+    assert!(requests_fee_collector(&payload));
+    let hinted = preflight_with_hints(
+        banks_client,
+        payer,
+        &mint_key(),
+        payer,
+        &holder,
+        100,
+        &hints,
+    )
+    .await;
+    assert!(!requests_fee_collector(&hinted));
+
+    let result = process(
+        banks_client,
+        payer,
+        &[],
+        set_transfer_fee(&payer.pubkey(), 250, 1_000),
+    )
+    .await;
+    assert_token_error(result, ErrorCode::AccountNotInitialized);
+    process(
+        banks_client,
+        payer,
+        &[],
+        initialize_transfer_fee(&payer.pubkey(), 250, 1_000),
+    )
+    .await
+    .unwrap();
+    let hinted = preflight_with_hints(
+        banks_client,
+        payer,
+        &mint_key(),
+        payer,
+        &holder,
+        100,
+        &hints,
+    )
+    .await;
+    assert!(requests_fee_collector(&hinted));
+    let preview = hinted.preview().unwrap();
+    assert_eq!(preview.fee, 2);
+    assert_eq!(preview.delta_for(&mint_key(), &holder), 98);
+
+    // Setting the fee to 0 bps stops charging it
+    process(
+        banks_client,
+        payer,
+        &[],
+        set_transfer_fee(&payer.pubkey(), 0, 0),
+    )
+    .await
+    .unwrap();
+    let hinted = preflight_with_hints(
+        banks_client,
+        payer,
+        &mint_key(),
+        payer,
+        &holder,
+        100,
+        &hints,
+    )
+    .await;
+    assert!(!requests_fee_collector(&hinted));
+    transfer(banks_client, payer, &mint_key(), payer, &holder, 100)
+        .await
+        .unwrap();
+    assert_eq!(balance(banks_client, &holder).await, Some(100));
+    assert_eq!(withheld_fees(banks_client, &ledger_key()).await, Some(0));
+}
@@ -0,0 +1,385 @@
+// Anchor's derives use cfgs that newer compilers warn about
+#![allow(unknown_lints, unexpected_cfgs)]
+use anchor_lang::{InstructionData, ToAccountMetas};
+use solana_sdk::{
+    instruction::Instruction,
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+};
+use token_program::{balance_address, TokenError};
+
+mod common;
+use common::*;
+
+fn freeze_account(freeze_authority: &Pubkey, owner: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: token_program::id(),
+        accounts: token_program::accounts::FreezeAccount {
+            freeze_authority: *freeze_authority,
+            ledger: ledger_key(),
+            owner: *owner,
+        }
+        .to_account_metas(None),
+        data: token_program::instruction::FreezeAccount {}.data(),
+    }
+}
+
+fn thaw_account(freeze_authority: &Pubkey, owner: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: token_program::id(),
+        accounts: token_program::accounts::FreezeAccount {
+            freeze_authority: *freeze_authority,
+            ledger: ledger_key(),
+            owner: *owner,
+        }
+        .to_account_metas(None),
+        data: token_program::instruction::ThawAccount {}.data(),
+    }
+}
+
+fn set_freeze_authority(freeze_authority: &Pubkey, new_authority: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: token_program::id(),
+        accounts: token_program::accounts::SetFreezeAuthority {
+            freeze_authority: *freeze_authority,
+            ledger: ledger_key(),
+        }
+        .to_account_metas(None),
+        data: token_program::instruction::SetFreezeAuthority {
+            freeze_authority: *new_authority,
+        }
+        .data(),
+    }
+}
+
+fn freeze_balance(freeze_authority: &Pubkey, mint: &Pubkey, owner: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: token_program::id(),
+        accounts: token_program::accounts::FreezeBalance {
+            freeze_authority: *freeze_authority,
+            mint: *mint,
+            owner: *owner,
+            balance: balance_address(mint, owner).0,
+        }
+        .to_account_metas(None),
+        data: token_program::instruction::FreezeBalance {}.data(),
+    }
+}
+
+fn thaw_balance(freeze_authority: &Pubkey, mint: &Pubkey, owner: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: token_program::id(),
+        accounts: token_program::accounts::FreezeBalance {
+            freeze_authority: *freeze_authority,
+            mint: *mint,
+            owner: *owner,
+            balance: balance_address(mint, owner).0,
+        }
+        .to_account_metas(None),
+        data: token_program::instruction::ThawBalance {}.data(),
+    }
+}
+
+fn set_balance_freeze_authority(
+    freeze_authority: &Pubkey,
+    mint: &Pubkey,
+    new_authority: &Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: token_program::id(),
+        accounts: token_program::accounts::SetBalanceFreezeAuthority {
+            freeze_authority: *freeze_authority,
+            mint: *mint,
+        }
+        .to_account_metas(None),
+        data: token_program::instruction::SetBalanceFreezeAuthority {
+            freeze_authority: *new_authority,
+        }
+        .data(),
+    }
+}
+
+#[tokio::test]
+async fn frozen_balances_cannot_move() {
+    let mut context = setup(1_000).await;
+    let (banks_client, payer) = (&mut context.banks_client, &context.payer);
+    let holder = Keypair::new();
+    transfer(
+        banks_client,
+        payer,
+        &mint_key(),
+        payer,
+        &holder.pubkey(),
+        10,
+    )
+    .await
+    .unwrap();
+
+    let result = process(
+        banks_client,
+        payer,
+        &[&holder],
+        freeze_account(&holder.pubkey(), &holder.pubkey()),
+    )
+    .await;
+    assert_token_error(result, TokenError::Unauthorized);
+    let result = process(
+        banks_client,
+        payer,
+        &[],
+        freeze_account(&payer.pubkey(), &Pubkey::new_unique()),
+    )
+    .await;
+    assert_token_error(result, TokenError::UnknownHolder);
+
+    process(
+        banks_client,
+        payer,
+        &[],
+        freeze_account(&payer.pubkey(), &holder.pubkey()),
+    )
+    .await
+    .unwrap();
+    let result = transfer(
+        banks_client,
+        payer,
+        &mint_key(),
+        &holder,
+        &payer.pubkey(),
+        1,
+    )
+    .await;
+    assert_token_error(result, TokenError::AccountFrozen);
+    let result = transfer(banks_client, payer, &mint_key(), payer, &holder.pubkey(), 1).await;
+    assert_token_error(result, TokenError::AccountFrozen);
+
+    // Preflight doesn't preview transfers that will fail
+    let payload = preflight_with_hints(
+        banks_client,
+        payer,
+        &mint_key(),
+        &holder,
+        &payer.pubkey(),
+        1,
+        &[ledger_key()],
+    )
+    .await;
+    assert!(payload.preview().is_none());
+
+    process(
+        banks_client,
+        payer,
+        &[],
+        thaw_account(&payer.pubkey(), &holder.pubkey()),
+    )
+    .await
+    .unwrap();
+    transfer(
+        banks_client,
+        payer,
+        &mint_key(),
+        &holder,
+        &payer.pubkey(),
+        1,
+    )
+    .await
+    .unwrap();
+    transfer(banks_client, payer, &mint_key(), payer, &holder.pubkey(), 1)
+        .await
+        .unwrap();
+    assert_eq!(balance(banks_client, &holder.pubkey()).await, Some(10));
+}
+
+#[tokio::test]
+async fn freeze_authority_can_be_handed_over() {
+    let mut context = setup(1_000).await;
+    let (banks_client, payer) = (&mut context.banks_client, &context.payer);
+    let freezer = Keypair::new();
+    let holder = Pubkey::new_unique();
+    transfer(banks_client, payer, &mint_key(), payer, &holder, 10)
+        .await
+        .unwrap();
+
+    let result = process(
+        banks_client,
+        payer,
+        &[&freezer],
+        set_freeze_authority(&freezer.pubkey(), &freezer.pubkey()),
+    )
+    .await;
+    assert_token_error(result, TokenError::Unauthorized);
+    process(
+        banks_client,
+        payer,
+        &[],
+        set_freeze_authority(&payer.pubkey(), &freezer.pubkey()),
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        { ledger(banks_client).await.0.freeze_authority },
+        freezer.pubkey()
+    );
+
+    let result = process(
+        banks_client,
+        payer,
+        &[],
+        freeze_account(&payer.pubkey(), &holder),
+    )
+    .await;
+    assert_token_error(result, TokenError::Unauthorized);
+    process(
+        banks_client,
+        payer,
+        &[&freezer],
+        freeze_account(&freezer.pubkey(), &holder),
+    )
+    .await
+    .unwrap();
+    let result = transfer(banks_client, payer, &mint_key(), payer, &holder, 1).await;
+    assert_token_error(result, TokenError::AccountFrozen);
+}
+
+#[tokio::test]
+async fn balance_mints_freeze_balances() {
+    let mut context = setup(1_000).await;
+    let (banks_client, payer) = (&mut context.banks_client, &context.payer);
+    let mint = initialize_balance_mint(banks_client, payer, 100).await;
+    let holder = Keypair::new();
+    transfer(banks_client, payer, &mint, payer, &holder.pubkey(), 10)
+        .await
+        .unwrap();
+
+    let result = process(
+        banks_client,
+        payer,
+        &[&holder],
+        freeze_balance(&holder.pubkey(), &mint, &holder.pubkey()),
+    )
+    .await;
+    assert_token_error(result, TokenError::Unauthorized);
+    let result = process(
+        banks_client,
+        payer,
+        &[],
+        freeze_balance(&payer.pubkey(), &mint, &Pubkey::new_unique()),
+    )
+    .await;
+    assert_token_error(result, TokenError::UnknownHolder);
+
+    process(
+        banks_client,
+        payer,
+        &[],
+        freeze_balance(&payer.pubkey(), &mint, &holder.pubkey()),
+    )
+    .await
+    .unwrap();
+    let result = transfer(banks_client, payer, &mint, &holder, &payer.pubkey(), 1).await;
+    assert_token_error(result, TokenError::AccountFrozen);
+    let result = transfer(banks_client, payer, &mint, payer, &holder.pubkey(), 1).await;
+    assert_token_error(result, TokenError::AccountFrozen);
+
+    // Preflight doesn't preview transfers from or to the frozen balance
+    let hints = [
+        balance_address(&mint, &payer.pubkey()).0,
+        balance_address(&mint, &holder.pubkey()).0,
+    ];
+    let payload = preflight_with_hints(
+        banks_client,
+        payer,
+        &mint,
+        payer,
+        &holder.pubkey(),
+        1,
+        &hints,
+    )
+    .await;
+    assert!(payload.preview().is_none());
+    let payload = preflight_with_hints(
+        banks_client,
+        payer,
+        &mint,
+        &holder,
+        &payer.pubkey(),
+        1,
+        &hints,
+    )
+    .await;
+    assert!(payload.preview().is_none());
+
+    process(
+        banks_client,
+        payer,
+        &[],
+        thaw_balance(&payer.pubkey(), &mint, &holder.pubkey()),
+    )
+    .await
+    .unwrap();
+    let payload = preflight_with_hints(
+        banks_client,
+        payer,
+        &mint,
+        payer,
+        &holder.pubkey(),
+        3,
+        &hints,
+    )
+    .await;
+    assert!(payload.preview().is_some());
+    transfer(banks_client, payer, &mint, &holder, &payer.pubkey(), 2)
+        .await
+        .unwrap();
+    transfer(banks_client, payer, &mint, payer, &holder.pubkey(), 3)
+        .await
+        .unwrap();
+    assert_eq!(
+        balance_of(banks_client, &mint, &holder.pubkey()).await,
+        Some(11)
+    );
+}
+
+#[tokio::test]
+async fn balance_freeze_authority_can_be_handed_over() {
+    let mut context = setup(1_000).await;
+    let (banks_client, payer) = (&mut context.banks_client, &context.payer);
+    let mint = initialize_balance_mint(banks_client, payer, 100).await;
+    let freezer = Keypair::new();
+
+    let result = process(
+        banks_client,
+        payer,
+        &[&freezer],
+        set_balance_freeze_authority(&freezer.pubkey(), &mint, &freezer.pubkey()),
+    )
+    .await;
+    assert_token_error(result, TokenError::Unauthorized);
+    process(
+        banks_client,
+        payer,
+        &[],
+        set_balance_freeze_authority(&payer.pubkey(), &mint, &freezer.pubkey()),
+    )
+    .await
+    .unwrap();
+
+    let result = process(
+        banks_client,
+        payer,
+        &[],
+        freeze_balance(&payer.pubkey(), &mint, &payer.pubkey()),
+    )
+    .await;
+    assert_token_error(result, TokenError::Unauthorized);
+    process(
+        banks_client,
+        payer,
+        &[&freezer],
+        freeze_balance(&freezer.pubkey(), &mint, &payer.pubkey()),
+    )
+    .await
+    .unwrap();
+    let result = transfer(banks_client, payer, &mint, payer, &freezer.pubkey(), 1).await;
+    assert_token_error(result, TokenError::AccountFrozen);
+}
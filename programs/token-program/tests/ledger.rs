@@ -0,0 +1,482 @@
+// Anchor's derives use cfgs that newer compilers warn about
+#![allow(unknown_lints, unexpected_cfgs)]
+use anchor_lang::{error::ErrorCode, AnchorSerialize, InstructionData, ToAccountMetas};
+use solana_program_test::ProgramTest;
+use solana_sdk::{
+    account::Account,
+    instruction::Instruction,
+    native_token::LAMPORTS_PER_SOL,
+    pubkey::Pubkey,
+    rent::Rent,
+    signature::{Keypair, Signer},
+    system_program,
+};
+use token_interface_conformance::anchor_processor;
+use token_program::{
+    ledger_address, ledger_size, mint_address, routing_address, split_ledger, LedgerAccount,
+    LedgerAccounts, LegacyLedger, TokenError, DEFAULT_CAPACITY, LEDGER_ENTRY_SIZE,
+};
+
+mod common;
+use common::*;
+
+fn set_max_ledger_size(authority: &Pubkey, max_size: u32) -> Instruction {
+    Instruction {
+        program_id: token_program::id(),
+        accounts: token_program::accounts::SetMaxLedgerSize {
+            authority: *authority,
+            ledger: ledger_key(),
+        }
+        .to_account_metas(None),
+        data: token_program::instruction::SetMaxLedgerSize { max_size }.data(),
+    }
+}
+
+fn migrate_ledger(authority: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: token_program::id(),
+        accounts: token_program::accounts::MigrateLedger {
+            authority: *authority,
+            ledger: ledger_address(&token_program::id()).0,
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: token_program::instruction::MigrateLedger {}.data(),
+    }
+}
+
+// A ledger in the baseline Borsh layout, with room for `spare` more holders
+fn legacy_ledger(authority: &Pubkey, holders: &[(Pubkey, u64)], spare: usize) -> Account {
+    let accounts: LedgerAccounts = holders
+        .iter()
+        .map(|(owner, amount)| {
+            (
+                *owner,
+                LedgerAccount {
+                    amount: *amount,
+                    is_frozen: false,
+                },
+            )
+        })
+        .collect();
+    let opaque_accounts = accounts.try_to_vec().unwrap();
+    let total_supply: u64 = holders.iter().map(|(_, amount)| amount).sum();
+
+    // Anchor's discriminator for the baseline `Ledger` account
+    let discriminator = [43, 41, 21, 213, 180, 176, 95, 32];
+    assert_eq!(LegacyLedger::discriminator(), discriminator);
+
+    // total_supply, mint_authority, freeze_authority, opaque_accounts
+    let mut data = discriminator.to_vec();
+    data.extend_from_slice(&total_supply.to_le_bytes());
+    data.extend_from_slice(authority.as_ref());
+    data.extend_from_slice(authority.as_ref());
+    data.extend_from_slice(&(opaque_accounts.len() as u32).to_le_bytes());
+    data.extend_from_slice(&opaque_accounts);
+    data.resize(data.len() + spare * LEDGER_ENTRY_SIZE, 0);
+    Account {
+        lamports: Rent::default().minimum_balance(data.len()),
+        data,
+        owner: token_program::id(),
+        executable: false,
+        rent_epoch: 0,
+    }
+}
+
+fn close_account(owner: &Pubkey, destination: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: token_program::id(),
+        accounts: token_program::accounts::CloseAccount {
+            owner: *owner,
+            ledger: ledger_key(),
+            destination: *destination,
+        }
+        .to_account_metas(None),
+        data: token_program::instruction::CloseAccount {}.data(),
+    }
+}
+
+#[tokio::test]
+async fn ledger_grows_for_hundreds_of_holders() {
+    let mut context = setup(1_000).await;
+    let (banks_client, payer) = (&mut context.banks_client, &context.payer);
+
+    for _ in 0..300 {
+        transfer(
+            banks_client,
+            payer,
+            &mint_key(),
+            payer,
+            &Pubkey::new_unique(),
+            1,
+        )
+        .await
+        .unwrap();
+    }
+
+    let (header, len) = ledger(banks_client).await;
+    assert_eq!(len, ledger_size(301));
+    assert_eq!({ header.len }, 301);
+    assert_eq!(balance(banks_client, &payer.pubkey()).await, Some(700));
+
+    let data = banks_client
+        .get_account(ledger_key())
+        .await
+        .unwrap()
+        .unwrap()
+        .data;
+    let (_, entries) = split_ledger(&data).unwrap();
+    assert!(entries.windows(2).all(|pair| pair[0].owner < pair[1].owner));
+    assert_ledger_invariants(banks_client, &ledger_key()).await;
+}
+
+#[tokio::test]
+async fn ledger_stops_growing_at_max_size() {
+    let mut context = setup(1_000).await;
+    let (banks_client, payer) = (&mut context.banks_client, &context.payer);
+
+    // Can't go below the current size
+    let result = process(
+        banks_client,
+        payer,
+        &[],
+        set_max_ledger_size(&payer.pubkey(), ledger_size(DEFAULT_CAPACITY) as u32 - 1),
+    )
+    .await;
+    assert_token_error(result, TokenError::InvalidLedgerSize);
+
+    let max_size = ledger_size(DEFAULT_CAPACITY + 2) as u32;
+    process(
+        banks_client,
+        payer,
+        &[],
+        set_max_ledger_size(&payer.pubkey(), max_size),
+    )
+    .await
+    .unwrap();
+
+    for _ in 0..DEFAULT_CAPACITY + 1 {
+        transfer(
+            banks_client,
+            payer,
+            &mint_key(),
+            payer,
+            &Pubkey::new_unique(),
+            1,
+        )
+        .await
+        .unwrap();
+    }
+    let result = transfer(
+        banks_client,
+        payer,
+        &mint_key(),
+        payer,
+        &Pubkey::new_unique(),
+        1,
+    )
+    .await;
+    assert_token_error(result, TokenError::LedgerCapacityFull);
+    assert_eq!(ledger(banks_client).await.1, max_size as usize);
+}
+
+#[tokio::test]
+async fn growing_the_ledger_needs_the_system_program() {
+    let mut context = setup(1_000).await;
+    let (banks_client, payer) = (&mut context.banks_client, &context.payer);
+
+    // Fill the space `initialize_mint` allocated
+    for _ in 1..DEFAULT_CAPACITY {
+        transfer(
+            banks_client,
+            payer,
+            &mint_key(),
+            payer,
+            &Pubkey::new_unique(),
+            1,
+        )
+        .await
+        .unwrap();
+    }
+    let len = ledger(banks_client).await.1;
+
+    let recipient = Pubkey::new_unique();
+    let mut payload = preflight(banks_client, payer, &mint_key(), payer, &recipient, 1).await;
+    payload
+        .accounts
+        .retain(|acc| acc.pubkey != system_program::ID);
+    let result = transfer_with_payload(
+        banks_client,
+        payer,
+        &mint_key(),
+        payer,
+        &recipient,
+        1,
+        &payload,
+    )
+    .await;
+    assert_token_error(result, TokenError::MissingAccount);
+    assert_eq!(ledger(banks_client).await.1, len);
+}
+
+#[tokio::test]
+async fn closing_an_account_shrinks_the_ledger() {
+    let mut context = setup(1_000).await;
+    let (banks_client, payer) = (&mut context.banks_client, &context.payer);
+    let holder = Keypair::new();
+    let destination = Pubkey::new_unique();
+
+    let result = process(
+        banks_client,
+        payer,
+        &[&holder],
+        close_account(&holder.pubkey(), &destination),
+    )
+    .await;
+    assert_token_error(result, TokenError::UnknownHolder);
+
+    transfer(banks_client, payer, &mint_key(), payer, &holder.pubkey(), 1)
+        .await
+        .unwrap();
+    let result = process(
+        banks_client,
+        payer,
+        &[&holder],
+        close_account(&holder.pubkey(), &destination),
+    )
+    .await;
+    assert_token_error(result, TokenError::NonZeroBalance);
+
+    transfer(
+        banks_client,
+        payer,
+        &mint_key(),
+        &holder,
+        &payer.pubkey(),
+        1,
+    )
+    .await
+    .unwrap();
+    let (_, len_before) = ledger(banks_client).await;
+    process(
+        banks_client,
+        payer,
+        &[&holder],
+        close_account(&holder.pubkey(), &destination),
+    )
+    .await
+    .unwrap();
+
+    let (_, len) = ledger(banks_client).await;
+    assert_eq!(len, ledger_size(1));
+    assert_eq!(balance(banks_client, &holder.pubkey()).await, None);
+    assert_ledger_invariants(banks_client, &ledger_key()).await;
+    let rent = banks_client.get_rent().await.unwrap();
+    assert_eq!(
+        banks_client.get_balance(destination).await.unwrap(),
+        rent.minimum_balance(len_before) - rent.minimum_balance(len)
+    );
+}
+
+#[tokio::test]
+async fn migrates_a_legacy_ledger() {
+    let authority = Keypair::new();
+    let mut holders: Vec<(Pubkey, u64)> = (1..=20).map(|i| (Pubkey::new_unique(), i)).collect();
+    holders.push((authority.pubkey(), 100));
+
+    // Single-mint deployments keep the ledger of the program id mint
+    let legacy = ledger_address(&token_program::id()).0;
+    let mut program_test = ProgramTest::default();
+    program_test.add_program(
+        "token_program",
+        token_program::id(),
+        anchor_processor!(token_program::entry),
+    );
+    program_test.add_account(legacy, legacy_ledger(&authority.pubkey(), &holders, 3));
+    program_test.add_account(
+        authority.pubkey(),
+        Account::new(LAMPORTS_PER_SOL, 0, &system_program::ID),
+    );
+    let mut context = program_test.start_with_context().await;
+    let (banks_client, payer) = (&mut context.banks_client, &context.payer);
+    let legacy_len = banks_client
+        .get_account(legacy)
+        .await
+        .unwrap()
+        .unwrap()
+        .data
+        .len();
+
+    // Transfers need the new layout
+    let recipient = Pubkey::new_unique();
+    let result = transfer(
+        banks_client,
+        payer,
+        &token_program::id(),
+        &authority,
+        &recipient,
+        1,
+    )
+    .await;
+    assert_token_error(result, ErrorCode::AccountDiscriminatorMismatch);
+
+    let result = process(banks_client, payer, &[], migrate_ledger(&payer.pubkey())).await;
+    assert_token_error(result, TokenError::Unauthorized);
+
+    process(
+        banks_client,
+        payer,
+        &[&authority],
+        migrate_ledger(&authority.pubkey()),
+    )
+    .await
+    .unwrap();
+
+    let (header, len) = ledger_at(banks_client, &legacy).await;
+    assert_eq!(len, ledger_size(holders.len()));
+    assert_eq!({ header.len } as usize, holders.len());
+    assert_eq!({ header.total_supply }, 310);
+    assert_eq!({ header.mint_authority }, authority.pubkey());
+    assert_eq!({ header.max_size } as usize, legacy_len);
+    for (owner, amount) in &holders {
+        assert_eq!(
+            balance_in(banks_client, &legacy, owner).await,
+            Some(*amount)
+        );
+    }
+    assert_ledger_invariants(banks_client, &legacy).await;
+    let rent = banks_client.get_rent().await.unwrap();
+    assert_eq!(
+        banks_client.get_balance(authority.pubkey()).await.unwrap(),
+        LAMPORTS_PER_SOL + rent.minimum_balance(legacy_len) - rent.minimum_balance(len)
+    );
+
+    transfer(
+        banks_client,
+        payer,
+        &token_program::id(),
+        &authority,
+        &recipient,
+        1,
+    )
+    .await
+    .unwrap();
+    assert_eq!(balance_in(banks_client, &legacy, &recipient).await, Some(1));
+
+    // Migration only runs once
+    let result = process(
+        banks_client,
+        payer,
+        &[&authority],
+        migrate_ledger(&authority.pubkey()),
+    )
+    .await;
+    assert_token_error(result, ErrorCode::AccountDiscriminatorMismatch);
+}
+
+// The new header is larger than the legacy one, so ledgers with few holders grow
+#[tokio::test]
+async fn migrating_a_small_ledger_funds_its_growth() {
+    for holders in 0..=1 {
+        let authority = Keypair::new();
+        let holders: Vec<(Pubkey, u64)> = (0..holders).map(|_| (authority.pubkey(), 7)).collect();
+        let legacy = ledger_address(&token_program::id()).0;
+        let mut program_test = ProgramTest::default();
+        program_test.add_program(
+            "token_program",
+            token_program::id(),
+            anchor_processor!(token_program::entry),
+        );
+        program_test.add_account(legacy, legacy_ledger(&authority.pubkey(), &holders, 0));
+        program_test.add_account(
+            authority.pubkey(),
+            Account::new(LAMPORTS_PER_SOL, 0, &system_program::ID),
+        );
+        let mut context = program_test.start_with_context().await;
+        let (banks_client, payer) = (&mut context.banks_client, &context.payer);
+        let legacy_len = banks_client
+            .get_account(legacy)
+            .await
+            .unwrap()
+            .unwrap()
+            .data
+            .len();
+        assert!(legacy_len < ledger_size(holders.len()));
+
+        process(
+            banks_client,
+            payer,
+            &[&authority],
+            migrate_ledger(&authority.pubkey()),
+        )
+        .await
+        .unwrap();
+
+        let (header, len) = ledger_at(banks_client, &legacy).await;
+        assert_eq!(len, ledger_size(holders.len()));
+        assert_eq!({ header.len } as usize, holders.len());
+        assert_eq!({ header.max_size } as usize, len);
+        let rent = banks_client.get_rent().await.unwrap();
+        assert_eq!(
+            banks_client.get_balance(legacy).await.unwrap(),
+            rent.minimum_balance(len)
+        );
+        assert_eq!(
+            banks_client.get_balance(authority.pubkey()).await.unwrap(),
+            LAMPORTS_PER_SOL - (rent.minimum_balance(len) - rent.minimum_balance(legacy_len))
+        );
+        assert_ledger_invariants(banks_client, &legacy).await;
+    }
+}
+
+#[tokio::test]
+async fn hosts_several_mints() {
+    let mut context = setup(1_000).await;
+    let (banks_client, payer) = (&mut context.banks_client, &context.payer);
+    let other_id = Pubkey::new_unique();
+    initialize_mint(banks_client, payer, &other_id, 50)
+        .await
+        .unwrap();
+    let (other_mint, other_ledger) = (mint_address(&other_id).0, ledger_address(&other_id).0);
+
+    let holder = Pubkey::new_unique();
+    transfer(banks_client, payer, &mint_key(), payer, &holder, 7)
+        .await
+        .unwrap();
+    transfer(banks_client, payer, &other_mint, payer, &holder, 3)
+        .await
+        .unwrap();
+    assert_eq!(balance(banks_client, &holder).await, Some(7));
+    assert_eq!(balance(banks_client, &payer.pubkey()).await, Some(993));
+    assert_eq!(
+        balance_in(banks_client, &other_ledger, &holder).await,
+        Some(3)
+    );
+    assert_eq!(
+        { ledger_at(banks_client, &other_ledger).await.0.total_supply },
+        50
+    );
+
+    assert_ledger_invariants(banks_client, &ledger_key()).await;
+    assert_ledger_invariants(banks_client, &other_ledger).await;
+
+    // Each id has a single mint
+    let result = initialize_mint(banks_client, payer, &other_id, 50).await;
+    assert!(result.is_err());
+
+    // Transfers must pass the ledger of the mint they name
+    let mut payload = preflight(banks_client, payer, &other_mint, payer, &holder, 1).await;
+    payload.accounts[0].pubkey = ledger_key();
+    payload.accounts[1].pubkey = routing_address(&ledger_key()).0;
+    let result = transfer_with_payload(
+        banks_client,
+        payer,
+        &other_mint,
+        payer,
+        &holder,
+        1,
+        &payload,
+    )
+    .await;
+    assert_token_error(result, TokenError::LedgerMismatch);
+}
@@ -0,0 +1,527 @@
+// Anchor's derives use cfgs that newer compilers warn about
+#![allow(unknown_lints, unexpected_cfgs)]
+use anchor_lang::{system_program, AccountDeserialize, InstructionData, ToAccountMetas};
+use solana_sdk::{
+    instruction::Instruction,
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+};
+use token_program::{balance_address, BalanceMint, TokenError, DELEGATE_PREFIX};
+
+mod common;
+use common::*;
+
+fn delegation_key(owner: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[
+            DELEGATE_PREFIX.as_bytes(),
+            ledger_key().as_ref(),
+            owner.as_ref(),
+        ],
+        &token_program::id(),
+    )
+    .0
+}
+
+fn mint_to(mint_authority: &Pubkey, to: &Pubkey, amount: u64) -> Instruction {
+    Instruction {
+        program_id: token_program::id(),
+        accounts: token_program::accounts::MintTo {
+            mint_authority: *mint_authority,
+            ledger: ledger_key(),
+            to: *to,
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: token_program::instruction::MintTo { amount }.data(),
+    }
+}
+
+fn burn(authority: &Pubkey, owner: &Pubkey, amount: u64) -> Instruction {
+    Instruction {
+        program_id: token_program::id(),
+        accounts: token_program::accounts::Burn {
+            authority: *authority,
+            owner: *owner,
+            ledger: ledger_key(),
+            delegation: delegation_key(owner),
+        }
+        .to_account_metas(None),
+        data: token_program::instruction::Burn { amount }.data(),
+    }
+}
+
+fn set_mint_authority(mint_authority: &Pubkey, new_authority: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: token_program::id(),
+        accounts: token_program::accounts::SetMintAuthority {
+            mint_authority: *mint_authority,
+            ledger: ledger_key(),
+        }
+        .to_account_metas(None),
+        data: token_program::instruction::SetMintAuthority {
+            mint_authority: *new_authority,
+        }
+        .data(),
+    }
+}
+
+fn approve(owner: &Pubkey, delegate: &Pubkey, allowance: u64) -> Instruction {
+    Instruction {
+        program_id: token_program::id(),
+        accounts: token_program::accounts::Approve {
+            owner: *owner,
+            ledger: ledger_key(),
+            delegate: *delegate,
+            delegation: delegation_key(owner),
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: token_program::instruction::Approve { allowance }.data(),
+    }
+}
+
+fn revoke(owner: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: token_program::id(),
+        accounts: token_program::accounts::Revoke {
+            owner: *owner,
+            ledger: ledger_key(),
+            delegation: delegation_key(owner),
+        }
+        .to_account_metas(None),
+        data: token_program::instruction::Revoke {}.data(),
+    }
+}
+
+fn balance_delegation_key(mint: &Pubkey, owner: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(
+        &[DELEGATE_PREFIX.as_bytes(), mint.as_ref(), owner.as_ref()],
+        &token_program::id(),
+    )
+    .0
+}
+
+fn mint_to_balance(
+    mint_authority: &Pubkey,
+    mint: &Pubkey,
+    to: &Pubkey,
+    amount: u64,
+) -> Instruction {
+    Instruction {
+        program_id: token_program::id(),
+        accounts: token_program::accounts::MintToBalance {
+            mint_authority: *mint_authority,
+            mint: *mint,
+            to: *to,
+            balance: balance_address(mint, to).0,
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: token_program::instruction::MintToBalance { amount }.data(),
+    }
+}
+
+fn burn_balance(authority: &Pubkey, mint: &Pubkey, owner: &Pubkey, amount: u64) -> Instruction {
+    Instruction {
+        program_id: token_program::id(),
+        accounts: token_program::accounts::BurnBalance {
+            authority: *authority,
+            owner: *owner,
+            mint: *mint,
+            balance: balance_address(mint, owner).0,
+            delegation: balance_delegation_key(mint, owner),
+        }
+        .to_account_metas(None),
+        data: token_program::instruction::BurnBalance { amount }.data(),
+    }
+}
+
+fn approve_balance(
+    owner: &Pubkey,
+    mint: &Pubkey,
+    delegate: &Pubkey,
+    allowance: u64,
+) -> Instruction {
+    Instruction {
+        program_id: token_program::id(),
+        accounts: token_program::accounts::ApproveBalance {
+            owner: *owner,
+            mint: *mint,
+            delegate: *delegate,
+            delegation: balance_delegation_key(mint, owner),
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: token_program::instruction::ApproveBalance { allowance }.data(),
+    }
+}
+
+fn revoke_balance(owner: &Pubkey, mint: &Pubkey) -> Instruction {
+    Instruction {
+        program_id: token_program::id(),
+        accounts: token_program::accounts::RevokeBalance {
+            owner: *owner,
+            mint: *mint,
+            delegation: balance_delegation_key(mint, owner),
+        }
+        .to_account_metas(None),
+        data: token_program::instruction::RevokeBalance {}.data(),
+    }
+}
+
+async fn total_supply(banks_client: &mut solana_program_test::BanksClient) -> u64 {
+    ledger(banks_client).await.0.total_supply
+}
+
+async fn balance_mint_supply(
+    banks_client: &mut solana_program_test::BanksClient,
+    mint: &Pubkey,
+) -> u64 {
+    let account = banks_client.get_account(*mint).await.unwrap().unwrap();
+    BalanceMint::try_deserialize(&mut account.data.as_slice())
+        .unwrap()
+        .total_supply
+}
+
+#[tokio::test]
+async fn mint_to_and_burn_keep_supply_consistent() {
+    let mut context = setup(1_000).await;
+    let (banks_client, payer) = (&mut context.banks_client, &context.payer);
+    let holders: Vec<Pubkey> = (0..8).map(|_| Pubkey::new_unique()).collect();
+
+    // New holders past the initial capacity grow the ledger
+    for holder in &holders {
+        process(
+            banks_client,
+            payer,
+            &[],
+            mint_to(&payer.pubkey(), holder, 50),
+        )
+        .await
+        .unwrap();
+        assert_ledger_invariants(banks_client, &ledger_key()).await;
+    }
+    process(
+        banks_client,
+        payer,
+        &[],
+        mint_to(&payer.pubkey(), &payer.pubkey(), 10),
+    )
+    .await
+    .unwrap();
+    assert_eq!(total_supply(banks_client).await, 1_410);
+    assert_eq!(balance(banks_client, &payer.pubkey()).await, Some(1_010));
+
+    let intruder = Keypair::new();
+    let result = process(
+        banks_client,
+        payer,
+        &[&intruder],
+        mint_to(&intruder.pubkey(), &intruder.pubkey(), 1),
+    )
+    .await;
+    assert_token_error(result, TokenError::Unauthorized);
+    let result = process(
+        banks_client,
+        payer,
+        &[],
+        mint_to(&payer.pubkey(), &holders[0], u64::MAX),
+    )
+    .await;
+    assert_token_error(result, TokenError::MathOverflow);
+
+    process(
+        banks_client,
+        payer,
+        &[],
+        burn(&payer.pubkey(), &payer.pubkey(), 110),
+    )
+    .await
+    .unwrap();
+    let result = process(
+        banks_client,
+        payer,
+        &[],
+        burn(&payer.pubkey(), &payer.pubkey(), 901),
+    )
+    .await;
+    assert_token_error(result, TokenError::InsufficientFunds);
+    assert_eq!(total_supply(banks_client).await, 1_300);
+    assert_eq!(balance(banks_client, &payer.pubkey()).await, Some(900));
+    assert_ledger_invariants(banks_client, &ledger_key()).await;
+
+    // Transfers move balances without changing the supply
+    transfer(banks_client, payer, &mint_key(), payer, &holders[0], 25)
+        .await
+        .unwrap();
+    assert_eq!(total_supply(banks_client).await, 1_300);
+    assert_ledger_invariants(banks_client, &ledger_key()).await;
+}
+
+#[tokio::test]
+async fn delegates_burn_from_their_allowance() {
+    let mut context = setup(1_000).await;
+    let (banks_client, payer) = (&mut context.banks_client, &context.payer);
+    let delegate = Keypair::new();
+
+    let result = process(
+        banks_client,
+        payer,
+        &[&delegate],
+        burn(&delegate.pubkey(), &payer.pubkey(), 1),
+    )
+    .await;
+    assert_token_error(result, TokenError::Unauthorized);
+
+    process(
+        banks_client,
+        payer,
+        &[],
+        approve(&payer.pubkey(), &delegate.pubkey(), 3),
+    )
+    .await
+    .unwrap();
+    process(
+        banks_client,
+        payer,
+        &[&delegate],
+        burn(&delegate.pubkey(), &payer.pubkey(), 2),
+    )
+    .await
+    .unwrap();
+    let result = process(
+        banks_client,
+        payer,
+        &[&delegate],
+        burn(&delegate.pubkey(), &payer.pubkey(), 3),
+    )
+    .await;
+    assert_token_error(result, TokenError::InsufficientFunds);
+    assert_eq!(total_supply(banks_client).await, 998);
+    assert_ledger_invariants(banks_client, &ledger_key()).await;
+
+    process(banks_client, payer, &[], revoke(&payer.pubkey()))
+        .await
+        .unwrap();
+    let result = process(
+        banks_client,
+        payer,
+        &[&delegate],
+        burn(&delegate.pubkey(), &payer.pubkey(), 1),
+    )
+    .await;
+    assert_token_error(result, TokenError::Unauthorized);
+}
+
+#[tokio::test]
+async fn mint_authority_can_be_handed_over() {
+    let mut context = setup(1_000).await;
+    let (banks_client, payer) = (&mut context.banks_client, &context.payer);
+    let minter = Keypair::new();
+    let holder = Pubkey::new_unique();
+
+    process(
+        banks_client,
+        payer,
+        &[],
+        set_mint_authority(&payer.pubkey(), &minter.pubkey()),
+    )
+    .await
+    .unwrap();
+    let result = process(
+        banks_client,
+        payer,
+        &[],
+        mint_to(&payer.pubkey(), &holder, 1),
+    )
+    .await;
+    assert_token_error(result, TokenError::Unauthorized);
+
+    // The ledger has room for the new entry, so the new authority pays nothing
+    process(
+        banks_client,
+        payer,
+        &[&minter],
+        mint_to(&minter.pubkey(), &holder, 5),
+    )
+    .await
+    .unwrap();
+    assert_eq!(balance(banks_client, &holder).await, Some(5));
+    assert_ledger_invariants(banks_client, &ledger_key()).await;
+}
+
+// Lamports sent to a balance address up front don't block minting to it
+#[tokio::test]
+async fn balance_mints_mint_to_prefunded_balances() {
+    let mut context = setup(1_000).await;
+    let (banks_client, payer) = (&mut context.banks_client, &context.payer);
+    let mint = initialize_balance_mint(banks_client, payer, 100).await;
+    let holder = Pubkey::new_unique();
+    let lamports = banks_client.get_rent().await.unwrap().minimum_balance(0);
+    prefund_balance(banks_client, payer, &mint, &holder, lamports).await;
+
+    process(
+        banks_client,
+        payer,
+        &[],
+        mint_to_balance(&payer.pubkey(), &mint, &holder, 7),
+    )
+    .await
+    .unwrap();
+    assert_eq!(balance_of(banks_client, &mint, &holder).await, Some(7));
+    assert_eq!(balance_mint_supply(banks_client, &mint).await, 107);
+}
+
+#[tokio::test]
+async fn balance_mints_mint_and_burn() {
+    let mut context = setup(1_000).await;
+    let (banks_client, payer) = (&mut context.banks_client, &context.payer);
+    let mint = initialize_balance_mint(banks_client, payer, 100).await;
+    let holder = Keypair::new();
+
+    // The recipient's balance is created on first use
+    process(
+        banks_client,
+        payer,
+        &[],
+        mint_to_balance(&payer.pubkey(), &mint, &holder.pubkey(), 50),
+    )
+    .await
+    .unwrap();
+    process(
+        banks_client,
+        payer,
+        &[],
+        mint_to_balance(&payer.pubkey(), &mint, &holder.pubkey(), 5),
+    )
+    .await
+    .unwrap();
+    assert_eq!(
+        balance_of(banks_client, &mint, &holder.pubkey()).await,
+        Some(55)
+    );
+    assert_eq!(balance_mint_supply(banks_client, &mint).await, 155);
+
+    let result = process(
+        banks_client,
+        payer,
+        &[&holder],
+        mint_to_balance(&holder.pubkey(), &mint, &holder.pubkey(), 1),
+    )
+    .await;
+    assert_token_error(result, TokenError::Unauthorized);
+    let result = process(
+        banks_client,
+        payer,
+        &[],
+        mint_to_balance(&payer.pubkey(), &mint, &holder.pubkey(), u64::MAX),
+    )
+    .await;
+    assert_token_error(result, TokenError::MathOverflow);
+
+    process(
+        banks_client,
+        payer,
+        &[&holder],
+        burn_balance(&holder.pubkey(), &mint, &holder.pubkey(), 20),
+    )
+    .await
+    .unwrap();
+    let result = process(
+        banks_client,
+        payer,
+        &[&holder],
+        burn_balance(&holder.pubkey(), &mint, &holder.pubkey(), 36),
+    )
+    .await;
+    assert_token_error(result, TokenError::InsufficientFunds);
+    let stranger = Keypair::new();
+    let result = process(
+        banks_client,
+        payer,
+        &[&stranger],
+        burn_balance(&stranger.pubkey(), &mint, &stranger.pubkey(), 1),
+    )
+    .await;
+    assert_token_error(result, TokenError::UnknownHolder);
+    assert_eq!(
+        balance_of(banks_client, &mint, &holder.pubkey()).await,
+        Some(35)
+    );
+    assert_eq!(balance_mint_supply(banks_client, &mint).await, 135);
+}
+
+#[tokio::test]
+async fn balance_mint_delegates_burn_from_their_allowance() {
+    let mut context = setup(1_000).await;
+    let (banks_client, payer) = (&mut context.banks_client, &context.payer);
+    let mint = initialize_balance_mint(banks_client, payer, 100).await;
+    let delegate = Keypair::new();
+
+    let result = process(
+        banks_client,
+        payer,
+        &[&delegate],
+        burn_balance(&delegate.pubkey(), &mint, &payer.pubkey(), 1),
+    )
+    .await;
+    assert_token_error(result, TokenError::Unauthorized);
+
+    process(
+        banks_client,
+        payer,
+        &[],
+        approve_balance(&payer.pubkey(), &mint, &delegate.pubkey(), 3),
+    )
+    .await
+    .unwrap();
+    // Delegations of the ledger mint don't carry over
+    let result = process(
+        banks_client,
+        payer,
+        &[&delegate],
+        burn(&delegate.pubkey(), &payer.pubkey(), 1),
+    )
+    .await;
+    assert_token_error(result, TokenError::Unauthorized);
+
+    process(
+        banks_client,
+        payer,
+        &[&delegate],
+        burn_balance(&delegate.pubkey(), &mint, &payer.pubkey(), 2),
+    )
+    .await
+    .unwrap();
+    let result = process(
+        banks_client,
+        payer,
+        &[&delegate],
+        burn_balance(&delegate.pubkey(), &mint, &payer.pubkey(), 3),
+    )
+    .await;
+    assert_token_error(result, TokenError::InsufficientFunds);
+    assert_eq!(
+        balance_of(banks_client, &mint, &payer.pubkey()).await,
+        Some(98)
+    );
+    assert_eq!(balance_mint_supply(banks_client, &mint).await, 98);
+
+    process(
+        banks_client,
+        payer,
+        &[],
+        revoke_balance(&payer.pubkey(), &mint),
+    )
+    .await
+    .unwrap();
+    let result = process(
+        banks_client,
+        payer,
+        &[&delegate],
+        burn_balance(&delegate.pubkey(), &mint, &payer.pubkey(), 1),
+    )
+    .await;
+    assert_token_error(result, TokenError::Unauthorized);
+}
@@ -1,14 +1,29 @@
 use anchor_lang::prelude::*;
 use anchor_lang::solana_program::{
-    bpf_loader::ID as BPF_LOADER_ID, bpf_loader_upgradeable::ID as BPF_UPGRADEABLE_LOADER,
     program::MAX_RETURN_DATA, sysvar::instructions::ID as SYSVAR_INSTRUCTIONS_ID,
 };
-use anchor_spl::{token::ID as TOKEN_PROGRAM_ID, token_2022::ID as TOKEN_PROGRAM22_ID};
+use anchor_spl::{
+    token::{TokenAccount, ID as TOKEN_PROGRAM_ID},
+    token_2022::{
+        spl_token_2022::{
+            self,
+            extension::{
+                transfer_fee::TransferFeeConfig, BaseStateWithExtensions, StateWithExtensions,
+            },
+        },
+        ID as TOKEN_PROGRAM22_ID,
+    },
+};
 use borsh::ser::BorshSerialize;
-use mpl_token_metadata::{state::TokenMetadataAccount, ID as TOKEN_METADATA_ID};
+use mpl_token_metadata::{
+    state::{TokenMetadataAccount, TOKEN_RECORD_SIZE},
+    ID as TOKEN_METADATA_ID,
+};
 use token_interface::{
-    call, call_preflight_interface_function, IAccountMeta, ITransfer as _ITransfer,
-    PreflightPayload,
+    call_preflight_interface_function, call_with_config, find_preflight_payer, mint_program,
+    parse_interface_accounts, BalancePreview, CallConfig, FundingRequirement, IAccountMeta,
+    ITransfer as _ITransfer, ITransferWithPayer, InterfaceTransfer, PayerRole, PreflightPayload,
+    ReentrancyGuard, TransferRoute,
 };
 
 declare_id!("F96CHxPDRgjUypdUqpJocgT59vEPT79AFJXjtyPCBaRt");
@@ -32,57 +47,141 @@ pub mod token_wrapper {
 
     use super::*;
 
-    pub fn preflight_transfer(ctx: Context<ITransfer>, amount: u64) -> Result<()> {
+    pub fn preflight_transfer<'info>(
+        ctx: Context<'_, '_, '_, 'info, ITransfer<'info>>,
+        amount: u64,
+    ) -> Result<()> {
         let mint = &ctx.accounts.mint;
-        match match_callee(mint.owner) {
+        let payer = find_preflight_payer(ctx.remaining_accounts);
+        match match_callee(mint, ctx.remaining_accounts) {
             TransferInterface::SplToken => {
                 // TOKEN invoke
                 let source_ata = get_associated_token_address(ctx.accounts.owner.key, mint.key);
                 let destination_ata = get_associated_token_address(ctx.accounts.to.key, mint.key);
 
-                set_return_data(
-                    &PreflightPayload {
-                        accounts: vec![
-                            IAccountMeta {
-                                pubkey: *mint.owner,
-                                signer: false,
-                                writable: false,
-                            },
-                            IAccountMeta {
-                                pubkey: source_ata,
-                                signer: false,
-                                writable: true,
-                            },
-                            IAccountMeta {
-                                pubkey: destination_ata,
-                                signer: false,
-                                writable: true,
-                            },
-                        ],
+                let mut payload = PreflightPayload::new(vec![
+                    IAccountMeta {
+                        pubkey: *mint.owner,
+                        signer: false,
+                        writable: false,
+                    },
+                    IAccountMeta {
+                        pubkey: source_ata,
+                        signer: false,
+                        writable: true,
+                    },
+                    IAccountMeta {
+                        pubkey: destination_ata,
+                        signer: false,
+                        writable: true,
+                    },
+                ]);
+
+                // The destination ATA gets created during transfer,
+                // unless the caller hinted that it already exists
+                let destination_exists = ctx
+                    .remaining_accounts
+                    .iter()
+                    .any(|acc| acc.key() == destination_ata && !acc.data_is_empty());
+                if !destination_exists {
+                    payload.accounts.extend([
+                        payer_meta(&ctx.accounts.authority, payer),
+                        IAccountMeta {
+                            pubkey: system_program::id(),
+                            signer: false,
+                            writable: false,
+                        },
+                        IAccountMeta {
+                            pubkey: associated_token::ID,
+                            signer: false,
+                            writable: false,
+                        },
+                    ]);
+                    let lamports = Rent::get()?.minimum_balance(TokenAccount::LEN);
+                    payload = with_funding(payload, payer, lamports);
+                }
+
+                // Preview balances when the caller hinted the source ATA
+                let source = ctx
+                    .remaining_accounts
+                    .iter()
+                    .find(|acc| acc.key() == source_ata && !acc.data_is_empty());
+                if let Some(source) = source {
+                    let raw_source_data = source.try_borrow_data()?;
+                    let mut ptr = raw_source_data.as_ref();
+                    let source_data =
+                        anchor_spl::token_interface::TokenAccount::try_deserialize(&mut ptr)?;
+                    if source_data.amount >= amount {
+                        payload = payload.with_preview(BalancePreview::transfer(
+                            *mint.key,
+                            source_data.owner,
+                            *ctx.accounts.to.key,
+                            amount,
+                            transfer_fee(mint, amount)?,
+                        ));
                     }
-                    .try_to_vec()?,
-                );
+                }
+
+                set_return_data(&payload.for_caller(ctx.remaining_accounts).try_to_vec()?);
                 Ok(())
             }
-            TransferInterface::Interface => {
-                // Interface invoke
-                let ctx = CpiContext::new(
-                    mint.to_account_info(),
-                    _ITransfer {
-                        to: ctx.accounts.to.to_account_info(),
-                        owner: ctx.accounts.owner.to_account_info(),
-                        authority: ctx.accounts.authority.clone(),
-                        mint: mint.to_account_info(),
-                    },
-                );
-                call_preflight_interface_function(
-                    "transfer".to_string(),
-                    &ctx,
-                    &amount.try_to_vec()?,
-                )?;
-                let (key, return_data) = get_return_data().unwrap();
-                assert_eq!(key, *mint.key);
-                set_return_data(&return_data);
+            TransferInterface::Interface(program) => {
+                // Interface invoke, passing our caller's payer on to the target
+                ReentrancyGuard::new(crate::id()).check(program.key, ctx.remaining_accounts)?;
+                let hints: Vec<AccountInfo> = ctx
+                    .remaining_accounts
+                    .iter()
+                    .filter(|acc| !acc.is_writable)
+                    .cloned()
+                    .collect();
+                match payer {
+                    Some(payer) => {
+                        let ctx = CpiContext::new(
+                            program.clone(),
+                            ITransferWithPayer {
+                                to: ctx.accounts.to.to_account_info(),
+                                owner: ctx.accounts.owner.to_account_info(),
+                                authority: ctx.accounts.authority.clone(),
+                                mint: mint.to_account_info(),
+                                payer: Signer::try_from(payer)?,
+                            },
+                        )
+                        .with_remaining_accounts(hints);
+                        call_preflight_interface_function(
+                            "transfer".to_string(),
+                            &ctx,
+                            &amount.try_to_vec()?,
+                        )?;
+                    }
+                    None => {
+                        let ctx = CpiContext::new(
+                            program.clone(),
+                            _ITransfer {
+                                to: ctx.accounts.to.to_account_info(),
+                                owner: ctx.accounts.owner.to_account_info(),
+                                authority: ctx.accounts.authority.clone(),
+                                mint: mint.to_account_info(),
+                            },
+                        )
+                        .with_remaining_accounts(hints);
+                        call_preflight_interface_function(
+                            "transfer".to_string(),
+                            &ctx,
+                            &amount.try_to_vec()?,
+                        )?;
+                    }
+                }
+                let mut payload = parse_interface_accounts(program.key, get_return_data())?;
+                if program.key != mint.key {
+                    // Mint-state accounts don't name their program, so callers
+                    // get it back to pass along with the transfer
+                    payload.accounts.push(IAccountMeta {
+                        pubkey: program.key(),
+                        signer: false,
+                        writable: false,
+                    });
+                }
+                set_return_data(&payload.try_to_vec()?);
                 Ok(())
             }
             TransferInterface::TokenMetadata => {
@@ -114,6 +213,8 @@ pub mod token_wrapper {
                         signer: false,
                         writable: false,
                     },
+                    // payer for the destination token account & token record
+                    payer_meta(&ctx.accounts.authority, payer),
                     // #[account(0, writable, name="token", desc="Token account")]
                     IAccountMeta {
                         pubkey: owner_ata.key(),
@@ -204,7 +305,12 @@ pub mod token_wrapper {
                     }
                 }
 
-                let serialized = PreflightPayload { accounts }.try_to_vec()?;
+                let rent = Rent::get()?;
+                let lamports = rent.minimum_balance(TokenAccount::LEN)
+                    + rent.minimum_balance(TOKEN_RECORD_SIZE);
+                let serialized = with_funding(PreflightPayload::new(accounts), payer, lamports)
+                    .for_caller(ctx.remaining_accounts)
+                    .try_to_vec()?;
                 msg!("Serialized len: {}, {}", serialized.len(), MAX_RETURN_DATA);
                 set_return_data(&serialized);
                 Ok(())
@@ -219,14 +325,43 @@ pub mod token_wrapper {
         amount: u64,
     ) -> Result<()> {
         let mint = &ctx.accounts.mint;
-        match match_callee(mint.owner) {
+        let (program, route) = match match_callee(mint, ctx.remaining_accounts) {
             TransferInterface::SplToken => {
                 // Token invoke
                 msg!("SPL Token");
+                // Callers may pass unrelated accounts along, so look ours up by key
                 let remaining_accounts = ctx.remaining_accounts;
-                let token = remaining_accounts.get(0).unwrap().to_account_info();
-                let from = remaining_accounts.get(1).unwrap().to_account_info();
-                let to = remaining_accounts.get(2).unwrap().to_account_info();
+                let token = find_account(remaining_accounts, mint.owner)?;
+                let from = find_account(
+                    remaining_accounts,
+                    &get_associated_token_address(ctx.accounts.owner.key, mint.key),
+                )?;
+                let to = find_account(
+                    remaining_accounts,
+                    &get_associated_token_address(ctx.accounts.to.key, mint.key),
+                )?;
+
+                if to.data_is_empty() {
+                    // Preflight requested either the caller's payer or the authority
+                    // as the only writable signer among the additional accounts
+                    let payer = remaining_accounts
+                        .iter()
+                        .find(|acc| acc.is_signer && acc.is_writable)
+                        .cloned()
+                        .unwrap_or_else(|| ctx.accounts.authority.to_account_info());
+                    associated_token::create(CpiContext::new(
+                        find_account(remaining_accounts, &associated_token::ID)?,
+                        associated_token::Create {
+                            payer,
+                            associated_token: to.clone(),
+                            authority: ctx.accounts.to.to_account_info(),
+                            mint: mint.to_account_info(),
+                            system_program: find_account(remaining_accounts, &system_program::ID)?,
+                            token_program: token.clone(),
+                        },
+                    ))?;
+                }
+
                 let ctx = CpiContext::new(
                     token,
                     anchor_spl::token_interface::TransferChecked {
@@ -244,32 +379,73 @@ pub mod token_wrapper {
                 let mut ptr = raw_mint_data.as_ref();
                 let mint_data = anchor_spl::token_interface::Mint::try_deserialize(&mut ptr)?;
                 anchor_spl::token_interface::transfer_checked(ctx, amount, mint_data.decimals)?;
+                (*mint.owner, TransferRoute::SplToken)
             }
-            TransferInterface::Interface => {
+            TransferInterface::Interface(program) => {
                 // Interface invoke
                 msg!("Interface");
-                let ctx = CpiContext::new(
-                    mint.to_account_info(),
-                    _ITransfer {
-                        to: ctx.accounts.to.to_account_info(),
-                        owner: ctx.accounts.owner.to_account_info(),
-                        authority: ctx.accounts.authority.clone(),
-                        mint: mint.to_account_info(),
-                    },
-                )
-                .with_remaining_accounts(ctx.remaining_accounts.to_vec());
-                call("transfer".to_string(), ctx, amount.try_to_vec()?, false)?;
+                let config = CallConfig {
+                    guard: Some(ReentrancyGuard::new(crate::id())),
+                    ..Default::default()
+                };
+                // If the target requested a payer, it is the only writable signer we were passed
+                let payer = ctx
+                    .remaining_accounts
+                    .iter()
+                    .find(|acc| acc.is_signer && acc.is_writable);
+                match payer {
+                    Some(payer) => {
+                        let ctx = CpiContext::new(
+                            program.clone(),
+                            ITransferWithPayer {
+                                to: ctx.accounts.to.to_account_info(),
+                                owner: ctx.accounts.owner.to_account_info(),
+                                authority: ctx.accounts.authority.clone(),
+                                mint: mint.to_account_info(),
+                                payer: Signer::try_from(payer)?,
+                            },
+                        )
+                        .with_remaining_accounts(ctx.remaining_accounts.to_vec());
+                        call_with_config(
+                            "transfer".to_string(),
+                            ctx,
+                            amount.try_to_vec()?,
+                            config,
+                        )?;
+                    }
+                    None => {
+                        let ctx = CpiContext::new(
+                            program.clone(),
+                            _ITransfer {
+                                to: ctx.accounts.to.to_account_info(),
+                                owner: ctx.accounts.owner.to_account_info(),
+                                authority: ctx.accounts.authority.clone(),
+                                mint: mint.to_account_info(),
+                            },
+                        )
+                        .with_remaining_accounts(ctx.remaining_accounts.to_vec());
+                        call_with_config(
+                            "transfer".to_string(),
+                            ctx,
+                            amount.try_to_vec()?,
+                            config,
+                        )?;
+                    }
+                }
+                (program.key(), TransferRoute::Interface)
             }
             TransferInterface::TokenMetadata => {
                 // Token Metadata invoke
                 msg!("Token Metadata");
 
-                // Note: The first account is actually the token-metadata program account info
+                // Note: The first account is actually the token-metadata program account info,
+                // followed by the payer that preflight requested
                 let program_id = ctx.remaining_accounts.get(0).unwrap().key;
+                let payer = ctx.remaining_accounts.get(1).unwrap().to_account_info();
                 let mut accounts: Vec<AccountInfo> = vec![];
 
                 // yeah yeah it's inefficient, i know
-                accounts.extend_from_slice(&ctx.remaining_accounts[1..]);
+                accounts.extend_from_slice(&ctx.remaining_accounts[2..]);
 
                 // #[account(0, writable, name="token", desc="Token account")]
                 // #[account(1, name="token_owner", desc="Token account owner")]
@@ -286,9 +462,8 @@ pub mod token_wrapper {
                 // #[account(9, signer, name="authority", desc="Transfer authority (token owner or delegate)")]
                 accounts.insert(9, ctx.accounts.authority.to_account_info());
                 // #[account(10, signer, writable, name="payer", desc="Payer")]
-                // yeah the authority has to be the payer here...
-                // FIXME(ngundotra): this feels implicit, that all signers have to be the authority
-                accounts.insert(10, ctx.accounts.authority.to_account_info());
+                // either our caller's payer or the authority, whichever preflight requested
+                accounts.insert(10, payer);
                 // #[account(11, name="system_program", desc="System Program")]
                 // #[account(12, name="sysvar_instructions", desc="Instructions sysvar account")]
                 // #[account(13, name="spl_token_program", desc="SPL Token Program")]
@@ -305,37 +480,111 @@ pub mod token_wrapper {
                     accounts: accounts.to_account_metas(None),
                 };
                 anchor_lang::solana_program::program::invoke(&ix, accounts.as_slice())?;
+                (TOKEN_METADATA_ID, TransferRoute::TokenMetadata)
             }
             // Bad invoke
             _ => return Err(ErrorCode::InstructionMissing.into()),
-        }
+        };
+
+        emit!(InterfaceTransfer {
+            program,
+            mint: mint.key(),
+            owner: ctx.accounts.owner.key(),
+            to: ctx.accounts.to.key(),
+            authority: ctx.accounts.authority.key(),
+            amount,
+            route,
+        });
         Ok(())
     }
 }
 
-enum TransferInterface {
+enum TransferInterface<'info> {
     SplToken,
-    Interface,
+    // Carries the program implementing the interface
+    Interface(AccountInfo<'info>),
     TokenMetadata,
     Error,
 }
 
 // This is a check that allows us to determine if we are calling
 // an SPL token program, or a custom implementation
-fn match_callee(mint_owner: &Pubkey) -> TransferInterface {
-    if *mint_owner == TOKEN_PROGRAM22_ID || *mint_owner == TOKEN_PROGRAM_ID {
+fn match_callee<'info>(
+    mint: &AccountInfo<'info>,
+    remaining_accounts: &[AccountInfo<'info>],
+) -> TransferInterface<'info> {
+    if *mint.owner == TOKEN_PROGRAM22_ID || *mint.owner == TOKEN_PROGRAM_ID {
         TransferInterface::SplToken
-    } else if *mint_owner == BPF_LOADER_ID || *mint_owner == BPF_UPGRADEABLE_LOADER {
-        // If the `mint` account is actually a program, then we know to call a custom program
-        TransferInterface::Interface
-    } else if *mint_owner == TOKEN_METADATA_ID {
+    } else if *mint.owner == TOKEN_METADATA_ID {
         TransferInterface::TokenMetadata
+    } else if let Some(program) = mint_program(mint, remaining_accounts) {
+        // If the `mint` account is actually a program, or a mint-state account
+        // passed along with its program, then we know to call a custom program
+        TransferInterface::Interface(program)
     } else {
         // Here, we could add support for custom implementations of token programs
         TransferInterface::Error
     }
 }
 
+// The remaining account with `key`, wherever the caller put it
+fn find_account<'info>(
+    accounts: &[AccountInfo<'info>],
+    key: &Pubkey,
+) -> Result<AccountInfo<'info>> {
+    accounts
+        .iter()
+        .find(|acc| acc.key == key)
+        .cloned()
+        .ok_or_else(|| ErrorCode::AccountNotEnoughKeys.into())
+}
+
+// The account preflight asks to pay for new accounts: the caller's payer
+// if they passed one, otherwise the authority
+fn payer_meta(authority: &AccountInfo, payer: Option<&AccountInfo>) -> IAccountMeta {
+    IAccountMeta {
+        pubkey: payer.map_or(authority.key(), |payer| payer.key()),
+        signer: false,
+        writable: true,
+    }
+}
+
+// Adds the funding requirement for `lamports`, and lists the caller's payer
+// as a required signer when it is the one paying
+fn with_funding(
+    payload: PreflightPayload,
+    payer: Option<&AccountInfo>,
+    lamports: u64,
+) -> PreflightPayload {
+    match payer {
+        Some(payer) => payload
+            .with_funding(FundingRequirement {
+                lamports,
+                payer: PayerRole::Payer,
+            })
+            .with_signers(vec![payer.key()]),
+        None => payload.with_funding(FundingRequirement {
+            lamports,
+            payer: PayerRole::Authority,
+        }),
+    }
+}
+
+// Fee withheld by token-2022's transfer fee extension, if the mint has one
+fn transfer_fee(mint: &AccountInfo, amount: u64) -> Result<u64> {
+    if *mint.owner != TOKEN_PROGRAM22_ID {
+        return Ok(0);
+    }
+    let raw_mint_data = mint.try_borrow_data()?;
+    let mint_data = StateWithExtensions::<spl_token_2022::state::Mint>::unpack(&raw_mint_data)?;
+    match mint_data.get_extension::<TransferFeeConfig>() {
+        Ok(config) => Ok(config
+            .calculate_epoch_fee(Clock::get()?.epoch, amount)
+            .ok_or(ErrorCode::InstructionDidNotDeserialize)?),
+        Err(_) => Ok(0),
+    }
+}
+
 #[derive(Accounts)]
 pub struct ITransfer<'info> {
     /// CHECK:
@@ -0,0 +1,246 @@
+#![allow(dead_code)]
+use anchor_lang::{system_program, InstructionData, ToAccountMetas};
+use anchor_spl::token::spl_token;
+use solana_program_test::{
+    processor, BanksClient, BanksClientError, ProgramTest, ProgramTestContext,
+};
+use solana_sdk::{
+    instruction::{AccountMeta, Instruction, InstructionError},
+    program_pack::Pack,
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    system_instruction,
+    transaction::{Transaction, TransactionError},
+};
+use spl_associated_token_account::{
+    get_associated_token_address, instruction::create_associated_token_account,
+};
+use token_interface::{PreflightPayload, EXTENSIONS_HINT};
+use token_interface_conformance::{anchor_processor, decode_payload};
+use token_program::{find_entry, ledger_address, mint_address, split_ledger, MintMetadata};
+
+// Identifies the `token_program` mint `setup` creates
+pub fn mint_id() -> Pubkey {
+    Pubkey::new_from_array([7; 32])
+}
+
+pub fn mint_key() -> Pubkey {
+    mint_address(&mint_id()).0
+}
+
+pub fn ledger_key() -> Pubkey {
+    ledger_address(&mint_id()).0
+}
+
+// Starts a bank running `token_wrapper` and `token_program`, with a
+// `token_program` mint of `supply` held by the payer
+pub async fn setup(supply: u64) -> ProgramTestContext {
+    setup_with(ProgramTest::default(), supply).await
+}
+
+// Like `setup`, with the programs and accounts already in `program_test`
+pub async fn setup_with(mut program_test: ProgramTest, supply: u64) -> ProgramTestContext {
+    program_test.add_program(
+        "token_program",
+        token_program::id(),
+        anchor_processor!(token_program::entry),
+    );
+    program_test.add_program(
+        "token_wrapper",
+        token_wrapper::id(),
+        anchor_processor!(token_wrapper::entry),
+    );
+    let mut context = program_test.start_with_context().await;
+
+    let ix = Instruction {
+        program_id: token_program::id(),
+        accounts: token_program::accounts::InitializeMint {
+            authority: context.payer.pubkey(),
+            mint: mint_key(),
+            ledger: ledger_key(),
+            system_program: system_program::ID,
+        }
+        .to_account_metas(None),
+        data: token_program::instruction::InitializeMint {
+            id: mint_id(),
+            metadata: MintMetadata {
+                name: "Wrapped".to_string(),
+                symbol: "WRP".to_string(),
+                decimals: 0,
+            },
+            supply,
+        }
+        .data(),
+    };
+    process(&mut context.banks_client, &context.payer, &[], ix)
+        .await
+        .unwrap();
+    context
+}
+
+// Starts a bank running `token_wrapper` and SPL Token, with an SPL mint
+// whose `supply` sits in the payer's ATA
+pub async fn setup_spl_mint(supply: u64) -> (ProgramTestContext, Pubkey) {
+    let mut program_test = ProgramTest::default();
+    // The bundled SPL builds don't load on every host, so run them natively too
+    program_test.add_program(
+        "spl_token",
+        spl_token::ID,
+        processor!(spl_token::processor::Processor::process),
+    );
+    program_test.add_program(
+        "spl_associated_token_account",
+        spl_associated_token_account::ID,
+        processor!(spl_associated_token_account::processor::process_instruction),
+    );
+    program_test.add_program(
+        "token_wrapper",
+        token_wrapper::id(),
+        anchor_processor!(token_wrapper::entry),
+    );
+    let mut context = program_test.start_with_context().await;
+    let payer = &context.payer;
+
+    let mint = Keypair::new();
+    let rent = context.banks_client.get_rent().await.unwrap();
+    let ata = get_associated_token_address(&payer.pubkey(), &mint.pubkey());
+    let tx = Transaction::new_signed_with_payer(
+        &[
+            system_instruction::create_account(
+                &payer.pubkey(),
+                &mint.pubkey(),
+                rent.minimum_balance(spl_token::state::Mint::LEN),
+                spl_token::state::Mint::LEN as u64,
+                &spl_token::ID,
+            ),
+            spl_token::instruction::initialize_mint(
+                &spl_token::ID,
+                &mint.pubkey(),
+                &payer.pubkey(),
+                None,
+                0,
+            )
+            .unwrap(),
+            create_associated_token_account(
+                &payer.pubkey(),
+                &payer.pubkey(),
+                &mint.pubkey(),
+                &spl_token::ID,
+            ),
+            spl_token::instruction::mint_to(
+                &spl_token::ID,
+                &mint.pubkey(),
+                &ata,
+                &payer.pubkey(),
+                &[],
+                supply,
+            )
+            .unwrap(),
+        ],
+        Some(&payer.pubkey()),
+        &[payer, &mint],
+        context.last_blockhash,
+    );
+    context.banks_client.process_transaction(tx).await.unwrap();
+    (context, mint.pubkey())
+}
+
+// Sends `ix` paid for by `payer`, signed by `signers` as well
+pub async fn process(
+    banks_client: &mut BanksClient,
+    payer: &Keypair,
+    signers: &[&Keypair],
+    ix: Instruction,
+) -> Result<(), BanksClientError> {
+    let mut keypairs = vec![payer];
+    keypairs.extend(signers.iter().filter(|kp| kp.pubkey() != payer.pubkey()));
+    let blockhash = banks_client.get_latest_blockhash().await?;
+    let tx = Transaction::new_signed_with_payer(&[ix], Some(&payer.pubkey()), &keypairs, blockhash);
+    banks_client.process_transaction(tx).await
+}
+
+fn interface_accounts(owner: &Keypair, to: &Pubkey) -> Vec<AccountMeta> {
+    token_wrapper::accounts::ITransfer {
+        owner: owner.pubkey(),
+        to: *to,
+        authority: owner.pubkey(),
+        mint: mint_key(),
+    }
+    .to_account_metas(None)
+}
+
+// Resolves a wrapper transfer of the `setup` mint, passing `token_program`
+// as the hint the wrapper needs for mint-state accounts, and opting in to extensions
+pub async fn preflight(
+    banks_client: &mut BanksClient,
+    payer: &Keypair,
+    owner: &Keypair,
+    to: &Pubkey,
+    amount: u64,
+) -> PreflightPayload {
+    let mut accounts = interface_accounts(owner, to);
+    accounts.push(AccountMeta::new_readonly(token_program::id(), false));
+    accounts.push(AccountMeta::new_readonly(EXTENSIONS_HINT, false));
+    let ix = Instruction {
+        program_id: token_wrapper::id(),
+        accounts,
+        data: token_wrapper::instruction::PreflightTransfer { amount }.data(),
+    };
+    let mut keypairs = vec![payer];
+    if owner.pubkey() != payer.pubkey() {
+        keypairs.push(owner);
+    }
+    let blockhash = banks_client.get_latest_blockhash().await.unwrap();
+    let tx = Transaction::new_signed_with_payer(&[ix], Some(&payer.pubkey()), &keypairs, blockhash);
+    let simulation = banks_client.simulate_transaction(tx).await.unwrap();
+    simulation.result.unwrap().unwrap();
+    let return_data = simulation.simulation_details.unwrap().return_data.unwrap();
+    decode_payload(&return_data.data).unwrap()
+}
+
+// Preflights and sends a transfer of `amount` from `owner` to `to` through the wrapper
+pub async fn transfer(
+    banks_client: &mut BanksClient,
+    payer: &Keypair,
+    owner: &Keypair,
+    to: &Pubkey,
+    amount: u64,
+) -> Result<(), BanksClientError> {
+    let payload = preflight(banks_client, payer, owner, to, amount).await;
+    let mut accounts = interface_accounts(owner, to);
+    accounts.extend(payload.accounts.iter().map(|acc| AccountMeta {
+        pubkey: acc.pubkey,
+        is_signer: acc.signer,
+        is_writable: acc.writable,
+    }));
+    let ix = Instruction {
+        program_id: token_wrapper::id(),
+        accounts,
+        data: token_wrapper::instruction::Transfer { amount }.data(),
+    };
+    process(banks_client, payer, &[owner], ix).await
+}
+
+// `owner`'s balance in the `setup` ledger, if they have an entry
+pub async fn balance(banks_client: &mut BanksClient, owner: &Pubkey) -> Option<u64> {
+    let account = banks_client
+        .get_account(ledger_key())
+        .await
+        .unwrap()
+        .unwrap();
+    let (_, entries) = split_ledger(&account.data).unwrap();
+    find_entry(entries, owner)
+        .ok()
+        .map(|index| entries[index].amount)
+}
+
+// Asserts that a transaction failed with `error`, as raised by `token_program`
+pub fn assert_token_error(result: Result<(), BanksClientError>, error: impl Into<u32>) {
+    match result.unwrap_err() {
+        BanksClientError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(code),
+        )) => assert_eq!(code, error.into()),
+        err => panic!("unexpected error: {:?}", err),
+    }
+}
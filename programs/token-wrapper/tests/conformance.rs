@@ -0,0 +1,30 @@
+// Anchor's derives use cfgs that newer compilers warn about
+#![allow(unknown_lints, unexpected_cfgs)]
+use token_interface_conformance::Conformance;
+
+mod common;
+use common::*;
+
+// Runs the checks over the SPL Token route
+#[tokio::test]
+async fn token_wrapper_conforms() {
+    let (mut context, mint) = setup_spl_mint(1_000).await;
+
+    Conformance::new(token_wrapper::id(), mint)
+        .run(&mut context.banks_client, &context.payer)
+        .await
+        .assert_passed();
+}
+
+// Runs the checks over the interface route, for a `token_program` mint whose
+// `mint` is a mint-state account. Callers pass the program as a hint.
+#[tokio::test]
+async fn token_program_mint_conforms() {
+    let mut context = setup(1_000).await;
+
+    Conformance::new(token_wrapper::id(), mint_key())
+        .with_hints(vec![token_program::id()])
+        .run(&mut context.banks_client, &context.payer)
+        .await
+        .assert_passed();
+}
@@ -0,0 +1,74 @@
+// Anchor's derives use cfgs that newer compilers warn about
+#![allow(unknown_lints, unexpected_cfgs)]
+use anchor_lang::{InstructionData, ToAccountMetas};
+use solana_sdk::{
+    instruction::Instruction,
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+};
+use token_program::TokenError;
+
+mod common;
+use common::*;
+
+fn freeze_instruction(
+    freeze_authority: &Pubkey,
+    owner: &Pubkey,
+    data: impl InstructionData,
+) -> Instruction {
+    Instruction {
+        program_id: token_program::id(),
+        accounts: token_program::accounts::FreezeAccount {
+            freeze_authority: *freeze_authority,
+            ledger: ledger_key(),
+            owner: *owner,
+        }
+        .to_account_metas(None),
+        data: data.data(),
+    }
+}
+
+#[tokio::test]
+async fn wrapper_transfers_respect_frozen_balances() {
+    let mut context = setup(1_000).await;
+    let (banks_client, payer) = (&mut context.banks_client, &context.payer);
+    let holder = Keypair::new();
+    transfer(banks_client, payer, payer, &holder.pubkey(), 10)
+        .await
+        .unwrap();
+
+    process(
+        banks_client,
+        payer,
+        &[],
+        freeze_instruction(
+            &payer.pubkey(),
+            &holder.pubkey(),
+            token_program::instruction::FreezeAccount {},
+        ),
+    )
+    .await
+    .unwrap();
+    let result = transfer(banks_client, payer, &holder, &payer.pubkey(), 1).await;
+    assert_token_error(result, TokenError::AccountFrozen);
+    let result = transfer(banks_client, payer, payer, &holder.pubkey(), 1).await;
+    assert_token_error(result, TokenError::AccountFrozen);
+
+    process(
+        banks_client,
+        payer,
+        &[],
+        freeze_instruction(
+            &payer.pubkey(),
+            &holder.pubkey(),
+            token_program::instruction::ThawAccount {},
+        ),
+    )
+    .await
+    .unwrap();
+    transfer(banks_client, payer, &holder, &payer.pubkey(), 4)
+        .await
+        .unwrap();
+    assert_eq!(balance(banks_client, &holder.pubkey()).await, Some(6));
+    assert_eq!(balance(banks_client, &payer.pubkey()).await, Some(994));
+}
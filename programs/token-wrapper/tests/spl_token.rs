@@ -0,0 +1,165 @@
+// Anchor's derives use cfgs that newer compilers warn about
+#![allow(unknown_lints, unexpected_cfgs)]
+use anchor_lang::{InstructionData, ToAccountMetas};
+use anchor_spl::token::spl_token;
+use solana_program_test::BanksClient;
+use solana_sdk::{
+    instruction::{AccountMeta, Instruction},
+    program_pack::Pack,
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    transaction::Transaction,
+};
+use spl_associated_token_account::{
+    get_associated_token_address, instruction::create_associated_token_account,
+};
+use token_interface::{PreflightPayload, EXTENSIONS_HINT};
+use token_interface_conformance::decode_payload;
+
+mod common;
+use common::*;
+
+fn interface_accounts(mint: &Pubkey, owner: &Keypair, to: &Pubkey) -> Vec<AccountMeta> {
+    token_wrapper::accounts::ITransfer {
+        owner: owner.pubkey(),
+        to: *to,
+        authority: owner.pubkey(),
+        mint: *mint,
+    }
+    .to_account_metas(None)
+}
+
+async fn spl_preflight(
+    banks_client: &mut BanksClient,
+    owner: &Keypair,
+    mint: &Pubkey,
+    to: &Pubkey,
+    amount: u64,
+    hints: &[Pubkey],
+) -> PreflightPayload {
+    let mut accounts = interface_accounts(mint, owner, to);
+    accounts.extend(
+        hints
+            .iter()
+            .chain([&EXTENSIONS_HINT])
+            .map(|key| AccountMeta::new_readonly(*key, false)),
+    );
+    let ix = Instruction {
+        program_id: token_wrapper::id(),
+        accounts,
+        data: token_wrapper::instruction::PreflightTransfer { amount }.data(),
+    };
+    let blockhash = banks_client.get_latest_blockhash().await.unwrap();
+    let tx = Transaction::new_signed_with_payer(&[ix], Some(&owner.pubkey()), &[owner], blockhash);
+    let simulation = banks_client.simulate_transaction(tx).await.unwrap();
+    simulation.result.unwrap().unwrap();
+    let return_data = simulation.simulation_details.unwrap().return_data.unwrap();
+    decode_payload(&return_data.data).unwrap()
+}
+
+// The requested accounts, with unrelated accounts `before` and `after` them
+fn surrounded(payload: &PreflightPayload, before: &[Pubkey], after: &[Pubkey]) -> Vec<AccountMeta> {
+    let unrelated = |key: &Pubkey| AccountMeta::new_readonly(*key, false);
+    before
+        .iter()
+        .map(unrelated)
+        .chain(payload.accounts.iter().map(|acc| AccountMeta {
+            pubkey: acc.pubkey,
+            is_signer: acc.signer,
+            is_writable: acc.writable,
+        }))
+        .chain(after.iter().map(unrelated))
+        .collect()
+}
+
+// Sends a wrapper transfer followed by `additional` accounts
+async fn spl_transfer(
+    banks_client: &mut BanksClient,
+    owner: &Keypair,
+    mint: &Pubkey,
+    to: &Pubkey,
+    amount: u64,
+    additional: Vec<AccountMeta>,
+) {
+    let mut accounts = interface_accounts(mint, owner, to);
+    accounts.extend(additional);
+    let ix = Instruction {
+        program_id: token_wrapper::id(),
+        accounts,
+        data: token_wrapper::instruction::Transfer { amount }.data(),
+    };
+    process(banks_client, owner, &[], ix).await.unwrap();
+}
+
+async fn token_balance(banks_client: &mut BanksClient, mint: &Pubkey, owner: &Pubkey) -> u64 {
+    let account = banks_client
+        .get_account(get_associated_token_address(owner, mint))
+        .await
+        .unwrap()
+        .unwrap();
+    spl_token::state::Account::unpack(&account.data)
+        .unwrap()
+        .amount
+}
+
+#[tokio::test]
+async fn existing_atas_tolerate_unrelated_accounts() {
+    let (mut context, mint) = setup_spl_mint(1_000).await;
+    let (banks_client, payer) = (&mut context.banks_client, &context.payer);
+    let holder = Pubkey::new_unique();
+    let holder_ata = get_associated_token_address(&holder, &mint);
+    let ix = create_associated_token_account(&payer.pubkey(), &holder, &mint, &spl_token::ID);
+    process(banks_client, payer, &[], ix).await.unwrap();
+
+    // With the ATA hinted, preflight asks for no funding accounts
+    let payload = spl_preflight(banks_client, payer, &mint, &holder, 10, &[holder_ata]).await;
+    assert_eq!(payload.accounts.len(), 3);
+    let extra = Pubkey::new_unique();
+    spl_transfer(
+        banks_client,
+        payer,
+        &mint,
+        &holder,
+        10,
+        surrounded(&payload, &[], &[extra]),
+    )
+    .await;
+    assert_eq!(token_balance(banks_client, &mint, &holder).await, 10);
+
+    // Unhinted, the funding accounts are passed but the ATA isn't created again
+    let payload = spl_preflight(banks_client, payer, &mint, &holder, 11, &[]).await;
+    assert!(payload.accounts.len() > 3);
+    spl_transfer(
+        banks_client,
+        payer,
+        &mint,
+        &holder,
+        11,
+        surrounded(&payload, &[extra], &[Pubkey::new_unique()]),
+    )
+    .await;
+    assert_eq!(token_balance(banks_client, &mint, &holder).await, 21);
+    assert_eq!(
+        token_balance(banks_client, &mint, &payer.pubkey()).await,
+        979
+    );
+}
+
+#[tokio::test]
+async fn new_atas_are_created_with_unrelated_accounts_around() {
+    let (mut context, mint) = setup_spl_mint(1_000).await;
+    let (banks_client, payer) = (&mut context.banks_client, &context.payer);
+    let holder = Pubkey::new_unique();
+
+    let payload = spl_preflight(banks_client, payer, &mint, &holder, 5, &[]).await;
+    spl_transfer(
+        banks_client,
+        payer,
+        &mint,
+        &holder,
+        5,
+        surrounded(&payload, &[Pubkey::new_unique()], &[Pubkey::new_unique()]),
+    )
+    .await;
+    assert_eq!(token_balance(banks_client, &mint, &holder).await, 5);
+}
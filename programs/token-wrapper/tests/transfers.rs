@@ -0,0 +1,175 @@
+// Anchor's derives use cfgs that newer compilers warn about
+#![allow(unknown_lints, unexpected_cfgs)]
+use anchor_lang::{InstructionData, ToAccountMetas};
+use solana_program_test::{processor, ProgramTest};
+use solana_sdk::{
+    account::Account,
+    account_info::AccountInfo,
+    entrypoint::ProgramResult,
+    instruction::{AccountMeta, Instruction},
+    native_token::LAMPORTS_PER_SOL,
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+};
+use token_interface::InterfaceError;
+use token_program::TokenError;
+
+mod common;
+use common::*;
+
+fn set_reject_zero_amount(mint_authority: &Pubkey, reject: bool) -> Instruction {
+    Instruction {
+        program_id: token_program::id(),
+        accounts: token_program::accounts::ConfigureLedger {
+            mint_authority: *mint_authority,
+            ledger: ledger_key(),
+        }
+        .to_account_metas(None),
+        data: token_program::instruction::SetRejectZeroAmount { reject }.data(),
+    }
+}
+
+// An interface target whose preflight succeeds without setting return data
+fn process_silently(_: &Pubkey, _: &[AccountInfo], _: &[u8]) -> ProgramResult {
+    Ok(())
+}
+
+fn silent_program_id() -> Pubkey {
+    Pubkey::new_from_array([11; 32])
+}
+
+fn preflight_wrapper(owner: &Pubkey, mint: &Pubkey, hints: &[Pubkey]) -> Instruction {
+    let mut accounts = token_wrapper::accounts::ITransfer {
+        owner: *owner,
+        to: Pubkey::new_unique(),
+        authority: *owner,
+        mint: *mint,
+    }
+    .to_account_metas(None);
+    accounts.extend(
+        hints
+            .iter()
+            .map(|hint| AccountMeta::new_readonly(*hint, false)),
+    );
+    Instruction {
+        program_id: token_wrapper::id(),
+        accounts,
+        data: token_wrapper::instruction::PreflightTransfer { amount: 1 }.data(),
+    }
+}
+
+#[tokio::test]
+async fn wrapper_preflight_needs_the_target_payload() {
+    let mut program_test = ProgramTest::default();
+    program_test.add_program("silent", silent_program_id(), processor!(process_silently));
+    let mut context = setup_with(program_test, 1_000).await;
+    let (banks_client, payer) = (&mut context.banks_client, &context.payer);
+
+    let result = process(
+        banks_client,
+        payer,
+        &[],
+        preflight_wrapper(&payer.pubkey(), &silent_program_id(), &[]),
+    )
+    .await;
+    assert_token_error(result, InterfaceError::ReturnDataMissing);
+}
+
+#[tokio::test]
+async fn wrapper_preflight_needs_the_payload_for_mint_state_accounts() {
+    let mint_state = Pubkey::new_unique();
+    let mut program_test = ProgramTest::default();
+    program_test.add_program("silent", silent_program_id(), processor!(process_silently));
+    program_test.add_account(
+        mint_state,
+        Account::new(LAMPORTS_PER_SOL, 8, &silent_program_id()),
+    );
+    let mut context = setup_with(program_test, 1_000).await;
+    let (banks_client, payer) = (&mut context.banks_client, &context.payer);
+
+    // The wrapper finds the target through the hinted program owning `mint`
+    let result = process(
+        banks_client,
+        payer,
+        &[],
+        preflight_wrapper(&payer.pubkey(), &mint_state, &[silent_program_id()]),
+    )
+    .await;
+    assert_token_error(result, InterfaceError::ReturnDataMissing);
+}
+
+#[tokio::test]
+async fn wrapper_self_transfers_change_nothing() {
+    let mut context = setup(1_000).await;
+    let (banks_client, payer) = (&mut context.banks_client, &context.payer);
+
+    let payload = preflight(banks_client, payer, payer, &payer.pubkey(), 400).await;
+    assert!(payload.funding().is_none());
+    transfer(banks_client, payer, payer, &payer.pubkey(), 400)
+        .await
+        .unwrap();
+    assert_eq!(balance(banks_client, &payer.pubkey()).await, Some(1_000));
+
+    let result = transfer(banks_client, payer, payer, &payer.pubkey(), 1_001).await;
+    assert_token_error(result, TokenError::InsufficientFunds);
+    let stranger = Keypair::new();
+    let result = transfer(banks_client, payer, &stranger, &stranger.pubkey(), 0).await;
+    assert_token_error(result, TokenError::UnknownHolder);
+    assert_eq!(balance(banks_client, &stranger.pubkey()).await, None);
+}
+
+#[tokio::test]
+async fn wrapper_zero_amount_transfers_add_no_holders() {
+    let mut context = setup(1_000).await;
+    let (banks_client, payer) = (&mut context.banks_client, &context.payer);
+    let recipient = Pubkey::new_unique();
+
+    let payload = preflight(banks_client, payer, payer, &recipient, 0).await;
+    assert!(payload.funding().is_none());
+    transfer(banks_client, payer, payer, &recipient, 0)
+        .await
+        .unwrap();
+    assert_eq!(balance(banks_client, &recipient).await, None);
+    assert_eq!(balance(banks_client, &payer.pubkey()).await, Some(1_000));
+
+    // Only the mint authority picks the behaviour
+    let outsider = Keypair::new();
+    let result = process(
+        banks_client,
+        payer,
+        &[&outsider],
+        set_reject_zero_amount(&outsider.pubkey(), true),
+    )
+    .await;
+    assert_token_error(result, TokenError::Unauthorized);
+    process(
+        banks_client,
+        payer,
+        &[],
+        set_reject_zero_amount(&payer.pubkey(), true),
+    )
+    .await
+    .unwrap();
+    let result = transfer(banks_client, payer, payer, &Pubkey::new_unique(), 0).await;
+    assert_token_error(result, TokenError::ZeroAmount);
+
+    // Non-zero amounts are unaffected
+    transfer(banks_client, payer, payer, &recipient, 5)
+        .await
+        .unwrap();
+    assert_eq!(balance(banks_client, &recipient).await, Some(5));
+
+    process(
+        banks_client,
+        payer,
+        &[],
+        set_reject_zero_amount(&payer.pubkey(), false),
+    )
+    .await
+    .unwrap();
+    let other = Pubkey::new_unique();
+    transfer(banks_client, payer, payer, &other, 0)
+        .await
+        .unwrap();
+    assert_eq!(balance(banks_client, &other).await, None);
+}
@@ -0,0 +1,91 @@
+import * as anchor from "@coral-xyz/anchor";
+import { PublicKey } from "@solana/web3.js";
+
+// Client-side decoder for `token_interface::InterfaceTransfer` events
+
+export enum TransferRoute {
+  Direct = 0,
+  SplToken = 1,
+  Interface = 2,
+  TokenMetadata = 3,
+}
+
+export type InterfaceTransfer = {
+  program: PublicKey;
+  mint: PublicKey;
+  owner: PublicKey;
+  to: PublicKey;
+  authority: PublicKey;
+  amount: anchor.BN;
+  route: TransferRoute;
+};
+
+const PROGRAM_DATA = "Program data: ";
+const DISCRIMINATOR = Buffer.from(
+  anchor.utils.sha256.hash("event:InterfaceTransfer"),
+  "hex"
+).subarray(0, 8);
+
+export function decodeInterfaceTransfer(
+  data: Buffer
+): InterfaceTransfer | null {
+  if (!data.subarray(0, 8).equals(DISCRIMINATOR)) {
+    return null;
+  }
+  const key = (i: number) =>
+    new PublicKey(data.subarray(8 + i * 32, 8 + (i + 1) * 32));
+  return {
+    program: key(0),
+    mint: key(1),
+    owner: key(2),
+    to: key(3),
+    authority: key(4),
+    amount: new anchor.BN(data.subarray(168, 176), "le"),
+    route: data[176],
+  };
+}
+
+export type EmittedTransfer = InterfaceTransfer & { emitter: PublicKey };
+
+const LOG_TRUNCATED = "Log truncated";
+// The runtime's `Program <id> <status>` lines
+const PROGRAM_STATUS = /^Program ([1-9A-HJ-NP-Za-km-z]{32,44}) (.*)$/;
+
+// Returns every `InterfaceTransfer` emitted in a transaction's logs, along with
+// the program that emitted it. Any program can log bytes that decode as an
+// event, so `Direct` events only count when their `program` emitted them.
+// Callers should check the emitter of wrapped routes against trusted wrappers.
+export function interfaceTransfersFromLogs(logs: string[]): EmittedTransfer[] {
+  const invocations: PublicKey[] = [];
+  const events: EmittedTransfer[] = [];
+  for (const log of logs) {
+    if (log.startsWith(PROGRAM_DATA)) {
+      const event = decodeInterfaceTransfer(
+        Buffer.from(log.slice(PROGRAM_DATA.length), "base64")
+      );
+      const emitter = invocations[invocations.length - 1];
+      if (
+        event !== null &&
+        emitter !== undefined &&
+        (event.route !== TransferRoute.Direct || event.program.equals(emitter))
+      ) {
+        events.push({ ...event, emitter });
+      }
+      continue;
+    }
+    if (log === LOG_TRUNCATED) {
+      // The emitters of later lines can't be told apart anymore
+      break;
+    }
+    const [, program, status] = log.match(PROGRAM_STATUS) ?? [];
+    if (status === undefined) {
+      continue;
+    }
+    if (status.startsWith("invoke [")) {
+      invocations.push(new PublicKey(program));
+    } else if (status === "success" || status.startsWith("failed")) {
+      invocations.pop();
+    }
+  }
+  return events;
+}
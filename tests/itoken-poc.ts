@@ -23,14 +23,22 @@ import {
   TransactionInstruction,
 } from "@solana/web3.js";
 import { base64 } from "@coral-xyz/anchor/dist/cjs/utils/bytes";
+import { assert } from "chai";
 
 import { DEFAULT_PASS_RULESET, create, mintPnft } from "./pnft";
+import {
+  EXTENSIONS_HINT,
+  PayerRole,
+  PreflightPayload,
+  decodePreflightPayload,
+} from "./preflight";
+import { TransferRoute, interfaceTransfersFromLogs } from "./events";
 import { Metadata } from "@metaplex-foundation/mpl-token-metadata";
 
-async function resolveRemainingAccounts<I extends anchor.Idl>(
+async function simulatePreflight<I extends anchor.Idl>(
   program: anchor.Program<I>,
   instructions: TransactionInstruction[]
-): Promise<AccountMeta[]> {
+): Promise<PreflightPayload> {
   // Simulate transaction
   let message = MessageV0.compile({
     payerKey: program.provider.publicKey!,
@@ -55,28 +63,14 @@ async function resolveRemainingAccounts<I extends anchor.Idl>(
   // ===============================================================
   let logs = simulationResult.value.logs;
   let b64Data = logs[logs.length - 2].split(" ")[3];
-  let data = base64.decode(b64Data);
-
-  // We start deserializing the Vec<IAccountMeta> from the 5th byte
-  // The first 4 bytes are u32 for the Vec of the return data
-  let numBytes = data.slice(0, 4);
-  let numMetas = new anchor.BN(numBytes, null, "le");
-  let offset = 4;
-
-  let realAccountMetas: AccountMeta[] = [];
-  let coder = program.coder.types;
-  const metaSize = 34;
-  for (let i = 0; i < numMetas.toNumber(); i += 1) {
-    const start = offset + i * metaSize;
-    const end = start + metaSize;
-    let meta = coder.decode("ExternalIAccountMeta", data.slice(start, end));
-    realAccountMetas.push({
-      pubkey: meta.pubkey,
-      isWritable: meta.writable,
-      isSigner: meta.signer,
-    });
-  }
-  return realAccountMetas;
+  return decodePreflightPayload(Buffer.from(base64.decode(b64Data)));
+}
+
+async function resolveRemainingAccounts<I extends anchor.Idl>(
+  program: anchor.Program<I>,
+  instructions: TransactionInstruction[]
+): Promise<AccountMeta[]> {
+  return (await simulatePreflight(program, instructions)).accounts;
 }
 
 describe("itoken-poc", () => {
@@ -91,6 +85,23 @@ describe("itoken-poc", () => {
     const wrapper = anchor.workspace.TokenWrapper as Program<TokenWrapper>;
     const tokenkeg = TOKEN_PROGRAM_ID;
     const iProgram = anchor.workspace.TokenProgram as Program<TokenProgram>;
+    // iProgram hosts one ledger per mint, found from the mint's state account
+    const iMintId = Keypair.generate().publicKey;
+    const [iMint] = PublicKey.findProgramAddressSync(
+      [Buffer.from("mint"), iMintId.toBuffer()],
+      iProgram.programId
+    );
+    const [iLedger] = PublicKey.findProgramAddressSync(
+      [Buffer.from("ledger"), iMintId.toBuffer()],
+      iProgram.programId
+    );
+    // The wrapper calls the program owning a mint-state account, so it is
+    // passed along as a hint
+    const iProgramHint: AccountMeta = {
+      pubkey: iProgram.programId,
+      isSigner: false,
+      isWritable: false,
+    };
 
     // Tokenkeg
     let destination: PublicKey = Keypair.generate().publicKey;
@@ -104,9 +115,15 @@ describe("itoken-poc", () => {
     it("Can initialize a interface program", async () => {
       // Add your test here.
       let tx = await iProgram.methods
-        .initializeMint(new anchor.BN(10))
+        .initializeMint(
+          iMintId,
+          { name: "iToken", symbol: "ITK", decimals: 0 },
+          new anchor.BN(10)
+        )
         .accounts({
           authority: wallet,
+          mint: iMint,
+          ledger: iLedger,
         })
         .rpc({ skipPreflight: true });
       console.log("Initialized iProgram", tx);
@@ -162,9 +179,9 @@ describe("itoken-poc", () => {
           to: destination,
           owner: wallet,
           authority: wallet,
-          mint: iProgram.programId,
+          mint: iMint,
         })
-        .remainingAccounts([])
+        .remainingAccounts([iProgramHint])
         .instruction();
 
       let keys = await resolveRemainingAccounts(wrapper, [
@@ -177,12 +194,157 @@ describe("itoken-poc", () => {
           owner: wallet,
           to: destination,
           authority: wallet,
-          mint: iProgram.programId,
+          mint: iMint,
         })
         .remainingAccounts(keys)
         .rpc({ skipPreflight: true });
       console.log("Transferred iProgram with wrapper", tx);
     });
+    it("Grows the iProgram ledger past its initial capacity", async () => {
+      for (let i = 0; i < 6; i += 1) {
+        const recipient = Keypair.generate().publicKey;
+        const preflightInstruction = await wrapper.methods
+          .preflightTransfer(new anchor.BN(1))
+          .accounts({
+            to: recipient,
+            owner: wallet,
+            authority: wallet,
+            mint: iMint,
+          })
+          .remainingAccounts([iProgramHint])
+          .instruction();
+        let keys = await resolveRemainingAccounts(wrapper, [
+          preflightInstruction,
+        ]);
+
+        await wrapper.methods
+          .transfer(new anchor.BN(1))
+          .accounts({
+            owner: wallet,
+            to: recipient,
+            authority: wallet,
+            mint: iMint,
+          })
+          .remainingAccounts(keys)
+          .rpc({ commitment: "confirmed" });
+      }
+    });
+    it("Previews iProgram balances when the ledger is hinted", async () => {
+      const recipient = Keypair.generate().publicKey;
+      const preflightInstruction = await wrapper.methods
+        .preflightTransfer(new anchor.BN(2))
+        .accounts({
+          to: recipient,
+          owner: wallet,
+          authority: wallet,
+          mint: iMint,
+        })
+        .remainingAccounts([
+          iProgramHint,
+          { pubkey: iLedger, isSigner: false, isWritable: false },
+          EXTENSIONS_HINT,
+        ])
+        .instruction();
+
+      let payload = await simulatePreflight(wrapper, [preflightInstruction]);
+      assert.equal(payload.preview.deltas.length, 2);
+      assert.ok(payload.preview.deltas[0].owner.equals(wallet));
+      assert.equal(payload.preview.deltas[0].delta.toNumber(), -2);
+      assert.ok(payload.preview.deltas[1].owner.equals(recipient));
+      assert.equal(payload.preview.deltas[1].delta.toNumber(), 2);
+      assert.equal(payload.preview.fee.toNumber(), 0);
+    });
+    it("Only accepts iProgram transfers through an allowed router", async () => {
+      const ledger = iLedger;
+      const [routing] = PublicKey.findProgramAddressSync(
+        [Buffer.from("routing"), ledger.toBuffer()],
+        iProgram.programId
+      );
+      await iProgram.methods
+        .initializeRouting([wrapper.programId])
+        .accounts({ authority: wallet, ledger, routing })
+        .rpc({ commitment: "confirmed" });
+
+      const preflightInstruction = await iProgram.methods
+        .preflightTransfer(new anchor.BN(1))
+        .accounts({
+          to: destination,
+          owner: wallet,
+          authority: wallet,
+          mint: iMint,
+        })
+        .remainingAccounts([
+          { pubkey: routing, isSigner: false, isWritable: false },
+          EXTENSIONS_HINT,
+        ])
+        .instruction();
+      let payload = await simulatePreflight(iProgram, [preflightInstruction]);
+      assert.equal(payload.routers.length, 1);
+      assert.ok(payload.routers[0].equals(wrapper.programId));
+
+      // Calling iProgram directly is rejected
+      try {
+        await iProgram.methods
+          .transfer(new anchor.BN(1))
+          .accounts({
+            owner: wallet,
+            to: destination,
+            authority: wallet,
+            mint: iMint,
+            ledger,
+            routing,
+          })
+          .remainingAccounts(payload.accounts.slice(2))
+          .rpc({ commitment: "confirmed" });
+        assert.fail("transfer should have failed");
+      } catch (e) {
+        assert.include(e.logs.join("\n"), "RouterRequired");
+      }
+
+      // Going through the wrapper works
+      const wrapperPreflight = await wrapper.methods
+        .preflightTransfer(new anchor.BN(1))
+        .accounts({
+          to: destination,
+          owner: wallet,
+          authority: wallet,
+          mint: iMint,
+        })
+        .remainingAccounts([iProgramHint])
+        .instruction();
+      let keys = await resolveRemainingAccounts(wrapper, [wrapperPreflight]);
+      await wrapper.methods
+        .transfer(new anchor.BN(1))
+        .accounts({
+          owner: wallet,
+          to: destination,
+          authority: wallet,
+          mint: iMint,
+        })
+        .remainingAccounts(keys)
+        .rpc({ commitment: "confirmed" });
+
+      await iProgram.methods
+        .setRouters([])
+        .accounts({ authority: wallet, ledger, routing })
+        .rpc({ commitment: "confirmed" });
+    });
+    it("Rejects routing a transfer back into the wrapper", async () => {
+      try {
+        await wrapper.methods
+          .transfer(new anchor.BN(1))
+          .accounts({
+            owner: wallet,
+            to: destination,
+            authority: wallet,
+            mint: wrapper.programId,
+          })
+          .rpc({ commitment: "confirmed" });
+        assert.fail("transfer should have failed");
+      } catch (e) {
+        assert.include(e.logs.join("\n"), "Reentrancy");
+      }
+    });
     it("Can initialize a pnft", async () => {
       let {
         metadata: metadataKey,
@@ -261,17 +423,7 @@ describe("itoken-poc", () => {
       }
     });
     it("Can transfer tokenkeg using wrapper", async () => {
-      // TODO(ngundotra): do this in the wrapper
-      let preInstructions = [
-        createAssociatedTokenAccountInstruction(
-          wallet,
-          destinationAta,
-          destination,
-          tokenMint,
-          tokenkeg
-        ),
-      ];
-
+      // The wrapper creates the destination ATA, funded by the authority
       let preflightInstruction = await wrapper.methods
         .preflightTransfer(new anchor.BN(1))
         .accounts({
@@ -281,15 +433,13 @@ describe("itoken-poc", () => {
           authority: wallet,
         })
         .remainingAccounts([])
-        .preInstructions([])
         .instruction();
 
       let keys = await resolveRemainingAccounts(wrapper, [
-        ...preInstructions,
         preflightInstruction,
       ]);
 
-      let instruction = await wrapper.methods
+      let tx = await wrapper.methods
         .transfer(new anchor.BN(1))
         .accounts({
           to: destination,
@@ -298,21 +448,118 @@ describe("itoken-poc", () => {
           authority: wallet,
         })
         .remainingAccounts(keys)
-        .preInstructions([])
-        .instruction();
-      let message = MessageV0.compile({
-        payerKey: wallet,
-        instructions: [...preInstructions, instruction],
-        recentBlockhash: (
-          await wrapper.provider.connection.getRecentBlockhash()
-        ).blockhash,
-      });
-      let transaction = new VersionedTransaction(message);
-      let tx = await wrapper.provider.sendAndConfirm(transaction, [], {
-        skipPreflight: true,
+        .rpc({ skipPreflight: true, commitment: "confirmed" });
+      console.log("Transferred spl token with wrapper", tx);
+
+      let txInfo = await wrapper.provider.connection.getTransaction(tx, {
+        commitment: "confirmed",
+        maxSupportedTransactionVersion: 0,
       });
+      let events = interfaceTransfersFromLogs(txInfo.meta.logMessages);
+      assert.equal(events.length, 1);
+      assert.equal(events[0].route, TransferRoute.SplToken);
+      assert.ok(events[0].emitter.equals(wrapper.programId));
+      assert.ok(events[0].program.equals(tokenkeg));
+      assert.ok(events[0].mint.equals(tokenMint));
+      assert.equal(events[0].amount.toNumber(), 1);
 
-      console.log("Transferred spl token with wrapper", tx);
+      let balance = await wrapper.provider.connection.getTokenAccountBalance(
+        destinationAta,
+        "confirmed"
+      );
+      assert.equal(balance.value.amount, "1");
+    });
+    it("Skips ATA creation when the destination is hinted", async () => {
+      let preflightInstruction = await wrapper.methods
+        .preflightTransfer(new anchor.BN(1))
+        .accounts({
+          to: destination,
+          owner: wallet,
+          mint: tokenMint,
+          authority: wallet,
+        })
+        .remainingAccounts([
+          { pubkey: destinationAta, isSigner: false, isWritable: false },
+        ])
+        .instruction();
+
+      let keys = await resolveRemainingAccounts(wrapper, [
+        preflightInstruction,
+      ]);
+      assert.equal(keys.length, 3);
+
+      let tx = await wrapper.methods
+        .transfer(new anchor.BN(1))
+        .accounts({
+          to: destination,
+          owner: wallet,
+          mint: tokenMint,
+          authority: wallet,
+        })
+        .remainingAccounts(keys)
+        .rpc({ skipPreflight: true, commitment: "confirmed" });
+      console.log("Transferred spl token to existing ATA with wrapper", tx);
+    });
+    it("Bills a separate payer for the destination ATA", async () => {
+      const payer = Keypair.generate();
+      const payerFunds = 1_000_000_000;
+      await wrapper.provider.sendAndConfirm(
+        new Transaction().add(
+          SystemProgram.transfer({
+            fromPubkey: wallet,
+            toPubkey: payer.publicKey,
+            lamports: payerFunds,
+          })
+        ),
+        [],
+        { commitment: "confirmed" }
+      );
+      const recipient = Keypair.generate().publicKey;
+
+      // Passing a writable payer after `mint` opts into the `ITransferWithPayer` layout
+      let preflightInstruction = await wrapper.methods
+        .preflightTransfer(new anchor.BN(1))
+        .accounts({
+          to: recipient,
+          owner: wallet,
+          mint: tokenMint,
+          authority: wallet,
+        })
+        .remainingAccounts([
+          { pubkey: payer.publicKey, isSigner: false, isWritable: true },
+          EXTENSIONS_HINT,
+        ])
+        .instruction();
+
+      let payload = await simulatePreflight(wrapper, [preflightInstruction]);
+      assert.equal(payload.funding.payer, PayerRole.Payer);
+      let payerMeta = payload.accounts.find((acc) =>
+        acc.pubkey.equals(payer.publicKey)
+      );
+      assert.ok(payerMeta.isSigner && payerMeta.isWritable);
+      assert.ok(payload.signers.some((key) => key.equals(payer.publicKey)));
+
+      let tx = await wrapper.methods
+        .transfer(new anchor.BN(1))
+        .accounts({
+          to: recipient,
+          owner: wallet,
+          mint: tokenMint,
+          authority: wallet,
+        })
+        .remainingAccounts(payload.accounts)
+        .signers([payer])
+        .rpc({ skipPreflight: true, commitment: "confirmed" });
+      console.log("Transferred spl token with a separate payer", tx);
+
+      let payerBalance = await wrapper.provider.connection.getBalance(
+        payer.publicKey,
+        "confirmed"
+      );
+      assert.equal(
+        payerFunds - payerBalance,
+        payload.funding.lamports.toNumber()
+      );
     });
   });
 });
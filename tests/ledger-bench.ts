@@ -0,0 +1,200 @@
+import * as anchor from "@coral-xyz/anchor";
+import { Program } from "@coral-xyz/anchor";
+import { TokenProgram } from "../target/types/token_program";
+import { InterfaceBench } from "../target/types/interface_bench";
+import {
+  Keypair,
+  LAMPORTS_PER_SOL,
+  MessageV0,
+  PublicKey,
+  TransactionInstruction,
+  VersionedTransaction,
+} from "@solana/web3.js";
+import { base64 } from "@coral-xyz/anchor/dist/cjs/utils/bytes";
+import { assert } from "chai";
+import { PreflightPayload, decodePreflightPayload } from "./preflight";
+
+// Compares `transfer` in a mint's ledger against balance mints, whose
+// balances live in per-owner accounts. Also compares the Borsh `HashMap`
+// ledger layout against the sorted one.
+
+// Reads the two `Program consumption: N units remaining` lines
+// logged around the measured code, and returns the units spent between them
+async function measuredUnits(
+  connection: anchor.web3.Connection,
+  txId: string
+): Promise<number> {
+  const tx = await connection.getTransaction(txId, {
+    commitment: "confirmed",
+    maxSupportedTransactionVersion: 0,
+  });
+  const remaining = tx.meta.logMessages
+    .filter((log) => log.includes("Program consumption:"))
+    .map((log) => parseInt(log.split(" ")[2]));
+  return remaining[0] - remaining[1];
+}
+
+describe("ledger-bench", () => {
+  anchor.setProvider(anchor.AnchorProvider.env());
+  const iProgram = anchor.workspace.TokenProgram as Program<TokenProgram>;
+  const connection = iProgram.provider.connection;
+  const wallet = iProgram.provider.publicKey!;
+  const ledgerMintId = Keypair.generate().publicKey;
+  const [ledgerMint] = PublicKey.findProgramAddressSync(
+    [Buffer.from("mint"), ledgerMintId.toBuffer()],
+    iProgram.programId
+  );
+  const [ledger] = PublicKey.findProgramAddressSync(
+    [Buffer.from("ledger"), ledgerMintId.toBuffer()],
+    iProgram.programId
+  );
+  const balanceMint = Keypair.generate();
+
+  function interfaceAccounts(
+    mint: PublicKey,
+    owner: PublicKey,
+    to: PublicKey
+  ): anchor.web3.AccountMeta[] {
+    return [
+      { pubkey: owner, isSigner: false, isWritable: false },
+      { pubkey: to, isSigner: false, isWritable: false },
+      { pubkey: owner, isSigner: true, isWritable: false },
+      { pubkey: mint, isSigner: false, isWritable: false },
+    ];
+  }
+
+  async function preflight(
+    mint: PublicKey,
+    owner: PublicKey,
+    to: PublicKey
+  ): Promise<PreflightPayload> {
+    const instruction = new TransactionInstruction({
+      programId: iProgram.programId,
+      keys: interfaceAccounts(mint, owner, to),
+      data: iProgram.coder.instruction.encode("preflightTransfer", {
+        amount: new anchor.BN(1),
+      }),
+    });
+    const message = MessageV0.compile({
+      payerKey: wallet,
+      instructions: [instruction],
+      recentBlockhash: (await connection.getLatestBlockhash()).blockhash,
+    });
+    const simulation = await connection.simulateTransaction(
+      new VersionedTransaction(message),
+      { sigVerify: false, commitment: "confirmed" }
+    );
+    const logs = simulation.value.logs;
+    const b64Data = logs[logs.length - 2].split(" ")[3];
+    return decodePreflightPayload(Buffer.from(base64.decode(b64Data)));
+  }
+
+  // Sends a transfer of 1 with the accounts preflight asked for, and returns
+  // the units it consumed and whether it write-locked the ledger
+  async function transfer(
+    mint: PublicKey,
+    owner: Keypair | null,
+    to: PublicKey
+  ): Promise<{ units: number; locksLedger: boolean }> {
+    const ownerKey = owner ? owner.publicKey : wallet;
+    const payload = await preflight(mint, ownerKey, to);
+    const instruction = new TransactionInstruction({
+      programId: iProgram.programId,
+      keys: interfaceAccounts(mint, ownerKey, to).concat(payload.accounts),
+      data: iProgram.coder.instruction.encode("transfer", {
+        amount: new anchor.BN(1),
+      }),
+    });
+    const tx = new anchor.web3.Transaction().add(instruction);
+    const txId = await iProgram.provider.sendAndConfirm(
+      tx,
+      owner ? [owner] : [],
+      { commitment: "confirmed" }
+    );
+    const confirmed = await connection.getTransaction(txId, {
+      commitment: "confirmed",
+      maxSupportedTransactionVersion: 0,
+    });
+    const message = confirmed.transaction.message;
+    const index = message.staticAccountKeys.findIndex((key) =>
+      key.equals(ledger)
+    );
+    return {
+      units: confirmed.meta.computeUnitsConsumed,
+      locksLedger: index >= 0 && message.isAccountWritable(index),
+    };
+  }
+
+  before(async () => {
+    await iProgram.methods
+      .initializeMint(
+        ledgerMintId,
+        { name: "Bench", symbol: "BNCH", decimals: 0 },
+        new anchor.BN(100)
+      )
+      .accounts({ authority: wallet, mint: ledgerMint, ledger })
+      .rpc({ commitment: "confirmed" });
+
+    const [balance] = PublicKey.findProgramAddressSync(
+      [
+        Buffer.from("balance"),
+        balanceMint.publicKey.toBuffer(),
+        wallet.toBuffer(),
+      ],
+      iProgram.programId
+    );
+    await iProgram.methods
+      .initializeBalanceMint(new anchor.BN(100))
+      .accounts({
+        authority: wallet,
+        mint: balanceMint.publicKey,
+        balance,
+      })
+      .signers([balanceMint])
+      .rpc({ commitment: "confirmed" });
+  });
+
+  for (const mode of ["ledger", "balances"]) {
+    it(`Transfers with ${mode}`, async () => {
+      const mint = mode === "ledger" ? ledgerMint : balanceMint.publicKey;
+      const holder = Keypair.generate();
+      await connection.confirmTransaction(
+        await connection.requestAirdrop(holder.publicKey, LAMPORTS_PER_SOL),
+        "confirmed"
+      );
+
+      const toNew = await transfer(mint, null, holder.publicKey);
+      const toExisting = await transfer(mint, holder, wallet);
+      console.log(
+        `${mode}: new holder ${toNew.units} CU, existing holder ${toExisting.units} CU`
+      );
+      // Only the ledger serializes transfers between unrelated holders
+      assert.equal(toNew.locksLedger, mode === "ledger");
+      assert.equal(toExisting.locksLedger, mode === "ledger");
+    });
+  }
+});
+
+describe("ledger-layout-bench", () => {
+  anchor.setProvider(anchor.AnchorProvider.env());
+  const bench = anchor.workspace.InterfaceBench as Program<InterfaceBench>;
+
+  // Larger ledgers run the legacy layout out of heap
+  for (const holders of [1, 10, 50]) {
+    it(`Transfers within ${holders} holders`, async () => {
+      const legacyTx = await bench.methods
+        .ledgerLegacy(holders)
+        .rpc({ commitment: "confirmed" });
+      const sortedTx = await bench.methods
+        .ledgerSorted(holders)
+        .rpc({ commitment: "confirmed" });
+
+      const legacy = await measuredUnits(bench.provider.connection, legacyTx);
+      const sorted = await measuredUnits(bench.provider.connection, sortedTx);
+      console.log(
+        `${holders} holders: legacy ${legacy} CU, sorted ${sorted} CU`
+      );
+      assert.isAtMost(sorted, legacy);
+    });
+  }
+});
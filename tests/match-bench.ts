@@ -0,0 +1,124 @@
+import * as anchor from "@coral-xyz/anchor";
+import { Program } from "@coral-xyz/anchor";
+import { InterfaceBench } from "../target/types/interface_bench";
+import {
+  AccountMeta,
+  AddressLookupTableAccount,
+  AddressLookupTableProgram,
+  Keypair,
+  PublicKey,
+  TransactionInstruction,
+  TransactionMessage,
+  VersionedTransaction,
+} from "@solana/web3.js";
+import { assert } from "chai";
+
+// Reads the two `Program consumption: N units remaining` lines
+// logged around the measured code, and returns the units spent between them
+async function measuredUnits(
+  connection: anchor.web3.Connection,
+  txId: string
+): Promise<number> {
+  const tx = await connection.getTransaction(txId, {
+    commitment: "confirmed",
+    maxSupportedTransactionVersion: 0,
+  });
+  const remaining = tx.meta.logMessages
+    .filter((log) => log.includes("Program consumption:"))
+    .map((log) => parseInt(log.split(" ")[2]));
+  return remaining[0] - remaining[1];
+}
+
+// Legacy transactions fit about this many remaining accounts, so larger
+// benches pass theirs through an address lookup table
+const LEGACY_TX_ACCOUNTS = 30;
+
+describe("match-bench", () => {
+  anchor.setProvider(anchor.AnchorProvider.env());
+  const bench = anchor.workspace.InterfaceBench as Program<InterfaceBench>;
+  const provider = bench.provider as anchor.AnchorProvider;
+  const connection = provider.connection;
+  const wallet = provider.wallet.publicKey;
+
+  async function lookupTable(
+    addresses: PublicKey[]
+  ): Promise<AddressLookupTableAccount> {
+    const [create, table] = AddressLookupTableProgram.createLookupTable({
+      authority: wallet,
+      payer: wallet,
+      recentSlot: await connection.getSlot("finalized"),
+    });
+    await provider.sendAndConfirm(new anchor.web3.Transaction().add(create));
+    for (let i = 0; i < addresses.length; i += 20) {
+      const extend = AddressLookupTableProgram.extendLookupTable({
+        lookupTable: table,
+        authority: wallet,
+        payer: wallet,
+        addresses: addresses.slice(i, i + 20),
+      });
+      await provider.sendAndConfirm(new anchor.web3.Transaction().add(extend));
+    }
+    // Addresses can only be looked up from the slot after they were added
+    const extendedAt = await connection.getSlot("confirmed");
+    while ((await connection.getSlot("confirmed")) <= extendedAt) {
+      await new Promise((resolve) => setTimeout(resolve, 100));
+    }
+    return (await connection.getAddressLookupTable(table)).value;
+  }
+
+  // Sends `instruction` in a version 0 transaction that resolves its
+  // accounts through `table`
+  async function sendWithTable(
+    instruction: TransactionInstruction,
+    table: AddressLookupTableAccount
+  ): Promise<string> {
+    const message = new TransactionMessage({
+      payerKey: wallet,
+      recentBlockhash: (await connection.getLatestBlockhash()).blockhash,
+      instructions: [instruction],
+    }).compileToV0Message([table]);
+    return provider.sendAndConfirm(new VersionedTransaction(message), [], {
+      commitment: "confirmed",
+    });
+  }
+
+  // Sizes on both sides of `LINEAR_SCAN_LIMIT` (32)
+  for (const numAccounts of [1, 5, 15, 30, 33, 64]) {
+    it(`Matches ${numAccounts} accounts`, async () => {
+      const accounts: AccountMeta[] = [];
+      for (let i = 0; i < numAccounts; i += 1) {
+        accounts.push({
+          pubkey: Keypair.generate().publicKey,
+          isSigner: false,
+          isWritable: false,
+        });
+      }
+      // Request every account, in the reverse of the order they're passed in
+      const requested = Buffer.from(accounts.map((_, i) => i).reverse());
+
+      const table =
+        numAccounts > LEGACY_TX_ACCOUNTS
+          ? await lookupTable(accounts.map((acc) => acc.pubkey))
+          : null;
+      const send = async (
+        method: ReturnType<typeof bench.methods.matchAccounts>
+      ): Promise<string> => {
+        const withAccounts = method.remainingAccounts(accounts);
+        if (table) {
+          return sendWithTable(await withAccounts.instruction(), table);
+        }
+        return withAccounts.rpc({ commitment: "confirmed" });
+      };
+
+      const legacyTx = await send(bench.methods.matchLegacy(requested));
+      const currentTx = await send(bench.methods.matchAccounts(requested));
+
+      const legacy = await measuredUnits(connection, legacyTx);
+      const current = await measuredUnits(connection, currentTx);
+      console.log(
+        `${numAccounts} accounts: legacy ${legacy} CU, current ${current} CU`
+      );
+      assert.isAtMost(current, legacy);
+    });
+  }
+});
@@ -0,0 +1,145 @@
+import * as anchor from "@coral-xyz/anchor";
+import { AccountMeta, PublicKey } from "@solana/web3.js";
+
+// Client-side decoder for `token_interface::PreflightPayload`
+
+// Hint that opts in to extensions, `token_interface::EXTENSIONS_HINT`
+export const EXTENSIONS_HINT: AccountMeta = {
+  pubkey: new PublicKey("PreF1ightExtensions111111111111111111111111"),
+  isSigner: false,
+  isWritable: false,
+};
+
+export enum PayerRole {
+  Authority = 0,
+  Payer = 1,
+}
+
+export type FundingRequirement = {
+  lamports: anchor.BN;
+  payer: PayerRole;
+};
+
+export type BalanceDelta = {
+  asset: PublicKey;
+  owner: PublicKey;
+  delta: anchor.BN;
+};
+
+export type BalancePreview = {
+  deltas: BalanceDelta[];
+  fee: anchor.BN;
+};
+
+export type PreflightPayload = {
+  accounts: AccountMeta[];
+  funding?: FundingRequirement;
+  preview?: BalancePreview;
+  // Accounts that must sign the transaction, already flagged in `accounts`
+  signers: PublicKey[];
+  // Programs that `transfer` must be called through
+  routers?: PublicKey[];
+};
+
+// `solana_program::program::MAX_RETURN_DATA`
+const MAX_RETURN_DATA = 1024;
+
+class Reader {
+  offset = 0;
+  constructor(readonly data: Buffer, readonly end = data.length) {}
+
+  done(): boolean {
+    return this.offset >= this.end;
+  }
+  u8(): number {
+    return this.data[this.offset++];
+  }
+  bool(): boolean {
+    return this.u8() !== 0;
+  }
+  u32(): number {
+    const value = this.data.readUInt32LE(this.offset);
+    this.offset += 4;
+    return value;
+  }
+  u64(): anchor.BN {
+    return this.bytes(8, (b) => new anchor.BN(b, "le"));
+  }
+  i128(): anchor.BN {
+    return this.bytes(16, (b) => new anchor.BN(b, "le").fromTwos(128));
+  }
+  pubkey(): PublicKey {
+    return this.bytes(32, (b) => new PublicKey(b));
+  }
+  vec<T>(item: () => T): T[] {
+    const len = this.u32();
+    const items: T[] = [];
+    for (let i = 0; i < len; i += 1) {
+      items.push(item());
+    }
+    return items;
+  }
+  private bytes<T>(len: number, f: (b: Buffer) => T): T {
+    const value = f(this.data.subarray(this.offset, this.offset + len));
+    this.offset += len;
+    return value;
+  }
+}
+
+// Like `token_interface::decode_preflight_return_data`, pads back the trailing
+// zeros that simulations strip from return data
+export function decodePreflightPayload(data: Buffer): PreflightPayload {
+  const padded = Buffer.alloc(Math.max(data.length, MAX_RETURN_DATA));
+  data.copy(padded);
+  const reader = new Reader(padded, data.length);
+  const payload: PreflightPayload = {
+    accounts: reader.vec(() => ({
+      pubkey: reader.pubkey(),
+      isSigner: reader.bool(),
+      isWritable: reader.bool(),
+    })),
+    signers: [],
+  };
+  if (!reader.done()) {
+    decodeExtensions(reader, payload);
+  }
+
+  // Wallets must collect a signature for every listed signer
+  for (const account of payload.accounts) {
+    if (payload.signers.some((signer) => signer.equals(account.pubkey))) {
+      account.isSigner = true;
+    } else if (account.isSigner) {
+      payload.signers.push(account.pubkey);
+    }
+  }
+  return payload;
+}
+
+function decodeExtensions(reader: Reader, payload: PreflightPayload) {
+  reader.vec(() => {
+    const variant = reader.u8();
+    switch (variant) {
+      case 0:
+        payload.funding = { lamports: reader.u64(), payer: reader.u8() };
+        break;
+      case 1:
+        payload.preview = {
+          deltas: reader.vec(() => ({
+            asset: reader.pubkey(),
+            owner: reader.pubkey(),
+            delta: reader.i128(),
+          })),
+          fee: reader.u64(),
+        };
+        break;
+      case 2:
+        payload.signers.push(...reader.vec(() => reader.pubkey()));
+        break;
+      case 3:
+        payload.routers = reader.vec(() => reader.pubkey());
+        break;
+      default:
+        throw new Error("Unknown preflight extension: " + variant);
+    }
+  });
+}
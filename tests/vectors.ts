@@ -0,0 +1,75 @@
+import * as anchor from "@coral-xyz/anchor";
+import { expect } from "chai";
+import * as fs from "fs";
+import * as path from "path";
+import { decodePreflightPayload } from "./preflight";
+
+// Checks the TypeScript decoder against the Rust crate's golden test vectors
+
+type Vector = { name: string; hex: string };
+
+const vectors = JSON.parse(
+  fs.readFileSync(
+    path.join(__dirname, "../token-interface/tests/fixtures/test_vectors.json"),
+    "utf8"
+  )
+);
+
+function discriminator(name: string): string {
+  return anchor.utils.sha256.hash("global:" + name).slice(0, 16);
+}
+
+describe("test vectors", () => {
+  it("Derives the same discriminators", () => {
+    expect(vectors.discriminators.preflight_transfer).to.equal(
+      discriminator("preflight_transfer")
+    );
+    expect(vectors.discriminators.transfer).to.equal(discriminator("transfer"));
+  });
+
+  it("Decodes every payload", () => {
+    for (const vector of vectors.payloads as Vector[]) {
+      const data = Buffer.from(vector.hex, "hex");
+      const payload = decodePreflightPayload(data);
+      const count = data.readUInt32LE(0);
+      expect(payload.accounts.length, vector.name).to.equal(count);
+      if (vector.name === "max_size") {
+        expect(data.length).to.equal(4 + count * vectors.iaccount_meta_size);
+      }
+      if (vector.name === "all_extensions") {
+        expect(payload.funding).to.not.be.undefined;
+        expect(payload.preview).to.not.be.undefined;
+        expect(payload.routers).to.have.length(1);
+        expect(payload.signers).to.have.length(1);
+      }
+    }
+  });
+
+  it("Decodes payloads without their trailing zeros", () => {
+    for (const vector of vectors.payloads as Vector[]) {
+      const data = Buffer.from(vector.hex, "hex");
+      let len = data.length;
+      while (len > 0 && data[len - 1] === 0) {
+        len -= 1;
+      }
+      const full = decodePreflightPayload(data);
+      const truncated = decodePreflightPayload(data.subarray(0, len));
+      expect(
+        truncated.accounts.map((acc) => [
+          acc.pubkey.toBase58(),
+          acc.isSigner,
+          acc.isWritable,
+        ]),
+        vector.name
+      ).to.deep.equal(
+        full.accounts.map((acc) => [
+          acc.pubkey.toBase58(),
+          acc.isSigner,
+          acc.isWritable,
+        ])
+      );
+      expect(truncated.funding, vector.name).to.deep.equal(full.funding);
+      expect(truncated.preview, vector.name).to.deep.equal(full.preview);
+    }
+  });
+});
@@ -0,0 +1,473 @@
+// Checks a program's `transfer` interface against the spec, by running it
+// in a local `solana-program-test` bank and reporting which checks passed.
+pub use solana_program_test;
+
+use std::fmt;
+
+use anchor_lang::prelude::AnchorSerialize;
+use anchor_lang::solana_program::{hash, program::MAX_RETURN_DATA};
+use solana_program_test::{BanksClient, BanksClientError};
+use solana_sdk::{
+    instruction::{AccountMeta, Instruction, InstructionError},
+    pubkey::Pubkey,
+    signature::{Keypair, Signer},
+    transaction::{Transaction, TransactionError},
+};
+use token_interface::test_vectors::{PREFLIGHT_TRANSFER_DISCRIMINATOR, TRANSFER_DISCRIMINATOR};
+use token_interface::{decode_preflight_return_data, PreflightPayload, EXTENSIONS_HINT};
+
+// Wraps an Anchor program's `entry` so it can be passed to `ProgramTest::add_program`.
+// Anchor ties the accounts to the slice holding them, which the runtime's
+// entrypoint type doesn't, so each account list is leaked for the rest of the test.
+#[macro_export]
+macro_rules! anchor_processor {
+    ($entry:path) => {
+        $crate::solana_program_test::processor!(|program_id, accounts, data| {
+            let accounts = Box::leak(Box::new(accounts.to_vec()));
+            $entry(program_id, accounts, data)
+        })
+    };
+}
+
+// The checks run by `Conformance::run`, in order
+pub const CHECKS: [&str; 9] = [
+    "discriminators",
+    "return data shape",
+    "no signer requests",
+    "stable ordering",
+    "extra accounts",
+    "zero amount",
+    "self transfer",
+    "overflow",
+    "legacy encoding",
+];
+
+#[derive(Debug, Clone)]
+pub struct Check {
+    pub name: &'static str,
+    /// Why the check failed
+    pub result: Result<(), String>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Report {
+    pub program_id: Pubkey,
+    pub checks: Vec<Check>,
+}
+
+impl Report {
+    pub fn passed(&self) -> bool {
+        self.checks.iter().all(|check| check.result.is_ok())
+    }
+
+    pub fn failures(&self) -> impl Iterator<Item = &Check> {
+        self.checks.iter().filter(|check| check.result.is_err())
+    }
+
+    // Panics with the full report unless every check passed
+    pub fn assert_passed(&self) {
+        assert!(self.passed(), "{}", self);
+    }
+
+    fn record(&mut self, name: &'static str, result: Result<(), String>) {
+        self.checks.push(Check { name, result });
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "Conformance report for {}", self.program_id)?;
+        for check in self.checks.iter() {
+            match &check.result {
+                Ok(()) => writeln!(f, "  [PASS] {}", check.name)?,
+                Err(reason) => writeln!(f, "  [FAIL] {}: {}", check.name, reason)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+// Runs the conformance checks against `program_id`, transferring from `authority`
+// as both owner and authority, through the `mint` account the program expects.
+// `authority` pays for every transaction and must hold at least `amount` tokens
+// for a few transfers, each to a new recipient.
+//
+// Native processors can't recover from panics, so a program that panics on
+// bad input aborts the test instead of failing a check.
+// Set `SBF_OUT_DIR` to run the checks against a program's build instead.
+#[derive(Debug, Clone)]
+pub struct Conformance {
+    program_id: Pubkey,
+    mint: Pubkey,
+    amount: u64,
+    hints: Vec<Pubkey>,
+}
+
+impl Conformance {
+    pub fn new(program_id: Pubkey, mint: Pubkey) -> Self {
+        Self {
+            program_id,
+            mint,
+            amount: 1,
+            hints: vec![],
+        }
+    }
+
+    pub fn with_amount(mut self, amount: u64) -> Self {
+        self.amount = amount;
+        self
+    }
+
+    // Hints passed to every preflight, e.g. the program owning a mint-state `mint`
+    pub fn with_hints(mut self, hints: Vec<Pubkey>) -> Self {
+        self.hints = hints;
+        self
+    }
+
+    pub async fn run(&self, banks_client: &mut BanksClient, authority: &Keypair) -> Report {
+        let mut harness = Harness {
+            conformance: self,
+            banks_client,
+            authority,
+        };
+        let mut report = Report {
+            program_id: self.program_id,
+            checks: Vec::with_capacity(CHECKS.len()),
+        };
+
+        // Everything else depends on a successful preflight
+        let to = Pubkey::new_unique();
+        let preflight = harness.preflight(&to, self.amount, &[]).await;
+        report.record(CHECKS[0], harness.check_discriminators(&preflight).await);
+        let decoded =
+            preflight.and_then(|data| decode_payload(&data).map(|payload| (data, payload)));
+        report.record(
+            CHECKS[1],
+            decoded.as_ref().map(|_| ()).map_err(|e| e.clone()),
+        );
+        let (data, payload) = match decoded {
+            Ok(decoded) => decoded,
+            Err(_) => {
+                for name in CHECKS[2..].iter() {
+                    report.record(name, Err("needs a valid preflight".to_string()));
+                }
+                return report;
+            }
+        };
+
+        report.record(CHECKS[2], harness.check_signers(&payload));
+        report.record(
+            CHECKS[3],
+            harness.check_ordering(&to, &data, &payload).await,
+        );
+        report.record(CHECKS[4], harness.check_extra_accounts().await);
+        report.record(CHECKS[5], harness.check_zero_amount().await);
+        report.record(CHECKS[6], harness.check_self_transfer().await);
+        report.record(CHECKS[7], harness.check_overflow().await);
+        report.record(CHECKS[8], harness.check_legacy_encoding().await);
+        report
+    }
+}
+
+// Decodes `preflight_transfer` return data as reported by the bank.
+// Fails unless `data` is exactly one payload that fits in return data.
+pub fn decode_payload(data: &[u8]) -> Result<PreflightPayload, String> {
+    if data.len() > MAX_RETURN_DATA {
+        return Err(format!(
+            "return data is {} bytes, over the {} byte limit",
+            data.len(),
+            MAX_RETURN_DATA
+        ));
+    }
+    decode_preflight_return_data(data)
+        .map_err(|e| format!("return data is not a PreflightPayload: {}", e))
+}
+
+fn sighash(name: &str) -> Vec<u8> {
+    hash::hash(format!("global:{}", name).as_bytes()).to_bytes()[..8].to_vec()
+}
+
+// Whether the program failed cleanly with an error of its own
+fn is_program_error(err: &BanksClientError) -> bool {
+    matches!(
+        err,
+        BanksClientError::TransactionError(TransactionError::InstructionError(
+            _,
+            InstructionError::Custom(_)
+        )) | BanksClientError::SimulationError {
+            err: TransactionError::InstructionError(_, InstructionError::Custom(_)),
+            ..
+        }
+    )
+}
+
+struct Harness<'a> {
+    conformance: &'a Conformance,
+    banks_client: &'a mut BanksClient,
+    authority: &'a Keypair,
+}
+
+impl Harness<'_> {
+    // `ITransfer` accounts, followed by `extra`
+    fn instruction(
+        &self,
+        discriminator: &[u8],
+        to: &Pubkey,
+        amount: u64,
+        extra: Vec<AccountMeta>,
+    ) -> Instruction {
+        let authority = self.authority.pubkey();
+        let mut accounts = vec![
+            AccountMeta::new_readonly(authority, false),
+            AccountMeta::new_readonly(*to, false),
+            AccountMeta::new_readonly(authority, true),
+            AccountMeta::new_readonly(self.conformance.mint, false),
+        ];
+        accounts.extend(extra);
+        let mut data = discriminator.to_vec();
+        data.extend_from_slice(&amount.to_le_bytes());
+        Instruction {
+            program_id: self.conformance.program_id,
+            accounts,
+            data,
+        }
+    }
+
+    async fn transaction(&mut self, ix: Instruction) -> Result<Transaction, BanksClientError> {
+        let blockhash = self.banks_client.get_latest_blockhash().await?;
+        Ok(Transaction::new_signed_with_payer(
+            &[ix],
+            Some(&self.authority.pubkey()),
+            &[self.authority],
+            blockhash,
+        ))
+    }
+
+    // Simulates `preflight_transfer` with the configured hints and readonly `hints`,
+    // opting in to extensions, and returns its raw return data
+    async fn preflight(
+        &mut self,
+        to: &Pubkey,
+        amount: u64,
+        hints: &[Pubkey],
+    ) -> Result<Vec<u8>, String> {
+        let hints: Vec<Pubkey> = hints.iter().copied().chain([EXTENSIONS_HINT]).collect();
+        self.preflight_with(to, amount, &hints).await
+    }
+
+    // Like `preflight`, but only opts in to extensions if `hints` has `EXTENSIONS_HINT`
+    async fn preflight_with(
+        &mut self,
+        to: &Pubkey,
+        amount: u64,
+        hints: &[Pubkey],
+    ) -> Result<Vec<u8>, String> {
+        let hints = self
+            .conformance
+            .hints
+            .iter()
+            .chain(hints)
+            .map(|hint| AccountMeta::new_readonly(*hint, false))
+            .collect();
+        let ix = self.instruction(&PREFLIGHT_TRANSFER_DISCRIMINATOR, to, amount, hints);
+        let tx = self.transaction(ix).await.map_err(|e| e.to_string())?;
+        let simulation = self
+            .banks_client
+            .simulate_transaction(tx)
+            .await
+            .map_err(|e| e.to_string())?;
+        match simulation.result {
+            Some(Ok(())) => {}
+            Some(Err(e)) => return Err(format!("preflight_transfer failed: {}", e)),
+            None => return Err("preflight_transfer was not simulated".to_string()),
+        }
+        match simulation.simulation_details.and_then(|d| d.return_data) {
+            Some(return_data) if return_data.program_id == self.conformance.program_id => {
+                Ok(return_data.data)
+            }
+            Some(return_data) => Err(format!("return data was set by {}", return_data.program_id)),
+            None => Err("preflight_transfer did not set return data".to_string()),
+        }
+    }
+
+    // Sends `transfer` with the requested accounts, in order, followed by `extra`
+    async fn transfer(
+        &mut self,
+        to: &Pubkey,
+        amount: u64,
+        payload: &PreflightPayload,
+        extra: &[Pubkey],
+    ) -> Result<(), BanksClientError> {
+        self.transfer_with(&TRANSFER_DISCRIMINATOR, to, amount, payload, extra)
+            .await
+    }
+
+    // Like `transfer`, but with any instruction `discriminator`
+    async fn transfer_with(
+        &mut self,
+        discriminator: &[u8],
+        to: &Pubkey,
+        amount: u64,
+        payload: &PreflightPayload,
+        extra: &[Pubkey],
+    ) -> Result<(), BanksClientError> {
+        let accounts = payload
+            .accounts
+            .iter()
+            .map(|acc| AccountMeta {
+                pubkey: acc.pubkey,
+                is_signer: acc.signer,
+                is_writable: acc.writable,
+            })
+            .chain(
+                extra
+                    .iter()
+                    .map(|acc| AccountMeta::new_readonly(*acc, false)),
+            )
+            .collect();
+        let ix = self.instruction(discriminator, to, amount, accounts);
+        let tx = self.transaction(ix).await?;
+        self.banks_client.process_transaction(tx).await
+    }
+
+    // Preflights and transfers are built from the pinned discriminators,
+    // which must be Anchor's and the only ones the program answers to
+    async fn check_discriminators(
+        &mut self,
+        preflight: &Result<Vec<u8>, String>,
+    ) -> Result<(), String> {
+        for (name, pinned) in [
+            ("preflight_transfer", PREFLIGHT_TRANSFER_DISCRIMINATOR),
+            ("transfer", TRANSFER_DISCRIMINATOR),
+        ] {
+            if sighash(name) != pinned {
+                return Err(format!(
+                    "the pinned {} discriminator is not its sighash",
+                    name
+                ));
+            }
+        }
+        preflight.as_ref().map_err(|e| e.clone())?;
+
+        let amount = self.conformance.amount;
+        let to = Pubkey::new_unique();
+        let payload = decode_payload(&self.preflight(&to, amount, &[]).await?)?;
+        let mut flipped = TRANSFER_DISCRIMINATOR;
+        flipped[0] ^= 0xff;
+        if self
+            .transfer_with(&flipped, &to, amount, &payload, &[])
+            .await
+            .is_ok()
+        {
+            return Err("a transfer with a flipped discriminator byte succeeded".to_string());
+        }
+        self.transfer(&to, amount, &payload, &[])
+            .await
+            .map_err(|e| format!("transfer failed: {}", e))?;
+
+        let ix = self.instruction(
+            &sighash("conformance_unknown"),
+            &Pubkey::new_unique(),
+            0,
+            vec![],
+        );
+        let tx = self.transaction(ix).await.map_err(|e| e.to_string())?;
+        let simulation = self
+            .banks_client
+            .simulate_transaction(tx)
+            .await
+            .map_err(|e| e.to_string())?;
+        match simulation.result {
+            Some(Err(_)) => Ok(()),
+            _ => Err("an unknown instruction did not fail".to_string()),
+        }
+    }
+
+    // The interface can't escalate signers, so preflight may only ask
+    // for signatures the caller already provides
+    fn check_signers(&self, payload: &PreflightPayload) -> Result<(), String> {
+        let authority = self.authority.pubkey();
+        match payload
+            .required_signers()
+            .find(|signer| **signer != authority)
+        {
+            Some(signer) => Err(format!("requested {} as a signer", signer)),
+            None => Ok(()),
+        }
+    }
+
+    async fn check_ordering(
+        &mut self,
+        to: &Pubkey,
+        data: &[u8],
+        payload: &PreflightPayload,
+    ) -> Result<(), String> {
+        let amount = self.conformance.amount;
+        if self.preflight(to, amount, &[]).await? != data {
+            return Err("preflight_transfer is not deterministic".to_string());
+        }
+        self.transfer(to, amount, payload, &[])
+            .await
+            .map_err(|e| format!("transfer with the requested accounts failed: {}", e))
+    }
+
+    async fn check_extra_accounts(&mut self) -> Result<(), String> {
+        let amount = self.conformance.amount;
+        let to = Pubkey::new_unique();
+        let unrelated = Pubkey::new_unique();
+        let payload = decode_payload(&self.preflight(&to, amount, &[]).await?)?;
+        let hinted = decode_payload(&self.preflight(&to, amount, &[unrelated]).await?)?;
+        if hinted.accounts.try_to_vec().unwrap() != payload.accounts.try_to_vec().unwrap() {
+            return Err("an unrelated hint changed the requested accounts".to_string());
+        }
+        self.transfer(&to, amount, &payload, &[unrelated])
+            .await
+            .map_err(|e| format!("transfer with an unrelated account failed: {}", e))
+    }
+
+    // Zero amounts must resolve, and either succeed or fail cleanly
+    async fn check_zero_amount(&mut self) -> Result<(), String> {
+        let to = Pubkey::new_unique();
+        let payload = decode_payload(&self.preflight(&to, 0, &[]).await?)?;
+        match self.transfer(&to, 0, &payload, &[]).await {
+            Ok(()) => Ok(()),
+            Err(e) if is_program_error(&e) => Ok(()),
+            Err(e) => Err(format!("transfer of 0 failed without an error: {}", e)),
+        }
+    }
+
+    // Holders must be able to send to themselves
+    async fn check_self_transfer(&mut self) -> Result<(), String> {
+        let amount = self.conformance.amount;
+        let to = self.authority.pubkey();
+        let payload = decode_payload(&self.preflight(&to, amount, &[]).await?)?;
+        self.transfer(&to, amount, &payload, &[])
+            .await
+            .map_err(|e| format!("transfer to the owner failed: {}", e))
+    }
+
+    // Callers that predate extensions decode the payload as a bare account list
+    async fn check_legacy_encoding(&mut self) -> Result<(), String> {
+        let amount = self.conformance.amount;
+        let to = Pubkey::new_unique();
+        let payload = decode_payload(&self.preflight_with(&to, amount, &[]).await?)?;
+        if !payload.extensions.is_empty() {
+            return Err("extensions were returned without the extensions hint".to_string());
+        }
+        Ok(())
+    }
+
+    // Amounts nobody holds must resolve, then fail cleanly
+    async fn check_overflow(&mut self) -> Result<(), String> {
+        let to = Pubkey::new_unique();
+        let payload = decode_payload(&self.preflight(&to, u64::MAX, &[]).await?)?;
+        match self.transfer(&to, u64::MAX, &payload, &[]).await {
+            Ok(()) => Err("transfer of u64::MAX succeeded".to_string()),
+            Err(e) if is_program_error(&e) => Ok(()),
+            Err(e) => Err(format!(
+                "transfer of u64::MAX failed without an error: {}",
+                e
+            )),
+        }
+    }
+}
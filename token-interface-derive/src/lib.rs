@@ -0,0 +1,112 @@
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::{parse_macro_input, spanned::Spanned, Data, DeriveInput, Fields};
+
+// Derives `ToTargetProgram` for an Accounts struct, using the field
+// marked `#[target_program]` as the program to call.
+//
+// ```ignore
+// #[derive(Accounts, ToTargetProgram)]
+// pub struct MyTransfer<'info> {
+//     pub owner: AccountInfo<'info>,
+//     pub authority: Signer<'info>,
+//     #[target_program]
+//     pub mint: AccountInfo<'info>,
+// }
+// ```
+#[proc_macro_derive(ToTargetProgram, attributes(target_program))]
+pub fn derive_to_target_program(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+    match expand(input) {
+        Ok(tokens) => tokens.into(),
+        Err(err) => err.to_compile_error().into(),
+    }
+}
+
+fn expand(input: DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let name = &input.ident;
+
+    let fields = match &input.data {
+        Data::Struct(data) => match &data.fields {
+            Fields::Named(fields) => &fields.named,
+            _ => {
+                return Err(syn::Error::new(
+                    input.span(),
+                    "ToTargetProgram can only be derived for structs with named fields",
+                ))
+            }
+        },
+        _ => {
+            return Err(syn::Error::new(
+                input.span(),
+                "ToTargetProgram can only be derived for structs",
+            ))
+        }
+    };
+
+    let lifetimes: Vec<_> = input.generics.lifetimes().collect();
+    if lifetimes.len() != 1 || input.generics.params.len() != 1 {
+        return Err(syn::Error::new(
+            input.generics.span(),
+            "ToTargetProgram requires exactly one lifetime parameter, e.g. `<'info>`",
+        ));
+    }
+    let lifetime = &lifetimes[0].lifetime;
+
+    let mut target = None;
+    for field in fields.iter() {
+        if field
+            .attrs
+            .iter()
+            .any(|attr| attr.path.is_ident("target_program"))
+        {
+            if target.is_some() {
+                return Err(syn::Error::new(
+                    field.span(),
+                    "only one field can be marked `#[target_program]`",
+                ));
+            }
+            target = field.ident.clone();
+        }
+    }
+    let target = target.ok_or_else(|| {
+        syn::Error::new(
+            name.span(),
+            "mark the account to call with `#[target_program]`",
+        )
+    })?;
+
+    let field_names: Vec<_> = fields.iter().map(|field| &field.ident).collect();
+
+    Ok(quote! {
+        impl<#lifetime> ::token_interface::to_target_program::ToTargetProgram<#lifetime>
+            for #name<#lifetime>
+        {
+            type TargetCtx = #name<#lifetime>;
+
+            fn to_target_program(&self) -> ::anchor_lang::prelude::Pubkey {
+                *::anchor_lang::ToAccountInfo::to_account_info(&self.#target).key
+            }
+            fn get_target_program(
+                &self,
+                _remaining_accounts: &[::anchor_lang::prelude::AccountInfo<#lifetime>],
+            ) -> ::std::option::Option<::anchor_lang::prelude::AccountInfo<#lifetime>> {
+                ::std::option::Option::Some(::anchor_lang::ToAccountInfo::to_account_info(
+                    &self.#target,
+                ))
+            }
+
+            fn to_target_context(
+                &self,
+                remaining_accounts: ::std::vec::Vec<::anchor_lang::prelude::AccountInfo<#lifetime>>,
+            ) -> ::anchor_lang::prelude::CpiContext<'_, '_, '_, #lifetime, Self::TargetCtx> {
+                let inner = #name {
+                    #(#field_names: ::std::clone::Clone::clone(&self.#field_names),)*
+                };
+                let program = ::anchor_lang::ToAccountInfo::to_account_info(&self.#target);
+                ::anchor_lang::prelude::CpiContext::new(program, inner)
+                    .with_remaining_accounts(remaining_accounts)
+            }
+        }
+    })
+}
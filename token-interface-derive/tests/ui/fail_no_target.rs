@@ -0,0 +1,15 @@
+// Anchor's derives use cfgs that newer compilers warn about
+#![allow(unknown_lints, unexpected_cfgs)]
+use anchor_lang::prelude::*;
+use token_interface::ToTargetProgram;
+
+declare_id!("32d7pdBNmDmXAXcHkivteqLjaNVovWQ1JMn85LdyyAux");
+
+#[derive(Accounts, ToTargetProgram)]
+pub struct MyTransfer<'info> {
+    pub authority: Signer<'info>,
+    /// CHECK:
+    pub mint: AccountInfo<'info>,
+}
+
+fn main() {}
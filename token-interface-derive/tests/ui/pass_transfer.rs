@@ -0,0 +1,28 @@
+// Anchor's derives use cfgs that newer compilers warn about
+#![allow(unknown_lints, unexpected_cfgs)]
+use anchor_lang::prelude::*;
+use token_interface::to_target_program::ToTargetProgram as _;
+use token_interface::ToTargetProgram;
+
+declare_id!("32d7pdBNmDmXAXcHkivteqLjaNVovWQ1JMn85LdyyAux");
+
+#[derive(Accounts, ToTargetProgram)]
+pub struct MyTransfer<'info> {
+    /// CHECK:
+    pub owner: AccountInfo<'info>,
+    /// CHECK:
+    pub to: AccountInfo<'info>,
+    pub authority: Signer<'info>,
+    /// CHECK:
+    #[target_program]
+    pub mint: AccountInfo<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+fn target_of(accounts: &MyTransfer) -> Pubkey {
+    accounts.to_target_program()
+}
+
+fn main() {
+    let _ = target_of;
+}
@@ -0,0 +1,71 @@
+#![no_main]
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::hash;
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use token_interface::{interface_instruction, IAccountMeta, PreflightPayload};
+
+#[derive(Debug, Arbitrary)]
+struct Meta {
+    key: [u8; 32],
+    signer: bool,
+    writable: bool,
+}
+
+#[derive(Debug, Arbitrary)]
+struct Input {
+    function_name: String,
+    args: Vec<u8>,
+    metas: Vec<Meta>,
+    requested: Vec<Meta>,
+}
+
+// The instruction `call_interface_function` invokes is the caller's accounts
+// followed by exactly the requested ones, with the requested flags
+fuzz_target!(|input: Input| {
+    let program_id = Pubkey::new_from_array([1; 32]);
+    let metas: Vec<AccountMeta> = input
+        .metas
+        .iter()
+        .map(|meta| AccountMeta {
+            pubkey: Pubkey::new_from_array(meta.key),
+            is_signer: meta.signer,
+            is_writable: meta.writable,
+        })
+        .collect();
+    let payload = PreflightPayload::new(
+        input
+            .requested
+            .iter()
+            .map(|meta| IAccountMeta {
+                pubkey: Pubkey::new_from_array(meta.key),
+                signer: meta.signer,
+                writable: meta.writable,
+            })
+            .collect(),
+    );
+
+    let ix = interface_instruction(
+        program_id,
+        &input.function_name,
+        &input.args,
+        metas.clone(),
+        &payload,
+    );
+
+    assert_eq!(ix.program_id, program_id);
+    let discriminator = hash::hash(format!("global:{}", input.function_name).as_bytes()).to_bytes();
+    assert_eq!(ix.data[..8], discriminator[..8]);
+    assert_eq!(ix.data[8..], input.args[..]);
+
+    assert_eq!(ix.accounts.len(), metas.len() + payload.accounts.len());
+    assert_eq!(ix.accounts[..metas.len()], metas[..]);
+    for (meta, requested) in ix.accounts[metas.len()..]
+        .iter()
+        .zip(payload.accounts.iter())
+    {
+        assert_eq!(meta.pubkey, requested.pubkey);
+        assert_eq!(meta.is_signer, requested.signer);
+        assert_eq!(meta.is_writable, requested.writable);
+    }
+});
@@ -0,0 +1,92 @@
+#![no_main]
+use anchor_lang::prelude::*;
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use token_interface::{IAccountMeta, PreflightPayload};
+
+// Keys are drawn from a small pool so that duplicates are common.
+// Lists longer than `LINEAR_SCAN_LIMIT` exercise the sorted matcher.
+const KEY_POOL: u8 = 48;
+const MAX_PASSED: usize = 96;
+
+#[derive(Debug, Arbitrary)]
+struct Account {
+    key: u8,
+    signer: bool,
+    writable: bool,
+}
+
+#[derive(Debug, Arbitrary)]
+struct Input {
+    requested: Vec<Account>,
+    passed: Vec<Account>,
+}
+
+fn key(index: u8) -> Pubkey {
+    Pubkey::new_from_array([index % KEY_POOL; 32])
+}
+
+fuzz_target!(|input: Input| {
+    let passed = &input.passed[..input.passed.len().min(MAX_PASSED)];
+    let keys: Vec<Pubkey> = passed.iter().map(|acc| key(acc.key)).collect();
+    let owner = Pubkey::default();
+    let mut lamports = vec![0u64; passed.len()];
+    let mut data = vec![Vec::<u8>::new(); passed.len()];
+    let infos: Vec<AccountInfo> = keys
+        .iter()
+        .zip(lamports.iter_mut())
+        .zip(data.iter_mut())
+        .zip(passed.iter())
+        .map(|(((key, lamports), data), acc)| {
+            AccountInfo::new(
+                key,
+                acc.signer,
+                acc.writable,
+                lamports,
+                data,
+                &owner,
+                false,
+                0,
+            )
+        })
+        .collect();
+
+    let payload = PreflightPayload::new(
+        input
+            .requested
+            .iter()
+            .map(|acc| IAccountMeta {
+                pubkey: key(acc.key),
+                signer: acc.signer,
+                writable: acc.writable,
+            })
+            .collect(),
+    );
+
+    let last = |key: &Pubkey| infos.iter().rev().find(|info| info.key == key);
+    let all_passed = payload
+        .accounts
+        .iter()
+        .all(|acc| last(&acc.pubkey).is_some());
+    match payload.match_accounts(&infos) {
+        Ok(matched) => {
+            assert!(all_passed);
+            assert_eq!(matched.len(), payload.accounts.len());
+            for (found, requested) in matched.iter().zip(payload.accounts.iter()) {
+                // Every matched account is the requested one, as last passed
+                assert_eq!(*found.key, requested.pubkey);
+                let expected = last(&requested.pubkey).unwrap();
+                assert_eq!(found.is_signer, expected.is_signer);
+                assert_eq!(found.is_writable, expected.is_writable);
+            }
+        }
+        Err(_) => assert!(!all_passed),
+    }
+
+    // Signers are checked against the last occurrence as well
+    let all_signed = payload
+        .required_signers()
+        .all(|signer| last(signer).map_or(false, |info| info.is_signer));
+    let mut signed = payload.clone();
+    assert_eq!(signed.apply_signers(&infos).is_ok(), all_signed);
+});
@@ -0,0 +1,32 @@
+#![no_main]
+use anchor_lang::prelude::*;
+use libfuzzer_sys::fuzz_target;
+use token_interface::{parse_interface_accounts, PreflightPayload};
+
+// Return data is written by arbitrary target programs. Decoding it must never
+// panic, and anything that decodes must survive a round trip.
+fuzz_target!(|data: &[u8]| {
+    let target = Pubkey::new_from_array([1; 32]);
+    let other = Pubkey::new_from_array([2; 32]);
+
+    assert!(parse_interface_accounts(&target, None).is_err());
+    assert!(parse_interface_accounts(&target, Some((other, data.to_vec()))).is_err());
+
+    let payload = match parse_interface_accounts(&target, Some((target, data.to_vec()))) {
+        Ok(payload) => payload,
+        Err(_) => return,
+    };
+    let encoded = payload.try_to_vec().unwrap();
+    let decoded = PreflightPayload::try_from_slice(&encoded).unwrap();
+    assert_eq!(decoded.try_to_vec().unwrap(), encoded);
+    assert_eq!(decoded.accounts.len(), payload.accounts.len());
+
+    // The accessors only read what was decoded
+    let _ = payload.funding();
+    let _ = payload.preview();
+    let _ = payload.routers();
+    assert!(
+        payload.required_signers().count()
+            >= payload.accounts.iter().filter(|acc| acc.signer).count()
+    );
+});
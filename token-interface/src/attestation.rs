@@ -0,0 +1,65 @@
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::{
+    instruction::{get_stack_height, TRANSACTION_LEVEL_STACK_HEIGHT},
+    sysvar::instructions::{self, get_instruction_relative},
+};
+
+use crate::InterfaceError;
+
+// Who invoked the currently executing instruction
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Caller {
+    /// Invoked directly as a top-level instruction
+    Transaction,
+    /// Invoked through CPI by this program
+    Program(Pubkey),
+    /// Invoked through nested CPI. The runtime only exposes the top-level
+    /// program, so intermediate callers can't be told apart.
+    Unknown,
+}
+
+// What the instructions sysvar and the CPI stack say about how we were invoked
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CallerAttestation {
+    pub top_level_program: Pubkey,
+    pub caller: Caller,
+    pub stack_height: usize,
+}
+
+impl CallerAttestation {
+    // Fails if `instructions_sysvar` is not the real instructions sysvar
+    pub fn load(instructions_sysvar: &AccountInfo) -> Result<Self> {
+        let top_level = get_instruction_relative(0, instructions_sysvar)?;
+        let stack_height = get_stack_height();
+        let caller = match stack_height {
+            height if height <= TRANSACTION_LEVEL_STACK_HEIGHT => Caller::Transaction,
+            height if height == TRANSACTION_LEVEL_STACK_HEIGHT + 1 => {
+                Caller::Program(top_level.program_id)
+            }
+            _ => Caller::Unknown,
+        };
+        Ok(Self {
+            top_level_program: top_level.program_id,
+            caller,
+            stack_height,
+        })
+    }
+
+    // Same as `load`, looking the instructions sysvar up among `accounts`
+    pub fn find(accounts: &[AccountInfo]) -> Result<Self> {
+        let sysvar = accounts
+            .iter()
+            .find(|acc| instructions::check_id(acc.key))
+            .ok_or(InterfaceError::InstructionsSysvarMissing)?;
+        Self::load(sysvar)
+    }
+
+    // Fails unless we were invoked through CPI by one of `routers`.
+    // Nested calls are rejected, since their immediate caller is unknown.
+    pub fn require_router(&self, routers: &[Pubkey]) -> Result<()> {
+        match self.caller {
+            Caller::Program(caller) if routers.contains(&caller) => Ok(()),
+            _ => Err(InterfaceError::RouterRequired.into()),
+        }
+    }
+}
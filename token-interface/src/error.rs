@@ -0,0 +1,31 @@
+use anchor_lang::prelude::*;
+
+#[error_code]
+pub enum InterfaceError {
+    #[msg("Target requested funding but no payer was provided")]
+    PayerNotProvided,
+    #[msg("Payer must be a writable signer")]
+    PayerNotWritableSigner,
+    #[msg("Payer cannot cover the lamports requested by the target")]
+    InsufficientPayerFunds,
+    #[msg("Target program is already executing")]
+    Reentrancy,
+    #[msg("Not enough CPI depth left to call the target program")]
+    CpiDepthExceeded,
+    #[msg("Target requested more accounts than the caller allows")]
+    TooManyAccounts,
+    #[msg("Target requested an account the caller denies")]
+    AccountDenied,
+    #[msg("Target requested an account the caller does not allow")]
+    AccountNotAllowed,
+    #[msg("Target requires a signer the caller did not provide")]
+    MissingRequiredSigner,
+    #[msg("Instructions sysvar was not provided")]
+    InstructionsSysvarMissing,
+    #[msg("Target must be called through an allowed router")]
+    RouterRequired,
+    #[msg("Target did not set return data during preflight")]
+    ReturnDataMissing,
+    #[msg("Return data was set by a program other than the target")]
+    ReturnDataMismatch,
+}
@@ -0,0 +1,87 @@
+use anchor_lang::prelude::*;
+use anchor_lang::Discriminator;
+
+// Prefix of the log lines that `emit!` produces
+pub const PROGRAM_DATA_LOG_PREFIX: &str = "Program data: ";
+
+// How an `InterfaceTransfer` reached the program that moved the balances
+#[derive(Debug, Clone, Copy, PartialEq, Eq, AnchorSerialize, AnchorDeserialize)]
+pub enum TransferRoute {
+    /// Emitted by the program that owns the balances, however it was invoked
+    Direct,
+    /// Routed by a wrapper to an SPL token program
+    SplToken,
+    /// Routed by a wrapper to a program implementing this interface
+    Interface,
+    /// Routed by a wrapper to token metadata
+    TokenMetadata,
+}
+
+// Standard event for indexers. `program` is the program that owns the
+// transferred balances, which for wrapped routes is not the emitting program.
+#[event]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InterfaceTransfer {
+    pub program: Pubkey,
+    pub mint: Pubkey,
+    pub owner: Pubkey,
+    pub to: Pubkey,
+    pub authority: Pubkey,
+    pub amount: u64,
+    pub route: TransferRoute,
+}
+
+impl InterfaceTransfer {
+    // Decodes an event from the raw bytes of a `sol_log_data` call
+    pub fn decode(data: &[u8]) -> Option<Self> {
+        let body = data.strip_prefix(&Self::DISCRIMINATOR)?;
+        Self::try_from_slice(body).ok()
+    }
+
+    // Decodes an event from a single `Program data: <base64>` log line
+    pub fn from_log(log: &str) -> Option<Self> {
+        let encoded = log.strip_prefix(PROGRAM_DATA_LOG_PREFIX)?;
+        Self::decode(&base64::decode(encoded).ok()?)
+    }
+
+    // Collects every `InterfaceTransfer` from a transaction's log messages,
+    // along with the program that emitted it. Any program can log bytes that
+    // decode as an event, so `Direct` events only count when their `program`
+    // emitted them. Callers should check the emitter of wrapped routes against
+    // the wrappers they trust.
+    pub fn from_logs<'a>(logs: impl IntoIterator<Item = &'a str>) -> Vec<(Pubkey, Self)> {
+        let mut invocations = vec![];
+        let mut events = vec![];
+        for log in logs {
+            if let Some(event) = Self::from_log(log) {
+                match invocations.last() {
+                    Some(emitter)
+                        if event.route != TransferRoute::Direct || event.program == *emitter =>
+                    {
+                        events.push((*emitter, event))
+                    }
+                    _ => {}
+                }
+            } else if let Some((program, status)) = program_status(log) {
+                if status.starts_with("invoke [") {
+                    invocations.push(program);
+                } else if status == "success" || status.starts_with("failed") {
+                    invocations.pop();
+                }
+            } else if log == LOG_TRUNCATED {
+                // The emitters of later lines can't be told apart anymore
+                break;
+            }
+        }
+        events
+    }
+}
+
+// What the runtime logs in place of lines past the log limit
+const LOG_TRUNCATED: &str = "Log truncated";
+
+// Splits the runtime's `Program <id> <status>` lines
+fn program_status(log: &str) -> Option<(Pubkey, &str)> {
+    let (program, status) = log.strip_prefix("Program ")?.split_once(' ')?;
+    Some((program.parse().ok()?, status))
+}
@@ -0,0 +1,107 @@
+use anchor_lang::prelude::*;
+
+// Callers opt in to extensions by passing this address,
+// `PreF1ightExtensions111111111111111111111111`, among the preflight accounts.
+// Without it, targets return the original `Vec<IAccountMeta>` encoding, which
+// older callers decode with `try_from_slice`.
+pub const EXTENSIONS_HINT: Pubkey = Pubkey::new_from_array([
+    5, 218, 232, 134, 39, 125, 134, 210, 54, 198, 32, 255, 162, 16, 156, 221, 20, 124, 38, 93, 140,
+    178, 16, 80, 195, 251, 28, 236, 196, 0, 0, 0,
+]);
+
+// Optional sections that a target program may append to its `PreflightPayload`.
+// Payloads without extensions are byte-for-byte identical to the original format.
+#[derive(Debug, Clone, AnchorSerialize, AnchorDeserialize)]
+pub enum PreflightExtension {
+    Funding(FundingRequirement),
+    Preview(BalancePreview),
+    Signers(RequiredSigners),
+    Routers(RouterRequirement),
+}
+
+// Declares that executing the instruction may create or grow accounts,
+// and which of the caller's accounts is expected to pay for it.
+#[derive(Debug, Clone, AnchorSerialize, AnchorDeserialize)]
+pub struct FundingRequirement {
+    /// Upper bound on the lamports the target may debit from the payer
+    pub lamports: u64,
+    pub payer: PayerRole,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, AnchorSerialize, AnchorDeserialize)]
+pub enum PayerRole {
+    /// The transfer `authority` pays. Targets must also request the authority
+    /// as a writable additional account so that it can be debited.
+    Authority,
+    /// The `payer` of the `ITransferWithPayer` layout pays. Targets may only use it
+    /// when the caller passed a payer to preflight, and must request it back
+    /// as a writable signer additional account.
+    Payer,
+}
+
+// Accounts that must sign the outer transaction, so that wallets can collect
+// their signatures before submitting. Callers pass them on as signers, but never
+// sign for them with their own seeds.
+#[derive(Debug, Clone, Default, AnchorSerialize, AnchorDeserialize)]
+pub struct RequiredSigners {
+    pub accounts: Vec<Pubkey>,
+}
+
+// Declares that the target only accepts `transfer` through CPI from one of `routers`,
+// and checks it with the instructions sysvar, which it also requests.
+#[derive(Debug, Clone, Default, AnchorSerialize, AnchorDeserialize)]
+pub struct RouterRequirement {
+    pub routers: Vec<Pubkey>,
+}
+
+// What the instruction is expected to do to balances, so that wallets
+// can show it without simulating the full instruction.
+#[derive(Debug, Clone, Default, AnchorSerialize, AnchorDeserialize)]
+pub struct BalancePreview {
+    pub deltas: Vec<BalanceDelta>,
+    /// Fee charged by the target, in units of the transferred asset
+    pub fee: u64,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, AnchorSerialize, AnchorDeserialize)]
+pub struct BalanceDelta {
+    pub asset: Pubkey,
+    pub owner: Pubkey,
+    pub delta: i128,
+}
+
+impl BalancePreview {
+    // Preview for moving `amount` of `asset` from `from` to `to`, where `to`
+    // receives `amount - fee`. Self transfers only pay the fee.
+    pub fn transfer(asset: Pubkey, from: Pubkey, to: Pubkey, amount: u64, fee: u64) -> Self {
+        let deltas = if from == to {
+            vec![BalanceDelta {
+                asset,
+                owner: from,
+                delta: -(fee as i128),
+            }]
+        } else {
+            vec![
+                BalanceDelta {
+                    asset,
+                    owner: from,
+                    delta: -(amount as i128),
+                },
+                BalanceDelta {
+                    asset,
+                    owner: to,
+                    delta: amount as i128 - fee as i128,
+                },
+            ]
+        };
+        Self { deltas, fee }
+    }
+
+    pub fn delta_for(&self, asset: &Pubkey, owner: &Pubkey) -> i128 {
+        self.deltas
+            .iter()
+            .filter(|delta| delta.asset == *asset && delta.owner == *owner)
+            .map(|delta| delta.delta)
+            .sum()
+    }
+}
@@ -0,0 +1,69 @@
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::{
+    instruction::{get_stack_height, TRANSACTION_LEVEL_STACK_HEIGHT},
+    sysvar::instructions,
+};
+
+use crate::{Caller, CallerAttestation, InterfaceError};
+
+// The runtime allows 4 nested CPIs below the top-level instruction
+pub const MAX_STACK_HEIGHT: usize = TRANSACTION_LEVEL_STACK_HEIGHT + 4;
+
+// Checks made before issuing interface CPIs, so that recursion fails
+// early with a clear error instead of deep inside the target program.
+#[derive(Debug, Clone)]
+pub struct ReentrancyGuard {
+    /// The program making the interface call
+    pub caller: Pubkey,
+    /// Allows the target to be `caller` itself
+    pub allow_reentry: bool,
+    /// How many levels of CPI must still be available below the caller.
+    /// Targets that CPI further themselves need more than 1.
+    pub required_depth: usize,
+}
+
+impl ReentrancyGuard {
+    pub fn new(caller: Pubkey) -> Self {
+        Self {
+            caller,
+            allow_reentry: false,
+            required_depth: 1,
+        }
+    }
+
+    pub fn allow_reentry(mut self) -> Self {
+        self.allow_reentry = true;
+        self
+    }
+
+    pub fn with_required_depth(mut self, required_depth: usize) -> Self {
+        self.required_depth = required_depth;
+        self
+    }
+
+    // If the instructions sysvar is among `accounts`, this also catches targets that are
+    // already executing as the top-level instruction, which the runtime would reject.
+    pub fn check(&self, target: &Pubkey, accounts: &[AccountInfo]) -> Result<()> {
+        let stack_height = get_stack_height();
+        if stack_height + self.required_depth > MAX_STACK_HEIGHT {
+            return Err(InterfaceError::CpiDepthExceeded.into());
+        }
+
+        if *target == self.caller && !self.allow_reentry {
+            return Err(InterfaceError::Reentrancy.into());
+        }
+
+        let sysvar = accounts.iter().find(|acc| instructions::check_id(acc.key));
+        if let Some(sysvar) = sysvar {
+            let attestation = CallerAttestation::load(sysvar)?;
+            let target_is_caller = *target == self.caller && self.allow_reentry;
+            if attestation.top_level_program == *target
+                && attestation.caller != Caller::Transaction
+                && !target_is_caller
+            {
+                return Err(InterfaceError::Reentrancy.into());
+            }
+        }
+        Ok(())
+    }
+}
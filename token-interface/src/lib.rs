@@ -1,13 +1,32 @@
-#![feature(generic_associated_types)]
-use std::collections::HashMap;
-
+pub mod attestation;
+pub mod error;
+pub mod events;
+pub mod extensions;
+pub mod guard;
+pub mod logging;
+pub mod matching;
+pub mod policy;
+// Only for tests and conformance checks, since building vectors can panic
+#[cfg(any(test, feature = "test-vectors"))]
+pub mod test_vectors;
 pub mod to_target_program;
-use to_target_program::*;
+pub use attestation::*;
+pub use error::*;
+pub use events::*;
+pub use extensions::*;
+pub use guard::*;
+use logging::log_at;
+pub use logging::LogLevel;
+use matching::*;
+pub use policy::*;
+pub use to_target_program::*;
+pub use token_interface_derive::ToTargetProgram;
 
 use anchor_lang::prelude::*;
 use anchor_lang::solana_program::{
     hash,
-    program::{get_return_data, invoke},
+    instruction::Instruction,
+    program::{get_return_data, invoke, MAX_RETURN_DATA},
 };
 
 #[derive(Debug, Clone, AnchorSerialize, AnchorDeserialize)]
@@ -17,96 +36,352 @@ pub struct IAccountMeta {
     pub writable: bool,
 }
 
-#[derive(Debug, Clone, AnchorDeserialize, AnchorSerialize)]
+#[derive(Debug, Clone, Default)]
 pub struct PreflightPayload {
     pub accounts: Vec<IAccountMeta>,
+    pub extensions: Vec<PreflightExtension>,
+}
+
+// Extensions are only written when present, so that payloads from programs
+// that don't use them keep the original `Vec<IAccountMeta>` encoding.
+impl AnchorSerialize for PreflightPayload {
+    fn serialize<W: std::io::Write>(&self, writer: &mut W) -> std::io::Result<()> {
+        self.accounts.serialize(writer)?;
+        if !self.extensions.is_empty() {
+            self.extensions.serialize(writer)?;
+        }
+        Ok(())
+    }
+}
+
+impl AnchorDeserialize for PreflightPayload {
+    fn deserialize(buf: &mut &[u8]) -> std::io::Result<Self> {
+        let accounts = Vec::<IAccountMeta>::deserialize(buf)?;
+        let extensions = if buf.is_empty() {
+            vec![]
+        } else {
+            Vec::<PreflightExtension>::deserialize(buf)?
+        };
+        Ok(Self {
+            accounts,
+            extensions,
+        })
+    }
 }
 
 impl PreflightPayload {
+    pub fn new(accounts: Vec<IAccountMeta>) -> Self {
+        Self {
+            accounts,
+            extensions: vec![],
+        }
+    }
+
+    pub fn with_funding(mut self, funding: FundingRequirement) -> Self {
+        self.extensions.push(PreflightExtension::Funding(funding));
+        self
+    }
+
+    pub fn with_preview(mut self, preview: BalancePreview) -> Self {
+        self.extensions.push(PreflightExtension::Preview(preview));
+        self
+    }
+
+    pub fn with_signers(mut self, accounts: Vec<Pubkey>) -> Self {
+        self.extensions
+            .push(PreflightExtension::Signers(RequiredSigners { accounts }));
+        self
+    }
+
+    pub fn with_routers(mut self, routers: Vec<Pubkey>) -> Self {
+        self.extensions
+            .push(PreflightExtension::Routers(RouterRequirement { routers }));
+        self
+    }
+
+    // What a target returns to a caller that passed `hints` to preflight: the
+    // payload as is if they include `EXTENSIONS_HINT`, else only its accounts,
+    // with the listed signers flagged since older callers can't read them.
+    pub fn for_caller(mut self, hints: &[AccountInfo]) -> Self {
+        if hints.iter().any(|acc| acc.key() == EXTENSIONS_HINT) {
+            return self;
+        }
+        self.flag_listed_signers();
+        self.extensions.clear();
+        self
+    }
+
+    pub fn funding(&self) -> Option<&FundingRequirement> {
+        self.extensions.iter().find_map(|ext| match ext {
+            PreflightExtension::Funding(funding) => Some(funding),
+            _ => None,
+        })
+    }
+
+    pub fn preview(&self) -> Option<&BalancePreview> {
+        self.extensions.iter().find_map(|ext| match ext {
+            PreflightExtension::Preview(preview) => Some(preview),
+            _ => None,
+        })
+    }
+
+    pub fn routers(&self) -> Option<&RouterRequirement> {
+        self.extensions.iter().find_map(|ext| match ext {
+            PreflightExtension::Routers(routers) => Some(routers),
+            _ => None,
+        })
+    }
+
+    // Every account that must sign: those listed in the `Signers` extension,
+    // and requested accounts flagged as signers. May contain duplicates.
+    pub fn required_signers(&self) -> impl Iterator<Item = &Pubkey> {
+        let flagged = self
+            .accounts
+            .iter()
+            .filter(|acc| acc.signer)
+            .map(|acc| &acc.pubkey);
+        self.listed_signers().chain(flagged)
+    }
+
+    fn listed_signers(&self) -> impl Iterator<Item = &Pubkey> {
+        self.extensions.iter().flat_map(|ext| match ext {
+            PreflightExtension::Signers(signers) => signers.accounts.as_slice(),
+            _ => &[],
+        })
+    }
+
+    // Checks that every required signer already signed the caller's instruction,
+    // and flags the listed ones as signers among the requested accounts.
+    pub fn apply_signers(&mut self, accounts: &[AccountInfo]) -> Result<()> {
+        let matcher = AccountMatcher::new(accounts);
+        for signer in self.required_signers() {
+            match matcher.find(signer) {
+                Some(acc) if acc.is_signer => {}
+                _ => {
+                    return Err(
+                        error!(InterfaceError::MissingRequiredSigner).with_account_name(signer)
+                    )
+                }
+            }
+        }
+
+        self.flag_listed_signers();
+        Ok(())
+    }
+
+    fn flag_listed_signers(&mut self) {
+        let listed: Vec<Pubkey> = self.listed_signers().copied().collect();
+        for acc in self.accounts.iter_mut() {
+            if listed.contains(&acc.pubkey) {
+                acc.signer = true;
+            }
+        }
+    }
+
+    // Returns the requested accounts, in the order they were requested.
+    // If a pubkey is passed more than once, its last occurrence is used.
     pub fn match_accounts<'info>(
         &self,
         accounts: &[AccountInfo<'info>],
     ) -> Result<Vec<AccountInfo<'info>>> {
-        let mut map = HashMap::<Pubkey, AccountInfo>::new();
-
-        for acc in accounts {
-            map.insert(acc.key(), acc.clone());
-        }
+        let mut found_accounts = Vec::with_capacity(self.accounts.len());
+        self.match_accounts_into(accounts, &mut found_accounts)?;
+        Ok(found_accounts)
+    }
 
-        let mut found_accounts = Vec::<AccountInfo>::new();
+    // Same as `match_accounts`, but appends to an existing vector
+    // so callers building a CPI account list don't allocate twice
+    pub fn match_accounts_into<'info>(
+        &self,
+        accounts: &[AccountInfo<'info>],
+        found_accounts: &mut Vec<AccountInfo<'info>>,
+    ) -> Result<()> {
+        let matcher = AccountMatcher::new(accounts);
+        found_accounts.reserve(self.accounts.len());
         for acc in self.accounts.iter() {
-            let found_acc = map.get(&acc.pubkey);
-            if found_acc.is_none() {
-                msg!(&format!("account not found: {:?}", acc.pubkey));
-                return Err(ProgramError::NotEnoughAccountKeys.into());
+            match matcher.find(&acc.pubkey) {
+                Some(found_acc) => found_accounts.push(found_acc.clone()),
+                None => {
+                    return Err(Error::from(ProgramError::NotEnoughAccountKeys)
+                        .with_account_name(acc.pubkey))
+                }
             }
-            found_accounts.push(found_acc.unwrap().clone());
         }
-        msg!("found accounts: {:?}", found_accounts.len());
-
-        Ok(found_accounts)
+        Ok(())
     }
 }
 
 pub fn get_interface_accounts(program_key: &Pubkey) -> Result<PreflightPayload> {
-    let (key, program_data) = get_return_data().unwrap();
-    assert_eq!(key, *program_key);
-    let program_data = program_data.as_slice();
+    parse_interface_accounts(program_key, get_return_data())
+}
+
+// Decodes the payload `program_key` left in `return_data` after preflight.
+// Payloads are never empty, so empty return data counts as missing.
+pub fn parse_interface_accounts(
+    program_key: &Pubkey,
+    return_data: Option<(Pubkey, Vec<u8>)>,
+) -> Result<PreflightPayload> {
+    let (key, program_data) = return_data
+        .filter(|(_, data)| !data.is_empty())
+        .ok_or(InterfaceError::ReturnDataMissing)?;
+    if key != *program_key {
+        return Err(error!(InterfaceError::ReturnDataMismatch).with_account_name(key));
+    }
     let additional_interface_accounts = PreflightPayload::try_from_slice(&program_data)?;
-    msg!(
-        "Additional interface accounts: {:?}",
-        &additional_interface_accounts
-    );
     Ok(additional_interface_accounts)
 }
 
+// Decodes preflight return data as reported off-chain. Simulations strip its
+// trailing zeros, so they are padded back before decoding. Fails if `data`
+// continues past the payload.
+pub fn decode_preflight_return_data(data: &[u8]) -> std::io::Result<PreflightPayload> {
+    let mut padded = data.to_vec();
+    padded.resize(data.len().max(MAX_RETURN_DATA), 0);
+    let mut buf = padded.as_slice();
+    let payload = PreflightPayload::deserialize(&mut buf)?;
+    let read = padded.len() - buf.len();
+    if read < data.len() {
+        return Err(std::io::Error::new(
+            std::io::ErrorKind::InvalidData,
+            format!("{} trailing bytes after the payload", data.len() - read),
+        ));
+    }
+    Ok(payload)
+}
+
+// Options for `call_with_config`
+#[derive(Debug, Clone, Default)]
+pub struct CallConfig {
+    pub log_level: LogLevel,
+    pub guard: Option<ReentrancyGuard>,
+    pub policy: Option<CallPolicy>,
+}
+
 // Allows calling `transfer` on the target program.
 // This invokes the preflight function followed by the actual function on the target program
-pub fn call<
+pub fn call<'info, C: ToAccountInfos<'info> + ToAccountMetas + ToTargetProgram<'info>>(
+    ix_name: String,
+    ctx: CpiContext<'_, '_, '_, 'info, C>,
+    args: Vec<u8>,
+    log_level: LogLevel,
+) -> Result<()> {
+    call_with_config(
+        ix_name,
+        ctx,
+        args,
+        CallConfig {
+            log_level,
+            ..Default::default()
+        },
+    )
+}
+
+pub fn call_with_config<
     'info,
-    C1: ToAccountInfos<'info> + ToAccountMetas + ToTargetProgram<'info, TargetCtx<'info> = C2>,
-    C2: ToAccountInfos<'info> + ToAccountMetas,
+    C: ToAccountInfos<'info> + ToAccountMetas + ToTargetProgram<'info>,
 >(
     ix_name: String,
-    ctx: CpiContext<'_, '_, '_, 'info, C1>,
+    ctx: CpiContext<'_, '_, '_, 'info, C>,
     args: Vec<u8>,
-    log_info: bool,
+    config: CallConfig,
 ) -> Result<()> {
-    // preflight
-    if log_info {
-        msg!("Preflight");
+    let log_level = config.log_level;
+    let target = ctx.accounts.to_target_program();
+
+    // make sure the target can't recurse into us
+    if let Some(guard) = &config.guard {
+        if let Err(e) = guard.check(&target, &ctx.remaining_accounts) {
+            log_at!(
+                log_level,
+                Summary,
+                "Guard rejected call to {} at stack height {}",
+                target,
+                anchor_lang::solana_program::instruction::get_stack_height()
+            );
+            return Err(e);
+        }
     }
+
+    // preflight
+    log_at!(log_level, Summary, "Preflight {}", &ix_name);
     call_preflight_interface_function(ix_name.clone(), &ctx, &args)?;
 
     // parse cpi return data
-    if log_info {
-        msg!("Parse return data");
+    let mut additional_interface_accounts = get_interface_accounts(&target)?;
+    log_at!(
+        log_level,
+        Summary,
+        "Target requested {} accounts",
+        additional_interface_accounts.accounts.len()
+    );
+    log_at!(
+        log_level,
+        Verbose,
+        "Additional interface accounts: {:?}",
+        &additional_interface_accounts
+    );
+
+    // enforce the caller's limits on what the target may request
+    if let Some(policy) = &config.policy {
+        if let Err(e) = policy.apply(&mut additional_interface_accounts, &ctx.remaining_accounts) {
+            log_at!(log_level, Summary, "Call policy rejected request: {}", e);
+            return Err(e);
+        }
     }
-    let additional_interface_accounts = get_interface_accounts(&ctx.accounts.to_target_program())?;
 
-    // wrap into target context
-    if log_info {
-        msg!("Convert into target context");
+    // make sure whoever the target expects to pay can actually pay
+    if let Some(funding) = additional_interface_accounts.funding() {
+        log_at!(
+            log_level,
+            Summary,
+            "Check payer for {} lamports",
+            funding.lamports
+        );
+        let payer = ctx
+            .accounts
+            .get_payer(&funding.payer)
+            .ok_or(InterfaceError::PayerNotProvided)?;
+        check_payer(&payer, funding)?;
     }
-    let cpi_ctx: CpiContext<C2> = ctx
+
+    // wrap into target context
+    let cpi_ctx: CpiContext<C::TargetCtx> = ctx
         .accounts
         .to_target_context(ctx.remaining_accounts.to_vec());
 
-    // execute
-    if log_info {
-        msg!("Execute {}", &ix_name);
+    // signers can't be escalated, they must have signed the outer transaction
+    let mut caller_accounts = cpi_ctx.accounts.to_account_infos();
+    caller_accounts.extend_from_slice(&cpi_ctx.remaining_accounts);
+    if let Err(e) = additional_interface_accounts.apply_signers(&caller_accounts) {
+        log_at!(log_level, Summary, "Missing required signer: {}", e);
+        return Err(e);
     }
+
+    // execute
+    log_at!(log_level, Summary, "Execute {}", &ix_name);
     call_interface_function(
-        ix_name.clone(),
+        ix_name,
         cpi_ctx,
         &args,
         additional_interface_accounts,
-        log_info,
+        log_level,
     )?;
     Ok(())
 }
 
-// This calls the preflight function on the target program
+pub fn check_payer(payer: &AccountInfo, funding: &FundingRequirement) -> Result<()> {
+    if !payer.is_signer || !payer.is_writable {
+        return Err(InterfaceError::PayerNotWritableSigner.into());
+    }
+    if payer.lamports() < funding.lamports {
+        return Err(InterfaceError::InsufficientPayerFunds.into());
+    }
+    Ok(())
+}
+
+// This calls the preflight function on the target program.
+// Any remaining accounts on `ctx` are forwarded as readonly hints, so that
+// the target can inspect existing state (e.g. whether a recipient exists yet)
 pub fn call_preflight_interface_function<'info, T: ToAccountInfos<'info> + ToAccountMetas>(
     function_name: String,
     ctx: &CpiContext<'_, '_, '_, 'info, T>,
@@ -119,15 +394,23 @@ pub fn call_preflight_interface_function<'info, T: ToAccountInfos<'info> + ToAcc
 
     ix_data.extend_from_slice(args);
 
-    let ix_account_metas = ctx.accounts.to_account_metas(Some(false));
-    let ix = anchor_lang::solana_program::instruction::Instruction {
+    let mut ix_account_metas = ctx.accounts.to_account_metas(Some(false));
+    ix_account_metas.extend(
+        ctx.remaining_accounts
+            .iter()
+            .map(|acc| AccountMeta::new_readonly(acc.key(), false)),
+    );
+    let ix = Instruction {
         program_id: ctx.program.key(),
         accounts: ix_account_metas,
         data: ix_data,
     };
 
+    let mut ix_ais = ctx.accounts.to_account_infos();
+    ix_ais.extend_from_slice(&ctx.remaining_accounts);
+
     // execute
-    invoke(&ix, &ctx.accounts.to_account_infos())?;
+    invoke(&ix, &ix_ais)?;
     Ok(())
 }
 
@@ -138,53 +421,29 @@ pub fn call_interface_function<'info, T: ToAccountInfos<'info> + ToAccountMetas>
     ctx: CpiContext<'_, '_, '_, 'info, T>,
     args: &[u8],
     additional_interface_accounts: PreflightPayload,
-    log_info: bool,
+    log_level: LogLevel,
 ) -> Result<()> {
     // setup
-    let remaining_accounts = ctx.remaining_accounts.to_vec();
-
-    let mut ix_data: Vec<u8> =
-        hash::hash(format!("global:{}", &function_name).as_bytes()).to_bytes()[..8].to_vec();
-    ix_data.extend_from_slice(&args);
-
-    let mut ix_account_metas = ctx.accounts.to_account_metas(None);
-    ix_account_metas.append(
-        additional_interface_accounts
-            .accounts
-            .iter()
-            .map(|acc| {
-                if acc.writable {
-                    AccountMeta::new(acc.pubkey, acc.signer)
-                } else {
-                    AccountMeta::new_readonly(acc.pubkey, acc.signer)
-                }
-            })
-            .collect::<Vec<AccountMeta>>()
-            .as_mut(),
+    let ix = interface_instruction(
+        ctx.program.key(),
+        &function_name,
+        args,
+        ctx.accounts.to_account_metas(None),
+        &additional_interface_accounts,
     );
 
-    let ix = anchor_lang::solana_program::instruction::Instruction {
-        program_id: ctx.program.key(),
-        accounts: ix_account_metas,
-        data: ix_data,
-    };
-
     let mut ix_ais: Vec<AccountInfo> = ctx.accounts.to_account_infos();
-    if log_info {
-        msg!("IX accounts: {:?}", &ix_ais.len());
-    }
-    ix_ais.extend_from_slice(
-        &mut additional_interface_accounts
-            .match_accounts(&remaining_accounts)?
-            .to_vec(),
-    );
-
-    if log_info {
-        msg!("IX accounts: {:?}", &ix_ais.len());
+    if let Err(e) =
+        additional_interface_accounts.match_accounts_into(&ctx.remaining_accounts, &mut ix_ais)
+    {
+        log_at!(log_level, Summary, "Requested account missing: {}", e);
+        return Err(e);
     }
 
-    if log_info {
-        ix_ais.iter().into_iter().for_each(|ai| {
+    log_at!(log_level, Summary, "IX accounts: {}", ix_ais.len());
+    #[cfg(feature = "logging")]
+    if log_level.enabled(LogLevel::Verbose) {
+        ix_ais.iter().for_each(|ai| {
             msg!(
                 "Account: {:?}, {:?}, {:?}, {:?}",
                 ai.key,
@@ -193,13 +452,41 @@ pub fn call_interface_function<'info, T: ToAccountInfos<'info> + ToAccountMetas>
                 ai.is_writable
             )
         });
-    } else {
-        // execute
-        invoke(&ix, &ix_ais)?;
     }
+
+    // execute
+    invoke(&ix, &ix_ais)?;
     Ok(())
 }
 
+// Builds the instruction `call_interface_function` invokes: the discriminator of
+// `function_name` followed by `args`, and `account_metas` followed by the requested accounts
+pub fn interface_instruction(
+    program_id: Pubkey,
+    function_name: &str,
+    args: &[u8],
+    mut account_metas: Vec<AccountMeta>,
+    additional_interface_accounts: &PreflightPayload,
+) -> Instruction {
+    let mut ix_data: Vec<u8> =
+        hash::hash(format!("global:{}", function_name).as_bytes()).to_bytes()[..8].to_vec();
+    ix_data.extend_from_slice(args);
+
+    account_metas.extend(additional_interface_accounts.accounts.iter().map(|acc| {
+        if acc.writable {
+            AccountMeta::new(acc.pubkey, acc.signer)
+        } else {
+            AccountMeta::new_readonly(acc.pubkey, acc.signer)
+        }
+    }));
+
+    Instruction {
+        program_id,
+        accounts: account_metas,
+        data: ix_data,
+    }
+}
+
 #[derive(Accounts)]
 pub struct ITransfer<'info> {
     /// CHECK:
@@ -211,29 +498,132 @@ pub struct ITransfer<'info> {
     pub mint: AccountInfo<'info>,
 }
 
+// The program implementing the interface behind `mint`: the mint itself when it
+// is a program, otherwise the owner of the mint-state account, which callers
+// pass along as a remaining account
+pub fn mint_program<'info>(
+    mint: &AccountInfo<'info>,
+    remaining_accounts: &[AccountInfo<'info>],
+) -> Option<AccountInfo<'info>> {
+    if mint.executable {
+        return Some(mint.clone());
+    }
+    remaining_accounts
+        .iter()
+        .find(|acc| acc.key == mint.owner && acc.executable)
+        .cloned()
+}
+
+fn mint_program_key(mint: &AccountInfo) -> Pubkey {
+    if mint.executable {
+        mint.key()
+    } else {
+        *mint.owner
+    }
+}
+
 // This allows us to create a new context out of `ITransfer`
-// that uses `mint` account as the target program.
+// that uses `mint` account, or the program owning it, as the target program.
 impl<'info> ToTargetProgram<'info> for ITransfer<'info> {
-    type TargetCtx<'a> = ITransfer<'a>;
+    type TargetCtx = ITransfer<'info>;
 
     fn to_target_program(&self) -> Pubkey {
-        self.mint.key()
+        mint_program_key(&self.mint)
     }
-    fn get_target_program(&self) -> AccountInfo<'info> {
-        self.mint.clone()
+    fn get_target_program(
+        &self,
+        remaining_accounts: &[AccountInfo<'info>],
+    ) -> Option<AccountInfo<'info>> {
+        mint_program(&self.mint, remaining_accounts)
     }
 
     fn to_target_context(
         &self,
         remaining_accounts: Vec<AccountInfo<'info>>,
-    ) -> CpiContext<'_, '_, '_, 'info, Self::TargetCtx<'info>> {
+    ) -> CpiContext<'_, '_, '_, 'info, Self::TargetCtx> {
         let inner = ITransfer {
             to: self.to.to_account_info(),
             mint: self.mint.to_account_info(),
             owner: self.owner.to_account_info(),
             authority: self.authority.clone(),
         };
-        CpiContext::new(self.get_target_program(), inner)
-            .with_remaining_accounts(remaining_accounts)
+        // Without the owning program the CPI goes to the mint, and fails
+        let program = self
+            .get_target_program(&remaining_accounts)
+            .unwrap_or_else(|| self.mint.clone());
+        CpiContext::new(program, inner).with_remaining_accounts(remaining_accounts)
     }
+
+    fn get_payer(&self, role: &PayerRole) -> Option<AccountInfo<'info>> {
+        match role {
+            PayerRole::Authority => Some(self.authority.to_account_info()),
+            PayerRole::Payer => None,
+        }
+    }
+}
+
+// `ITransfer` with a separate account paying for anything the target creates.
+// The payer is passed to `preflight_transfer` after `mint`, and targets that support it
+// request it back as an additional account. Targets that don't see it as an
+// ordinary hint, so callers can use this layout with any target.
+#[derive(Accounts)]
+pub struct ITransferWithPayer<'info> {
+    /// CHECK:
+    pub owner: AccountInfo<'info>,
+    /// CHECK:
+    pub to: AccountInfo<'info>,
+    pub authority: Signer<'info>,
+    /// CHECK:
+    pub mint: AccountInfo<'info>,
+    #[account(mut)]
+    pub payer: Signer<'info>,
+}
+
+// `transfer` itself keeps the `ITransfer` layout, the payer is only
+// passed along if the target requested it during preflight.
+impl<'info> ToTargetProgram<'info> for ITransferWithPayer<'info> {
+    type TargetCtx = ITransfer<'info>;
+
+    fn to_target_program(&self) -> Pubkey {
+        mint_program_key(&self.mint)
+    }
+    fn get_target_program(
+        &self,
+        remaining_accounts: &[AccountInfo<'info>],
+    ) -> Option<AccountInfo<'info>> {
+        mint_program(&self.mint, remaining_accounts)
+    }
+
+    fn to_target_context(
+        &self,
+        mut remaining_accounts: Vec<AccountInfo<'info>>,
+    ) -> CpiContext<'_, '_, '_, 'info, Self::TargetCtx> {
+        let inner = ITransfer {
+            to: self.to.to_account_info(),
+            mint: self.mint.to_account_info(),
+            owner: self.owner.to_account_info(),
+            authority: self.authority.clone(),
+        };
+        // Without the owning program the CPI goes to the mint, and fails
+        let program = self
+            .get_target_program(&remaining_accounts)
+            .unwrap_or_else(|| self.mint.clone());
+        remaining_accounts.push(self.payer.to_account_info());
+        CpiContext::new(program, inner).with_remaining_accounts(remaining_accounts)
+    }
+
+    fn get_payer(&self, role: &PayerRole) -> Option<AccountInfo<'info>> {
+        match role {
+            PayerRole::Authority => Some(self.authority.to_account_info()),
+            PayerRole::Payer => Some(self.payer.to_account_info()),
+        }
+    }
+}
+
+// Returns the payer a caller passed to `preflight_transfer` with the `ITransferWithPayer` layout.
+// Hints are always readonly, so the payer is the only writable remaining account.
+pub fn find_preflight_payer<'a, 'info>(
+    remaining_accounts: &'a [AccountInfo<'info>],
+) -> Option<&'a AccountInfo<'info>> {
+    remaining_accounts.iter().find(|acc| acc.is_writable)
 }
@@ -0,0 +1,38 @@
+// How much `token-interface` logs while making interface calls.
+// Logging only happens when the crate is built with the `logging` feature,
+// otherwise every level behaves like `Off` and no `msg!` is compiled in.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    #[default]
+    Off,
+    /// One line per step of the call, plus the reason a call was rejected
+    Summary,
+    /// Also dumps the preflight payload and every account passed to the target
+    Verbose,
+}
+
+impl LogLevel {
+    // Whether messages at `level` are logged, taking the `logging` feature into account
+    pub fn enabled(self, level: LogLevel) -> bool {
+        cfg!(feature = "logging") && level != LogLevel::Off && self >= level
+    }
+}
+
+// Logs through `msg!` if `$level` lets messages at `$at` through.
+// Expands to nothing without the `logging` feature.
+macro_rules! log_at {
+    ($level:expr, $at:ident, $($arg:tt)+) => {{
+        #[cfg(feature = "logging")]
+        {
+            if $level.enabled($crate::LogLevel::$at) {
+                ::anchor_lang::prelude::msg!($($arg)+);
+            }
+        }
+        #[cfg(not(feature = "logging"))]
+        {
+            let _ = $level;
+        }
+    }};
+}
+
+pub(crate) use log_at;
@@ -0,0 +1,39 @@
+use anchor_lang::prelude::*;
+
+// Up to this many remaining accounts, requested accounts are found with a
+// linear scan. Most transfers pass well under this, and the scan needs no heap.
+pub const LINEAR_SCAN_LIMIT: usize = 32;
+
+// Finds accounts by pubkey in a slice of account infos.
+// When a pubkey appears more than once, the last occurrence always wins,
+// as it did when matching went through a `HashMap`.
+pub enum AccountMatcher<'a, 'info> {
+    Linear(&'a [AccountInfo<'info>]),
+    // Indices into the slice, stably sorted by pubkey
+    Sorted(&'a [AccountInfo<'info>], Vec<usize>),
+}
+
+impl<'a, 'info> AccountMatcher<'a, 'info> {
+    pub fn new(accounts: &'a [AccountInfo<'info>]) -> Self {
+        if accounts.len() <= LINEAR_SCAN_LIMIT {
+            return Self::Linear(accounts);
+        }
+        let mut indices: Vec<usize> = (0..accounts.len()).collect();
+        indices.sort_by_key(|i| accounts[*i].key);
+        Self::Sorted(accounts, indices)
+    }
+
+    pub fn find(&self, key: &Pubkey) -> Option<&'a AccountInfo<'info>> {
+        match self {
+            Self::Linear(accounts) => accounts.iter().rev().find(|acc| acc.key == key),
+            Self::Sorted(accounts, indices) => {
+                // Past the last index with this key, which the stable sort
+                // keeps in the order the accounts were passed
+                let end = indices.partition_point(|i| accounts[*i].key <= key);
+                end.checked_sub(1)
+                    .map(|pos| &accounts[indices[pos]])
+                    .filter(|acc| acc.key == key)
+            }
+        }
+    }
+}
@@ -0,0 +1,64 @@
+use anchor_lang::prelude::*;
+
+use crate::{matching::AccountMatcher, IAccountMeta, InterfaceError, PreflightPayload};
+
+// Limits a caller puts on the accounts a target may request during preflight.
+// Requested accounts that are executable are checked against the program lists,
+// everything else against the account lists. `None` allow lists allow anything.
+#[derive(Debug, Clone, Default)]
+pub struct CallPolicy {
+    pub max_accounts: Option<usize>,
+    pub allowed_programs: Option<Vec<Pubkey>>,
+    pub denied_programs: Vec<Pubkey>,
+    pub allowed_accounts: Option<Vec<Pubkey>>,
+    pub denied_accounts: Vec<Pubkey>,
+    /// Downgrade every requested account to readonly instead of failing
+    pub readonly_only: bool,
+}
+
+impl CallPolicy {
+    // Checks `payload` against the policy, using `accounts` to tell programs apart.
+    // Errors carry the offending pubkey as the account name. `TooManyAccounts`
+    // names the first account over the limit, and compares the count to it.
+    pub fn apply(&self, payload: &mut PreflightPayload, accounts: &[AccountInfo]) -> Result<()> {
+        if let Some(max_accounts) = self.max_accounts {
+            if let Some(meta) = payload.accounts.get(max_accounts) {
+                return Err(error!(InterfaceError::TooManyAccounts)
+                    .with_account_name(meta.pubkey)
+                    .with_values((payload.accounts.len(), max_accounts)));
+            }
+        }
+
+        let matcher = AccountMatcher::new(accounts);
+        for meta in payload.accounts.iter_mut() {
+            let is_program = matches!(matcher.find(&meta.pubkey), Some(acc) if acc.executable);
+            let (allowed, denied) = if is_program {
+                (&self.allowed_programs, &self.denied_programs)
+            } else {
+                (&self.allowed_accounts, &self.denied_accounts)
+            };
+            check_lists(meta, allowed, denied).map_err(|e| e.with_account_name(meta.pubkey))?;
+
+            if self.readonly_only {
+                meta.writable = false;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn check_lists(
+    meta: &IAccountMeta,
+    allowed: &Option<Vec<Pubkey>>,
+    denied: &[Pubkey],
+) -> Result<()> {
+    if denied.contains(&meta.pubkey) {
+        return Err(error!(InterfaceError::AccountDenied));
+    }
+    if let Some(allowed) = allowed {
+        if !allowed.contains(&meta.pubkey) {
+            return Err(error!(InterfaceError::AccountNotAllowed));
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,181 @@
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::program::MAX_RETURN_DATA;
+
+use crate::{BalancePreview, FundingRequirement, IAccountMeta, PayerRole, PreflightPayload};
+
+// Canonical encodings of the wire format, exported to
+// `tests/fixtures/test_vectors.json` for other implementations to check against
+
+// The first 8 bytes of sha256("global:<instruction name>")
+pub const PREFLIGHT_TRANSFER_DISCRIMINATOR: [u8; 8] =
+    [0x9d, 0x84, 0xf5, 0x5a, 0x61, 0xea, 0x7b, 0xe2];
+pub const TRANSFER_DISCRIMINATOR: [u8; 8] = [0xa3, 0x34, 0xc8, 0xe7, 0x8c, 0x03, 0x45, 0xba];
+
+// Borsh size of one `IAccountMeta`: pubkey + signer + writable
+pub const IACCOUNT_META_SIZE: usize = 32 + 1 + 1;
+// The most requested accounts a payload without extensions can hold
+pub const MAX_PAYLOAD_ACCOUNTS: usize = (MAX_RETURN_DATA - 4) / IACCOUNT_META_SIZE;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TestVector {
+    pub name: &'static str,
+    pub bytes: Vec<u8>,
+}
+
+impl TestVector {
+    fn new(name: &'static str, value: &impl AnchorSerialize) -> Self {
+        Self {
+            name,
+            bytes: value.try_to_vec().unwrap(),
+        }
+    }
+}
+
+// Vectors use fixed keys, so their encodings never change
+pub fn key(seed: u8) -> Pubkey {
+    Pubkey::new_from_array([seed; 32])
+}
+
+fn meta(seed: u8, signer: bool, writable: bool) -> IAccountMeta {
+    IAccountMeta {
+        pubkey: key(seed),
+        signer,
+        writable,
+    }
+}
+
+fn metas(count: usize) -> Vec<IAccountMeta> {
+    (0..count)
+        .map(|i| meta(i as u8, false, i % 2 == 0))
+        .collect()
+}
+
+pub fn instruction_data() -> Vec<TestVector> {
+    let data = |discriminator: [u8; 8], amount: u64| {
+        let mut data = discriminator.to_vec();
+        data.extend_from_slice(&amount.to_le_bytes());
+        data
+    };
+    vec![
+        TestVector {
+            name: "preflight_transfer_0",
+            bytes: data(PREFLIGHT_TRANSFER_DISCRIMINATOR, 0),
+        },
+        TestVector {
+            name: "preflight_transfer_1",
+            bytes: data(PREFLIGHT_TRANSFER_DISCRIMINATOR, 1),
+        },
+        TestVector {
+            name: "preflight_transfer_max",
+            bytes: data(PREFLIGHT_TRANSFER_DISCRIMINATOR, u64::MAX),
+        },
+        TestVector {
+            name: "transfer_0",
+            bytes: data(TRANSFER_DISCRIMINATOR, 0),
+        },
+        TestVector {
+            name: "transfer_1",
+            bytes: data(TRANSFER_DISCRIMINATOR, 1),
+        },
+        TestVector {
+            name: "transfer_max",
+            bytes: data(TRANSFER_DISCRIMINATOR, u64::MAX),
+        },
+    ]
+}
+
+pub fn account_metas() -> Vec<TestVector> {
+    vec![
+        TestVector::new("readonly", &meta(1, false, false)),
+        TestVector::new("writable", &meta(2, false, true)),
+        TestVector::new("signer", &meta(3, true, false)),
+        TestVector::new("writable_signer", &meta(4, true, true)),
+    ]
+}
+
+// Payloads by name, see `payloads` for their encodings
+pub fn payload_values() -> Vec<(&'static str, PreflightPayload)> {
+    vec![
+        ("empty", PreflightPayload::new(vec![])),
+        ("one", PreflightPayload::new(metas(1))),
+        ("many", PreflightPayload::new(metas(8))),
+        (
+            "max_size",
+            PreflightPayload::new(metas(MAX_PAYLOAD_ACCOUNTS)),
+        ),
+        (
+            "funding",
+            PreflightPayload::new(metas(2)).with_funding(FundingRequirement {
+                lamports: 2_039_280,
+                payer: PayerRole::Authority,
+            }),
+        ),
+        (
+            "preview",
+            PreflightPayload::new(metas(2)).with_preview(BalancePreview::transfer(
+                key(10),
+                key(11),
+                key(12),
+                100,
+                1,
+            )),
+        ),
+        (
+            "signers",
+            PreflightPayload::new(metas(2)).with_signers(vec![key(13)]),
+        ),
+        (
+            "routers",
+            PreflightPayload::new(metas(2)).with_routers(vec![key(14), key(15)]),
+        ),
+        (
+            "all_extensions",
+            PreflightPayload::new(metas(3))
+                .with_funding(FundingRequirement {
+                    lamports: 1,
+                    payer: PayerRole::Payer,
+                })
+                .with_signers(vec![key(2)])
+                .with_preview(BalancePreview::transfer(key(10), key(11), key(11), 5, 2))
+                .with_routers(vec![key(14)]),
+        ),
+    ]
+}
+
+pub fn payloads() -> Vec<TestVector> {
+    payload_values()
+        .iter()
+        .map(|(name, payload)| TestVector::new(name, payload))
+        .collect()
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn json_vectors(vectors: &[TestVector]) -> String {
+    let entries: Vec<String> = vectors
+        .iter()
+        .map(|vector| {
+            format!(
+                "    {{ \"name\": \"{}\", \"hex\": \"{}\" }}",
+                vector.name,
+                hex(&vector.bytes)
+            )
+        })
+        .collect();
+    format!("[\n{}\n  ]", entries.join(",\n"))
+}
+
+// Every vector as the JSON stored in `tests/fixtures/test_vectors.json`
+pub fn to_json() -> String {
+    format!(
+        "{{\n  \"discriminators\": {{ \"preflight_transfer\": \"{}\", \"transfer\": \"{}\" }},\n  \"iaccount_meta_size\": {},\n  \"instruction_data\": {},\n  \"account_metas\": {},\n  \"payloads\": {}\n}}\n",
+        hex(&PREFLIGHT_TRANSFER_DISCRIMINATOR),
+        hex(&TRANSFER_DISCRIMINATOR),
+        IACCOUNT_META_SIZE,
+        json_vectors(&instruction_data()),
+        json_vectors(&account_metas()),
+        json_vectors(&payloads()),
+    )
+}
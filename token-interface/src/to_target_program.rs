@@ -1,15 +1,28 @@
 use anchor_lang::prelude::*;
 
+use crate::PayerRole;
+
 // This trait allows an anchor Context to be
 // converted into a new Context with one of it's named accounts
 // as the target program.
 pub trait ToTargetProgram<'info> {
-    type TargetCtx<'_info>: ToAccountInfos<'_info> + ToAccountMetas;
+    type TargetCtx: ToAccountInfos<'info> + ToAccountMetas;
 
     fn to_target_program(&self) -> Pubkey;
-    fn get_target_program(&self) -> AccountInfo<'info>;
+    // The account of `to_target_program`. Contexts that don't hold it look it
+    // up in `remaining_accounts`, and return `None` when it wasn't passed.
+    fn get_target_program(
+        &self,
+        remaining_accounts: &[AccountInfo<'info>],
+    ) -> Option<AccountInfo<'info>>;
     fn to_target_context(
         &self,
         remaining_accounts: Vec<AccountInfo<'info>>,
-    ) -> CpiContext<'_, '_, '_, 'info, Self::TargetCtx<'info>>;
+    ) -> CpiContext<'_, '_, '_, 'info, Self::TargetCtx>;
+
+    // Returns the account that fills `role` when a target requests funding.
+    // Contexts that can't pay for new accounts can leave this as `None`.
+    fn get_payer(&self, _role: &PayerRole) -> Option<AccountInfo<'info>> {
+        None
+    }
 }
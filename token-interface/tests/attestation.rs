@@ -0,0 +1,73 @@
+// Anchor's derives use cfgs that newer compilers warn about
+#![allow(unknown_lints, unexpected_cfgs)]
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::sysvar::instructions::{
+    self, construct_instructions_data, store_current_index, BorrowedInstruction,
+};
+use token_interface::{Caller, CallerAttestation, InterfaceError, PreflightPayload};
+
+mod common;
+use common::{Runtime, TestAccount};
+
+// An instructions sysvar whose current instruction calls `program_id`
+fn instructions_sysvar(program_id: &Pubkey) -> TestAccount {
+    let mut data = construct_instructions_data(&[BorrowedInstruction {
+        program_id,
+        accounts: vec![],
+        data: &[],
+    }]);
+    store_current_index(&mut data, 0);
+
+    let mut sysvar = TestAccount::new(instructions::ID);
+    sysvar.data = data;
+    sysvar
+}
+
+fn attest(top_level: &Pubkey, stack_height: u64) -> CallerAttestation {
+    let _runtime = Runtime::install(Pubkey::default(), &PreflightPayload::default());
+    Runtime::set_stack_height(stack_height);
+    let mut sysvar = instructions_sysvar(top_level);
+    CallerAttestation::find(&[sysvar.info()]).unwrap()
+}
+
+#[test]
+fn top_level_instructions_are_called_by_the_transaction() {
+    let program = Pubkey::new_unique();
+    let attestation = attest(&program, 1);
+    assert_eq!(attestation.top_level_program, program);
+    assert_eq!(attestation.caller, Caller::Transaction);
+}
+
+#[test]
+fn first_cpi_is_called_by_the_top_level_program() {
+    let router = Pubkey::new_unique();
+    let attestation = attest(&router, 2);
+    assert_eq!(attestation.caller, Caller::Program(router));
+    attestation.require_router(&[router]).unwrap();
+    assert_eq!(
+        attestation.require_router(&[Pubkey::new_unique()]),
+        Err(error!(InterfaceError::RouterRequired))
+    );
+}
+
+#[test]
+fn nested_cpi_caller_is_unknown() {
+    let router = Pubkey::new_unique();
+    let attestation = attest(&router, 3);
+    assert_eq!(attestation.top_level_program, router);
+    assert_eq!(attestation.caller, Caller::Unknown);
+    assert_eq!(
+        attestation.require_router(&[router]),
+        Err(error!(InterfaceError::RouterRequired))
+    );
+}
+
+#[test]
+fn requires_the_instructions_sysvar() {
+    let mut impostor = instructions_sysvar(&Pubkey::new_unique());
+    impostor.key = Pubkey::new_unique();
+    assert_eq!(
+        CallerAttestation::find(&[impostor.info()]).unwrap_err(),
+        error!(InterfaceError::InstructionsSysvarMissing)
+    );
+}
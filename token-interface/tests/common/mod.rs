@@ -0,0 +1,123 @@
+// Helpers shared by the integration tests
+#![allow(dead_code)]
+
+use std::sync::{Mutex, MutexGuard};
+
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::{
+    entrypoint::ProgramResult,
+    instruction::Instruction,
+    program_stubs::{set_syscall_stubs, SyscallStubs},
+};
+use anchor_lang::system_program;
+use token_interface::PreflightPayload;
+
+static SERIAL: Mutex<()> = Mutex::new(());
+static LOGS: Mutex<Vec<String>> = Mutex::new(Vec::new());
+static INVOKED: Mutex<Vec<Instruction>> = Mutex::new(Vec::new());
+static RETURN_DATA: Mutex<Option<(Pubkey, Vec<u8>)>> = Mutex::new(None);
+static STACK_HEIGHT: Mutex<u64> = Mutex::new(1);
+
+// Syscall stubs that record logs and CPIs, and answer every preflight
+// with the same payload. Stubs are global, so tests using them take turns.
+pub struct Runtime;
+
+impl Runtime {
+    // Installs the stubs until the returned guard is dropped
+    pub fn install(target: Pubkey, payload: &PreflightPayload) -> MutexGuard<'static, ()> {
+        let serial = SERIAL.lock().unwrap_or_else(|e| e.into_inner());
+        set_syscall_stubs(Box::new(Runtime));
+        *RETURN_DATA.lock().unwrap() = Some((target, payload.try_to_vec().unwrap()));
+        LOGS.lock().unwrap().clear();
+        INVOKED.lock().unwrap().clear();
+        *STACK_HEIGHT.lock().unwrap() = 1;
+        serial
+    }
+
+    pub fn logs() -> Vec<String> {
+        LOGS.lock().unwrap().clone()
+    }
+
+    pub fn invoked() -> Vec<Instruction> {
+        INVOKED.lock().unwrap().clone()
+    }
+
+    pub fn set_stack_height(stack_height: u64) {
+        *STACK_HEIGHT.lock().unwrap() = stack_height;
+    }
+}
+
+impl SyscallStubs for Runtime {
+    fn sol_log(&self, message: &str) {
+        LOGS.lock().unwrap().push(message.to_string());
+    }
+    fn sol_invoke_signed(
+        &self,
+        instruction: &Instruction,
+        _account_infos: &[AccountInfo],
+        _signers_seeds: &[&[&[u8]]],
+    ) -> ProgramResult {
+        INVOKED.lock().unwrap().push(instruction.clone());
+        Ok(())
+    }
+    fn sol_get_return_data(&self) -> Option<(Pubkey, Vec<u8>)> {
+        RETURN_DATA.lock().unwrap().clone()
+    }
+    fn sol_get_stack_height(&self) -> u64 {
+        *STACK_HEIGHT.lock().unwrap()
+    }
+}
+
+// Owns the data behind an `AccountInfo`
+pub struct TestAccount {
+    pub key: Pubkey,
+    pub owner: Pubkey,
+    pub lamports: u64,
+    pub data: Vec<u8>,
+    pub is_signer: bool,
+    pub is_writable: bool,
+    pub executable: bool,
+}
+
+impl TestAccount {
+    pub fn new(key: Pubkey) -> Self {
+        Self {
+            key,
+            owner: system_program::ID,
+            lamports: 0,
+            data: vec![],
+            is_signer: false,
+            is_writable: false,
+            executable: false,
+        }
+    }
+
+    pub fn signer(mut self) -> Self {
+        self.is_signer = true;
+        self
+    }
+
+    pub fn writable(mut self) -> Self {
+        self.is_writable = true;
+        self
+    }
+
+    pub fn program(mut self) -> Self {
+        self.executable = true;
+        self.owner = Pubkey::new_unique();
+        self
+    }
+
+    pub fn info(&mut self) -> AccountInfo<'_> {
+        AccountInfo::new(
+            &self.key,
+            self.is_signer,
+            self.is_writable,
+            &mut self.lamports,
+            &mut self.data,
+            &self.owner,
+            self.executable,
+            0,
+        )
+    }
+}
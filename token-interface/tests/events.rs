@@ -0,0 +1,94 @@
+#![allow(unknown_lints, unexpected_cfgs)]
+
+use anchor_lang::prelude::*;
+use anchor_lang::Event;
+use token_interface::{InterfaceTransfer, TransferRoute, PROGRAM_DATA_LOG_PREFIX};
+
+fn sample() -> InterfaceTransfer {
+    InterfaceTransfer {
+        program: Pubkey::new_unique(),
+        mint: Pubkey::new_unique(),
+        owner: Pubkey::new_unique(),
+        to: Pubkey::new_unique(),
+        authority: Pubkey::new_unique(),
+        amount: 42,
+        route: TransferRoute::Interface,
+    }
+}
+
+#[test]
+fn decodes_emitted_log_line() {
+    let event = sample();
+    let log = format!(
+        "{}{}",
+        PROGRAM_DATA_LOG_PREFIX,
+        base64::encode(event.data())
+    );
+    assert_eq!(InterfaceTransfer::from_log(&log), Some(event));
+}
+
+fn data_log(event: &InterfaceTransfer) -> String {
+    format!(
+        "{}{}",
+        PROGRAM_DATA_LOG_PREFIX,
+        base64::encode(event.data())
+    )
+}
+
+#[test]
+fn skips_unrelated_logs() {
+    let event = sample();
+    let logs = [
+        format!("Program {} invoke [1]", event.program),
+        "Program log: Instruction: Transfer".to_string(),
+        format!("{}{}", PROGRAM_DATA_LOG_PREFIX, base64::encode([1u8; 16])),
+        data_log(&event),
+        format!(
+            "Program {} consumed 5000 of 200000 compute units",
+            event.program
+        ),
+        format!("Program {} success", event.program),
+    ];
+    let events = InterfaceTransfer::from_logs(logs.iter().map(String::as_str));
+    assert_eq!(events, vec![(event.program, event)]);
+}
+
+#[test]
+fn tracks_the_emitter_of_nested_events() {
+    let wrapper = Pubkey::new_unique();
+    let mut direct = sample();
+    direct.route = TransferRoute::Direct;
+    let wrapped = InterfaceTransfer {
+        route: TransferRoute::Interface,
+        ..direct.clone()
+    };
+    let logs = [
+        format!("Program {} invoke [1]", wrapper),
+        format!("Program {} invoke [2]", direct.program),
+        data_log(&direct),
+        format!("Program {} success", direct.program),
+        data_log(&wrapped),
+        format!("Program {} success", wrapper),
+    ];
+    let events = InterfaceTransfer::from_logs(logs.iter().map(String::as_str));
+    assert_eq!(events, vec![(direct.program, direct), (wrapper, wrapped)]);
+}
+
+#[test]
+fn drops_direct_events_spoofed_by_other_programs() {
+    let mut event = sample();
+    event.route = TransferRoute::Direct;
+    let foreign = Pubkey::new_unique();
+    let logs = [
+        format!("Program {} invoke [1]", foreign),
+        data_log(&event),
+        format!("Program {} failed: custom program error: 0x1", foreign),
+        // Outside of any invocation, or once logs are cut off, nothing counts
+        data_log(&event),
+        format!("Program {} invoke [1]", event.program),
+        "Log truncated".to_string(),
+        data_log(&event),
+    ];
+    let events = InterfaceTransfer::from_logs(logs.iter().map(String::as_str));
+    assert!(events.is_empty());
+}
@@ -0,0 +1,111 @@
+// Anchor's derives use cfgs that newer compilers warn about
+#![allow(unknown_lints, unexpected_cfgs)]
+use anchor_lang::prelude::*;
+use token_interface::{call, IAccountMeta, ITransfer, LogLevel, PreflightPayload};
+
+mod common;
+use common::{Runtime, TestAccount};
+
+// Calls `transfer` through `call` at `log_level`, with a target that requests
+// `requested` accounts and a caller that passes `passed`. Returns the call result and its logs.
+fn transfer(log_level: LogLevel, requested: usize, passed: usize) -> (Result<()>, Vec<String>) {
+    let mut owner = TestAccount::new(Pubkey::new_unique());
+    let mut to = TestAccount::new(Pubkey::new_unique());
+    let mut authority = TestAccount::new(Pubkey::new_unique()).signer();
+    let mut mint = TestAccount::new(Pubkey::new_unique()).program();
+    let mut extras: Vec<TestAccount> = (0..requested.max(passed))
+        .map(|_| TestAccount::new(Pubkey::new_unique()))
+        .collect();
+
+    let payload = PreflightPayload::new(
+        extras[..requested]
+            .iter()
+            .map(|acc| IAccountMeta {
+                pubkey: acc.key,
+                signer: false,
+                writable: false,
+            })
+            .collect(),
+    );
+    let _runtime = Runtime::install(mint.key, &payload);
+
+    let authority_info = authority.info();
+    let mint_info = mint.info();
+    let accounts = ITransfer {
+        owner: owner.info(),
+        to: to.info(),
+        authority: Signer::try_from(&authority_info).unwrap(),
+        mint: mint_info.clone(),
+    };
+    let remaining = extras[..passed].iter_mut().map(|acc| acc.info()).collect();
+    let ctx = CpiContext::new(mint_info, accounts).with_remaining_accounts(remaining);
+
+    let result = call(
+        "transfer".to_string(),
+        ctx,
+        1u64.to_le_bytes().to_vec(),
+        log_level,
+    );
+    (result, Runtime::logs())
+}
+
+#[cfg(not(feature = "logging"))]
+#[test]
+fn nothing_is_logged_without_the_feature() {
+    for log_level in [LogLevel::Off, LogLevel::Summary, LogLevel::Verbose] {
+        assert!(!log_level.enabled(LogLevel::Summary));
+        let (result, logs) = transfer(log_level, 2, 2);
+        result.unwrap();
+        assert_eq!(logs, Vec::<String>::new());
+    }
+}
+
+#[cfg(feature = "logging")]
+#[test]
+fn off_logs_nothing() {
+    let (result, logs) = transfer(LogLevel::Off, 2, 2);
+    result.unwrap();
+    assert_eq!(logs, Vec::<String>::new());
+}
+
+#[cfg(feature = "logging")]
+#[test]
+fn summary_logs_each_step() {
+    let (result, logs) = transfer(LogLevel::Summary, 2, 2);
+    result.unwrap();
+    assert_eq!(
+        logs,
+        vec![
+            "Preflight transfer",
+            "Target requested 2 accounts",
+            "Execute transfer",
+            "IX accounts: 6",
+        ]
+    );
+}
+
+#[cfg(feature = "logging")]
+#[test]
+fn verbose_dumps_payload_and_accounts() {
+    let (result, logs) = transfer(LogLevel::Verbose, 2, 2);
+    result.unwrap();
+    assert_eq!(logs.len(), 11);
+    assert!(logs[2].starts_with("Additional interface accounts: PreflightPayload"));
+    assert_eq!(
+        logs.iter()
+            .filter(|log| log.starts_with("Account: "))
+            .count(),
+        6
+    );
+}
+
+#[cfg(feature = "logging")]
+#[test]
+fn summary_logs_why_a_call_failed() {
+    let (result, logs) = transfer(LogLevel::Summary, 2, 1);
+    assert!(result.is_err());
+    assert!(logs
+        .last()
+        .unwrap()
+        .starts_with("Requested account missing: "));
+}
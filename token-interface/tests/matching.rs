@@ -0,0 +1,97 @@
+// Anchor's derives use cfgs that newer compilers warn about
+#![allow(unknown_lints, unexpected_cfgs)]
+use anchor_lang::prelude::*;
+use token_interface::matching::{AccountMatcher, LINEAR_SCAN_LIMIT};
+use token_interface::{IAccountMeta, PreflightPayload};
+
+mod common;
+use common::TestAccount;
+
+// Sizes on both sides of the switch to the sorted matcher
+const SIZES: [usize; 4] = [
+    1,
+    LINEAR_SCAN_LIMIT,
+    LINEAR_SCAN_LIMIT + 1,
+    3 * LINEAR_SCAN_LIMIT,
+];
+
+// `len` accounts with descending keys, so the sorted matcher reorders them.
+// Each account's lamports hold its position.
+fn accounts(len: usize) -> Vec<TestAccount> {
+    let mut keys: Vec<Pubkey> = (0..len).map(|_| Pubkey::new_unique()).collect();
+    keys.reverse();
+    keys.into_iter()
+        .enumerate()
+        .map(|(i, key)| {
+            let mut account = TestAccount::new(key);
+            account.lamports = i as u64;
+            account
+        })
+        .collect()
+}
+
+// Passes the first key again in the middle and at the end
+fn with_duplicates(len: usize) -> Vec<TestAccount> {
+    let mut accounts = accounts(len);
+    let key = accounts[0].key;
+    accounts[len / 2].key = key;
+    accounts[len - 1].key = key;
+    accounts
+}
+
+#[test]
+fn finds_every_passed_account() {
+    for len in SIZES {
+        let mut accounts = accounts(len);
+        let infos: Vec<AccountInfo> = accounts.iter_mut().map(|acc| acc.info()).collect();
+        let matcher = AccountMatcher::new(&infos);
+        assert_eq!(
+            matches!(matcher, AccountMatcher::Linear(_)),
+            len <= LINEAR_SCAN_LIMIT
+        );
+
+        for (i, info) in infos.iter().enumerate() {
+            assert_eq!(matcher.find(info.key).unwrap().lamports(), i as u64);
+        }
+        assert!(matcher.find(&Pubkey::new_unique()).is_none());
+        assert!(matcher.find(&Pubkey::default()).is_none());
+    }
+}
+
+#[test]
+fn duplicate_keys_match_their_last_occurrence() {
+    for len in SIZES.into_iter().filter(|len| *len > 2) {
+        let mut accounts = with_duplicates(len);
+        let infos: Vec<AccountInfo> = accounts.iter_mut().map(|acc| acc.info()).collect();
+        let matcher = AccountMatcher::new(&infos);
+
+        let found = matcher.find(infos[0].key).unwrap();
+        assert_eq!(found.lamports(), len as u64 - 1);
+        // Keys around the duplicates are unaffected
+        assert_eq!(matcher.find(infos[1].key).unwrap().lamports(), 1);
+        assert_eq!(
+            matcher.find(infos[len - 2].key).unwrap().lamports(),
+            len as u64 - 2
+        );
+    }
+}
+
+#[test]
+fn match_accounts_uses_the_last_occurrence() {
+    for len in SIZES.into_iter().filter(|len| *len > 2) {
+        let mut accounts = with_duplicates(len);
+        accounts[len - 1].is_writable = true;
+        let key = accounts[0].key;
+        let infos: Vec<AccountInfo> = accounts.iter_mut().map(|acc| acc.info()).collect();
+
+        let payload = PreflightPayload::new(vec![IAccountMeta {
+            pubkey: key,
+            signer: false,
+            writable: true,
+        }]);
+        let matched = payload.match_accounts(&infos).unwrap();
+        assert_eq!(matched.len(), 1);
+        assert!(matched[0].is_writable);
+        assert_eq!(matched[0].lamports(), len as u64 - 1);
+    }
+}
@@ -0,0 +1,134 @@
+// Anchor's derives use cfgs that newer compilers warn about
+#![allow(unknown_lints, unexpected_cfgs)]
+use anchor_lang::error::{ComparedValues, ErrorOrigin};
+use anchor_lang::prelude::*;
+use token_interface::{CallPolicy, IAccountMeta, InterfaceError, PreflightPayload};
+
+mod common;
+use common::TestAccount;
+
+fn request(keys: &[Pubkey]) -> PreflightPayload {
+    PreflightPayload::new(
+        keys.iter()
+            .map(|key| IAccountMeta {
+                pubkey: *key,
+                signer: false,
+                writable: true,
+            })
+            .collect(),
+    )
+}
+
+// The account name and compared values `err` carries
+fn details(err: Error) -> (Option<String>, Option<(String, String)>) {
+    match err {
+        Error::AnchorError(err) => (
+            match err.error_origin {
+                Some(ErrorOrigin::AccountName(name)) => Some(name),
+                _ => None,
+            },
+            match err.compared_values {
+                Some(ComparedValues::Values(values)) => Some(values),
+                _ => None,
+            },
+        ),
+        Error::ProgramError(_) => (None, None),
+    }
+}
+
+#[test]
+fn max_accounts_is_inclusive() {
+    let keys: Vec<Pubkey> = (0..4).map(|_| Pubkey::new_unique()).collect();
+    let policy = CallPolicy {
+        max_accounts: Some(3),
+        ..Default::default()
+    };
+
+    policy.apply(&mut request(&keys[..3]), &[]).unwrap();
+    let err = policy.apply(&mut request(&keys), &[]).unwrap_err();
+    assert_eq!(err, error!(InterfaceError::TooManyAccounts));
+    assert_eq!(
+        details(err),
+        (
+            Some(keys[3].to_string()),
+            Some(("4".to_string(), "3".to_string()))
+        )
+    );
+}
+
+#[test]
+fn programs_and_accounts_use_separate_lists() {
+    let mut program = TestAccount::new(Pubkey::new_unique()).program();
+    let account = Pubkey::new_unique();
+    let program_info = program.info();
+    let keys = [program_info.key(), account];
+    let accounts = [program_info.clone()];
+
+    // A program is only checked against the program lists
+    let policy = CallPolicy {
+        allowed_programs: Some(vec![program_info.key()]),
+        allowed_accounts: Some(vec![account]),
+        ..Default::default()
+    };
+    policy.apply(&mut request(&keys), &accounts).unwrap();
+    let policy = CallPolicy {
+        allowed_programs: Some(vec![program_info.key()]),
+        denied_accounts: vec![program_info.key()],
+        ..Default::default()
+    };
+    policy.apply(&mut request(&keys), &accounts).unwrap();
+
+    // Without its account info, the program is treated as an account
+    let err = policy.apply(&mut request(&keys), &[]).unwrap_err();
+    assert_eq!(err, error!(InterfaceError::AccountDenied));
+
+    let policy = CallPolicy {
+        denied_programs: vec![program_info.key()],
+        ..Default::default()
+    };
+    let err = policy.apply(&mut request(&keys), &accounts).unwrap_err();
+    assert_eq!(err, error!(InterfaceError::AccountDenied));
+    let policy = CallPolicy {
+        allowed_programs: Some(vec![]),
+        ..Default::default()
+    };
+    let err = policy.apply(&mut request(&keys), &accounts).unwrap_err();
+    assert_eq!(err, error!(InterfaceError::AccountNotAllowed));
+}
+
+#[test]
+fn rejections_name_the_account() {
+    let keys: Vec<Pubkey> = (0..3).map(|_| Pubkey::new_unique()).collect();
+
+    let policy = CallPolicy {
+        denied_accounts: vec![keys[1]],
+        ..Default::default()
+    };
+    let err = policy.apply(&mut request(&keys), &[]).unwrap_err();
+    assert_eq!(err, error!(InterfaceError::AccountDenied));
+    assert_eq!(details(err), (Some(keys[1].to_string()), None));
+
+    let policy = CallPolicy {
+        allowed_accounts: Some(keys[..2].to_vec()),
+        ..Default::default()
+    };
+    let err = policy.apply(&mut request(&keys), &[]).unwrap_err();
+    assert_eq!(err, error!(InterfaceError::AccountNotAllowed));
+    assert_eq!(details(err), (Some(keys[2].to_string()), None));
+}
+
+#[test]
+fn readonly_only_downgrades_every_account() {
+    let keys: Vec<Pubkey> = (0..3).map(|_| Pubkey::new_unique()).collect();
+    let mut payload = request(&keys);
+
+    CallPolicy::default().apply(&mut payload, &[]).unwrap();
+    assert!(payload.accounts.iter().all(|acc| acc.writable));
+    let policy = CallPolicy {
+        readonly_only: true,
+        ..Default::default()
+    };
+    policy.apply(&mut payload, &[]).unwrap();
+    assert!(payload.accounts.iter().all(|acc| !acc.writable));
+    assert_eq!(payload.accounts.len(), 3);
+}
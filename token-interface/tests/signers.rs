@@ -0,0 +1,118 @@
+// Anchor's derives use cfgs that newer compilers warn about
+#![allow(unknown_lints, unexpected_cfgs)]
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::instruction::Instruction;
+use token_interface::{
+    call, IAccountMeta, ITransfer, InterfaceError, LogLevel, PreflightPayload, EXTENSIONS_HINT,
+};
+
+mod common;
+use common::{Runtime, TestAccount};
+
+// Calls `transfer` through `call` with a target that requests `delegate`
+// and answers with `payload`. Returns the call result and the `transfer` CPI, if any.
+fn transfer(
+    delegate: &mut TestAccount,
+    payload: PreflightPayload,
+) -> (Result<()>, Option<Instruction>) {
+    let mut owner = TestAccount::new(Pubkey::new_unique());
+    let mut to = TestAccount::new(Pubkey::new_unique());
+    let mut authority = TestAccount::new(Pubkey::new_unique()).signer();
+    let mut mint = TestAccount::new(Pubkey::new_unique()).program();
+    let _runtime = Runtime::install(mint.key, &payload);
+
+    let authority_info = authority.info();
+    let mint_info = mint.info();
+    let accounts = ITransfer {
+        owner: owner.info(),
+        to: to.info(),
+        authority: Signer::try_from(&authority_info).unwrap(),
+        mint: mint_info.clone(),
+    };
+    let ctx = CpiContext::new(mint_info, accounts).with_remaining_accounts(vec![delegate.info()]);
+
+    let result = call(
+        "transfer".to_string(),
+        ctx,
+        1u64.to_le_bytes().to_vec(),
+        LogLevel::Off,
+    );
+    // The first CPI is preflight
+    (result, Runtime::invoked().get(1).cloned())
+}
+
+fn request(delegate: Pubkey, signer: bool) -> PreflightPayload {
+    PreflightPayload::new(vec![IAccountMeta {
+        pubkey: delegate,
+        signer,
+        writable: false,
+    }])
+}
+
+#[test]
+fn listed_signers_are_passed_as_signers() {
+    let mut delegate = TestAccount::new(Pubkey::new_unique()).signer();
+    let payload = request(delegate.key, false).with_signers(vec![delegate.key]);
+
+    let (result, ix) = transfer(&mut delegate, payload);
+    result.unwrap();
+    let ix = ix.unwrap();
+    assert_eq!(ix.accounts.len(), 5);
+    assert_eq!(ix.accounts[4].pubkey, delegate.key);
+    assert!(ix.accounts[4].is_signer);
+}
+
+#[test]
+fn listed_signers_must_have_signed() {
+    let mut delegate = TestAccount::new(Pubkey::new_unique());
+    let payload = request(delegate.key, false).with_signers(vec![delegate.key]);
+
+    let (result, ix) = transfer(&mut delegate, payload);
+    assert_eq!(
+        result.unwrap_err(),
+        error!(InterfaceError::MissingRequiredSigner)
+    );
+    assert!(ix.is_none());
+}
+
+#[test]
+fn flagged_accounts_must_have_signed() {
+    let mut delegate = TestAccount::new(Pubkey::new_unique());
+    let payload = request(delegate.key, true);
+
+    let (result, ix) = transfer(&mut delegate, payload);
+    assert_eq!(
+        result.unwrap_err(),
+        error!(InterfaceError::MissingRequiredSigner)
+    );
+    assert!(ix.is_none());
+}
+
+#[test]
+fn unlisted_accounts_are_not_signers() {
+    let mut delegate = TestAccount::new(Pubkey::new_unique()).signer();
+    let payload = request(delegate.key, false);
+
+    let (result, ix) = transfer(&mut delegate, payload);
+    result.unwrap();
+    assert!(!ix.unwrap().accounts[4].is_signer);
+}
+
+#[test]
+fn callers_without_the_extensions_hint_get_listed_signers_flagged() {
+    let mut delegate = TestAccount::new(Pubkey::new_unique()).signer();
+    let mut hint = TestAccount::new(EXTENSIONS_HINT);
+    let payload = request(delegate.key, false).with_signers(vec![delegate.key]);
+
+    let bare = payload.clone().for_caller(&[delegate.info()]);
+    assert!(bare.extensions.is_empty());
+    assert!(bare.accounts[0].signer);
+    assert_eq!(
+        bare.try_to_vec().unwrap(),
+        bare.accounts.try_to_vec().unwrap()
+    );
+
+    let extended = payload.for_caller(&[delegate.info(), hint.info()]);
+    assert_eq!(extended.extensions.len(), 1);
+    assert!(!extended.accounts[0].signer);
+}
@@ -0,0 +1,122 @@
+// Anchor's derives use cfgs that newer compilers warn about
+#![allow(unknown_lints, unexpected_cfgs)]
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::{hash, program::MAX_RETURN_DATA};
+use token_interface::test_vectors::{self, *};
+use token_interface::{
+    decode_preflight_return_data, interface_instruction, IAccountMeta, PreflightPayload,
+};
+
+const FIXTURE: &str = "tests/fixtures/test_vectors.json";
+
+// Run with `UPDATE_TEST_VECTORS=1` to rewrite the fixture after an intended format change
+#[test]
+fn vectors_match_fixture() {
+    let json = test_vectors::to_json();
+    if std::env::var("UPDATE_TEST_VECTORS").is_ok() {
+        std::fs::write(FIXTURE, &json).unwrap();
+    }
+    assert_eq!(json, std::fs::read_to_string(FIXTURE).unwrap());
+}
+
+#[test]
+fn discriminators_are_derived_from_instruction_names() {
+    let sighash = |name: &str| hash::hash(format!("global:{}", name).as_bytes()).to_bytes();
+    assert_eq!(
+        sighash("preflight_transfer")[..8],
+        PREFLIGHT_TRANSFER_DISCRIMINATOR
+    );
+    assert_eq!(sighash("transfer")[..8], TRANSFER_DISCRIMINATOR);
+}
+
+#[test]
+fn instruction_data_matches_call() {
+    for vector in instruction_data() {
+        let (name, amount) = vector.name.rsplit_once('_').unwrap();
+        let amount = match amount {
+            "max" => u64::MAX,
+            amount => amount.parse().unwrap(),
+        };
+        let ix = interface_instruction(
+            Pubkey::default(),
+            name,
+            &amount.to_le_bytes(),
+            vec![],
+            &PreflightPayload::default(),
+        );
+        assert_eq!(ix.data, vector.bytes, "{}", vector.name);
+    }
+}
+
+#[test]
+fn account_metas_are_34_bytes() {
+    assert_eq!(IACCOUNT_META_SIZE, 34);
+    for vector in account_metas() {
+        assert_eq!(vector.bytes.len(), IACCOUNT_META_SIZE, "{}", vector.name);
+        let meta = IAccountMeta::try_from_slice(&vector.bytes).unwrap();
+        assert_eq!(meta.try_to_vec().unwrap(), vector.bytes);
+    }
+}
+
+#[test]
+fn payloads_round_trip() {
+    for vector in payloads() {
+        let payload = PreflightPayload::try_from_slice(&vector.bytes).unwrap();
+        assert_eq!(
+            payload.try_to_vec().unwrap(),
+            vector.bytes,
+            "{}",
+            vector.name
+        );
+    }
+}
+
+#[test]
+fn payloads_without_extensions_are_account_lists() {
+    for (name, payload) in payload_values() {
+        if !payload.extensions.is_empty() {
+            continue;
+        }
+        let bytes = payload.try_to_vec().unwrap();
+        assert_eq!(bytes.len(), 4 + payload.accounts.len() * IACCOUNT_META_SIZE);
+        assert_eq!(bytes, payload.accounts.try_to_vec().unwrap(), "{}", name);
+    }
+}
+
+#[test]
+fn max_size_payload_fills_return_data() {
+    let max_size = payloads()
+        .into_iter()
+        .find(|vector| vector.name == "max_size")
+        .unwrap();
+    assert_eq!(MAX_PAYLOAD_ACCOUNTS, 30);
+    assert_eq!(max_size.bytes.len(), MAX_RETURN_DATA);
+}
+
+// Simulations report return data without its trailing zeros
+#[test]
+fn truncated_return_data_decodes() {
+    for vector in payloads() {
+        let len = vector
+            .bytes
+            .iter()
+            .rposition(|byte| *byte != 0)
+            .map_or(0, |i| i + 1);
+        let payload = decode_preflight_return_data(&vector.bytes[..len]).unwrap();
+        assert_eq!(
+            payload.try_to_vec().unwrap(),
+            vector.bytes,
+            "{}",
+            vector.name
+        );
+    }
+    let payload = decode_preflight_return_data(&[]).unwrap();
+    assert!(payload.accounts.is_empty() && payload.extensions.is_empty());
+}
+
+#[test]
+fn return_data_past_the_payload_is_rejected() {
+    let mut data = PreflightPayload::default().try_to_vec().unwrap();
+    data.extend_from_slice(&[0, 0, 0, 0, 1]);
+    assert!(decode_preflight_return_data(&data).is_err());
+}
@@ -0,0 +1,218 @@
+// Anchor's derives use cfgs that newer compilers warn about
+#![allow(unknown_lints, unexpected_cfgs)]
+use anchor_lang::prelude::*;
+use anchor_lang::system_program;
+use token_interface::{call, ITransfer, ITransferWithPayer, LogLevel, PayerRole, ToTargetProgram};
+
+mod common;
+use common::TestAccount;
+
+declare_id!("32d7pdBNmDmXAXcHkivteqLjaNVovWQ1JMn85LdyyAux");
+
+#[derive(Accounts, ToTargetProgram)]
+pub struct EscrowTransfer<'info> {
+    /// CHECK:
+    pub owner: AccountInfo<'info>,
+    /// CHECK:
+    pub to: AccountInfo<'info>,
+    pub authority: Signer<'info>,
+    /// CHECK:
+    #[target_program]
+    pub mint: AccountInfo<'info>,
+    /// CHECK:
+    #[account(mut)]
+    pub escrow: AccountInfo<'info>,
+    pub system_program: Program<'info, System>,
+}
+
+// These only need to compile: `call` must accept both contexts as-is
+#[allow(dead_code)]
+fn call_itransfer<'info>(ctx: CpiContext<'_, '_, '_, 'info, ITransfer<'info>>) -> Result<()> {
+    call(
+        "transfer".to_string(),
+        ctx,
+        1u64.to_le_bytes().to_vec(),
+        LogLevel::Off,
+    )
+}
+
+#[allow(dead_code)]
+fn call_escrow<'info>(ctx: CpiContext<'_, '_, '_, 'info, EscrowTransfer<'info>>) -> Result<()> {
+    call(
+        "transfer".to_string(),
+        ctx,
+        1u64.to_le_bytes().to_vec(),
+        LogLevel::Off,
+    )
+}
+
+#[test]
+fn itransfer_targets_mint() {
+    let mut owner = TestAccount::new(Pubkey::new_unique());
+    let mut to = TestAccount::new(Pubkey::new_unique());
+    let mut authority = TestAccount::new(Pubkey::new_unique()).signer();
+    let mut mint = TestAccount::new(Pubkey::new_unique()).program();
+    let mut extra = TestAccount::new(Pubkey::new_unique());
+    let mint_key = mint.key;
+    let extra_key = extra.key;
+
+    let authority_info = authority.info();
+    let accounts = ITransfer {
+        owner: owner.info(),
+        to: to.info(),
+        authority: Signer::try_from(&authority_info).unwrap(),
+        mint: mint.info(),
+    };
+
+    assert_eq!(accounts.to_target_program(), mint_key);
+    assert_eq!(accounts.get_target_program(&[]).unwrap().key(), mint_key);
+
+    let ctx = accounts.to_target_context(vec![extra.info()]);
+    assert_eq!(ctx.program.key(), mint_key);
+    assert_eq!(ctx.accounts.to_account_metas(None).len(), 4);
+    assert_eq!(ctx.remaining_accounts.len(), 1);
+    assert_eq!(ctx.remaining_accounts[0].key(), extra_key);
+}
+
+#[test]
+fn itransfer_targets_the_program_owning_a_mint_state() {
+    let mut owner = TestAccount::new(Pubkey::new_unique());
+    let mut to = TestAccount::new(Pubkey::new_unique());
+    let mut authority = TestAccount::new(Pubkey::new_unique()).signer();
+    let mut program = TestAccount::new(Pubkey::new_unique()).program();
+    let mut mint = TestAccount::new(Pubkey::new_unique());
+    mint.owner = program.key;
+    let program_key = program.key;
+
+    let authority_info = authority.info();
+    let accounts = ITransfer {
+        owner: owner.info(),
+        to: to.info(),
+        authority: Signer::try_from(&authority_info).unwrap(),
+        mint: mint.info(),
+    };
+
+    let program_info = program.info();
+    assert_eq!(accounts.to_target_program(), program_key);
+    assert_eq!(
+        accounts
+            .get_target_program(std::slice::from_ref(&program_info))
+            .unwrap()
+            .key(),
+        program_key
+    );
+    assert!(accounts.get_target_program(&[]).is_none());
+    let ctx = accounts.to_target_context(vec![program_info]);
+    assert_eq!(ctx.program.key(), program_key);
+    assert_eq!(ctx.remaining_accounts.len(), 1);
+}
+
+#[test]
+fn itransfer_with_payer_targets_the_program_owning_a_mint_state() {
+    let mut owner = TestAccount::new(Pubkey::new_unique());
+    let mut to = TestAccount::new(Pubkey::new_unique());
+    let mut authority = TestAccount::new(Pubkey::new_unique()).signer();
+    let mut program = TestAccount::new(Pubkey::new_unique()).program();
+    let mut mint = TestAccount::new(Pubkey::new_unique());
+    let mut payer = TestAccount::new(Pubkey::new_unique()).signer().writable();
+    mint.owner = program.key;
+    let program_key = program.key;
+    let payer_key = payer.key;
+
+    let authority_info = authority.info();
+    let payer_info = payer.info();
+    let accounts = ITransferWithPayer {
+        owner: owner.info(),
+        to: to.info(),
+        authority: Signer::try_from(&authority_info).unwrap(),
+        mint: mint.info(),
+        payer: Signer::try_from(&payer_info).unwrap(),
+    };
+
+    let program_info = program.info();
+    assert_eq!(accounts.to_target_program(), program_key);
+    assert_eq!(
+        accounts
+            .get_target_program(std::slice::from_ref(&program_info))
+            .unwrap()
+            .key(),
+        program_key
+    );
+    assert!(accounts.get_target_program(&[]).is_none());
+    let ctx = accounts.to_target_context(vec![program_info]);
+    assert_eq!(ctx.program.key(), program_key);
+    assert_eq!(ctx.remaining_accounts.len(), 2);
+    assert_eq!(ctx.remaining_accounts[1].key(), payer_key);
+}
+
+#[test]
+fn derived_context_keeps_every_account() {
+    let mut owner = TestAccount::new(Pubkey::new_unique());
+    let mut to = TestAccount::new(Pubkey::new_unique());
+    let mut authority = TestAccount::new(Pubkey::new_unique()).signer();
+    let mut mint = TestAccount::new(Pubkey::new_unique()).program();
+    let mut escrow = TestAccount::new(Pubkey::new_unique()).writable();
+    let mut system = TestAccount::new(system_program::ID).program();
+    let mint_key = mint.key;
+    let escrow_key = escrow.key;
+
+    let authority_info = authority.info();
+    let system_info = system.info();
+    let accounts = EscrowTransfer {
+        owner: owner.info(),
+        to: to.info(),
+        authority: Signer::try_from(&authority_info).unwrap(),
+        mint: mint.info(),
+        escrow: escrow.info(),
+        system_program: Program::try_from(&system_info).unwrap(),
+    };
+
+    assert_eq!(accounts.to_target_program(), mint_key);
+    assert_eq!(accounts.get_target_program(&[]).unwrap().key(), mint_key);
+
+    let ctx = accounts.to_target_context(vec![]);
+    assert_eq!(ctx.program.key(), mint_key);
+    let metas = ctx.accounts.to_account_metas(None);
+    assert_eq!(metas.len(), 6);
+    assert_eq!(metas[4].pubkey, escrow_key);
+    assert!(metas[4].is_writable);
+    assert_eq!(metas[5].pubkey, system_program::ID);
+    assert!(ctx.remaining_accounts.is_empty());
+}
+
+#[test]
+fn payer_is_only_passed_as_remaining_account() {
+    let mut owner = TestAccount::new(Pubkey::new_unique());
+    let mut to = TestAccount::new(Pubkey::new_unique());
+    let mut authority = TestAccount::new(Pubkey::new_unique()).signer();
+    let mut mint = TestAccount::new(Pubkey::new_unique()).program();
+    let mut payer = TestAccount::new(Pubkey::new_unique()).signer().writable();
+    let authority_key = authority.key;
+    let payer_key = payer.key;
+
+    let authority_info = authority.info();
+    let payer_info = payer.info();
+    let accounts = ITransferWithPayer {
+        owner: owner.info(),
+        to: to.info(),
+        authority: Signer::try_from(&authority_info).unwrap(),
+        mint: mint.info(),
+        payer: Signer::try_from(&payer_info).unwrap(),
+    };
+
+    // preflight sees the payer after `mint`, as the only writable account
+    let metas = accounts.to_account_metas(Some(false));
+    assert_eq!(metas.len(), 5);
+    assert_eq!(metas[4].pubkey, payer_key);
+    assert_eq!(metas.iter().filter(|meta| meta.is_writable).count(), 1);
+
+    let payer = accounts.get_payer(&PayerRole::Payer).unwrap();
+    assert_eq!(payer.key(), payer_key);
+    let authority = accounts.get_payer(&PayerRole::Authority).unwrap();
+    assert_eq!(authority.key(), authority_key);
+
+    let ctx = accounts.to_target_context(vec![]);
+    assert_eq!(ctx.accounts.to_account_metas(None).len(), 4);
+    assert_eq!(ctx.remaining_accounts.len(), 1);
+    assert_eq!(ctx.remaining_accounts[0].key(), payer_key);
+}